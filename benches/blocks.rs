@@ -0,0 +1,46 @@
+// one instruction per cycle call against whole compiled blocks through run_steps,
+// `cargo bench --bench blocks` to compare
+use chip_8::Chip8;
+use criterion::{criterion_group, criterion_main, BatchSize, BenchmarkId, Criterion};
+
+const ROMS: [&str; 4] = ["1-chip8-logo", "2-ibm-logo", "3-corax+", "4-flags"];
+const STEPS: usize = 10_000;
+
+fn boot(rom: &str) -> Chip8 {
+    let mut chip8 = Chip8::new();
+    chip8.load_program(&format!("{}.ch8", rom)).unwrap();
+    chip8.pc = 0x200;
+    chip8
+}
+
+fn blocks(c: &mut Criterion) {
+    let mut group = c.benchmark_group("blocks");
+    for rom in ROMS {
+        group.bench_with_input(BenchmarkId::new("cycle", rom), rom, |b, rom| {
+            b.iter_batched(
+                || boot(rom),
+                |mut chip8| {
+                    for _ in 0..STEPS {
+                        chip8.cycle();
+                    }
+                    chip8
+                },
+                BatchSize::SmallInput,
+            )
+        });
+        group.bench_with_input(BenchmarkId::new("run_steps", rom), rom, |b, rom| {
+            b.iter_batched(
+                || boot(rom),
+                |mut chip8| {
+                    chip8.run_steps(STEPS);
+                    chip8
+                },
+                BatchSize::SmallInput,
+            )
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(benches, blocks);
+criterion_main!(benches);
@@ -0,0 +1,32 @@
+// each crt pass and the full chain on a frame at the default 20x scale. the window
+// presents crt frames at 60Hz, so the whole chain has to stay well under 16ms
+// alongside emulation, `cargo bench --bench crt` to check
+use chip_8::crt::{parse_passes, Crt};
+use chip_8::display::DisplayConfig;
+use chip_8::Chip8;
+use criterion::{criterion_group, criterion_main, Criterion};
+
+fn frame(display: &DisplayConfig) -> Vec<u32> {
+    let mut chip8 = Chip8::new();
+    chip8.load_program("2-ibm-logo.ch8").unwrap();
+    chip8.pc = 0x200;
+    chip8.run_steps(100);
+    let mut buffer = vec![0; display.width() * display.height()];
+    display.draw(&mut buffer, &chip8);
+    buffer
+}
+
+fn crt(c: &mut Criterion) {
+    let display = DisplayConfig::default();
+    let (width, height) = (display.width(), display.height());
+    let buffer = frame(&display);
+    let mut group = c.benchmark_group("crt");
+    for passes in ["scanlines", "bloom", "curvature", "vignette", "scanlines,bloom,curvature,vignette"] {
+        let mut crt = Crt::new(parse_passes(passes).unwrap());
+        group.bench_function(passes, |b| b.iter(|| crt.apply(&buffer, width, height)[0]));
+    }
+    group.finish();
+}
+
+criterion_group!(benches, crt);
+criterion_main!(benches);
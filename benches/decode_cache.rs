@@ -0,0 +1,37 @@
+// runs the bundled roms with and without the decode cache,
+// `cargo bench --bench decode_cache` to compare
+use chip_8::Chip8;
+use criterion::{criterion_group, criterion_main, BatchSize, BenchmarkId, Criterion};
+
+const ROMS: [&str; 4] = ["1-chip8-logo", "2-ibm-logo", "3-corax+", "4-flags"];
+const STEPS: usize = 10_000;
+
+fn boot(rom: &str, cached: bool) -> Chip8 {
+    let mut chip8 = Chip8::new();
+    chip8.load_program(&format!("{}.ch8", rom)).unwrap();
+    chip8.pc = 0x200;
+    chip8.set_decode_cache(cached);
+    chip8
+}
+
+fn run(mut chip8: Chip8) -> Chip8 {
+    for _ in 0..STEPS {
+        chip8.cycle();
+    }
+    chip8
+}
+
+fn decode_cache(c: &mut Criterion) {
+    let mut group = c.benchmark_group("decode_cache");
+    for rom in ROMS {
+        for (name, cached) in [("uncached", false), ("cached", true)] {
+            group.bench_with_input(BenchmarkId::new(name, rom), rom, |b, rom| {
+                b.iter_batched(|| boot(rom, cached), run, BatchSize::SmallInput)
+            });
+        }
+    }
+    group.finish();
+}
+
+criterion_group!(benches, decode_cache);
+criterion_main!(benches);
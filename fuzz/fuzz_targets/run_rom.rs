@@ -0,0 +1,32 @@
+#![no_main]
+// feeds arbitrary rom bytes and keypad input through the interpreter, any panic is a bug
+use chip_8::Chip8;
+use libfuzzer_sys::fuzz_target;
+
+const STEPS: usize = 2000;
+
+fuzz_target!(|data: &[u8]| {
+    // the first 32 bytes are 16 keypad states, one bitmask per 16 bit word,
+    // replayed over the run. the rest is the rom
+    let (keys, rom) = data.split_at(data.len().min(32));
+    let keys: Vec<u16> = keys.chunks(2).map(|pair| pair.iter().fold(0, |acc, &b| acc << 8 | b as u16)).collect();
+    let mut chip8 = Chip8::new();
+    chip8.pc = 0x200;
+    for (i, byte) in rom.iter().take(0x1000 - 0x200).enumerate() {
+        chip8.ram.write_byte(0x200 + i as u16, *byte);
+    }
+    for step in 0..STEPS {
+        if let Some(held) = keys.get(step / 64 % keys.len().max(1)) {
+            for key in 0..16 {
+                chip8.set_key(key, held >> key & 1 == 1);
+            }
+        }
+        if step % 8 == 0 {
+            chip8.tick_timers();
+        }
+        // faults are reported, not panicked, step over them and keep going
+        if chip8.cycle().is_some() {
+            chip8.pc = (chip8.pc + 2) & 0xFFF;
+        }
+    }
+});
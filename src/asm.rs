@@ -0,0 +1,645 @@
+// assembler for the mnemonics the disassembler prints. supports labels,
+// `name equ value` constants, `db`/`dw` data and `include "file"`.
+// octo syntax lives in the octo submodule.
+// SUPER-CHIP instructions use CHIPPER's mnemonics: SCD n, SCR, SCL, EXIT, LOW, HIGH,
+// LD HF, Vx, LD R, Vx and LD Vx, R. XO-CHIP adds SCU n, SAVE Vx, Vy, LOAD Vx, Vy,
+// LD I, LONG nnnn, PLANE n, LD AUDIO, [I] and LD PITCH, Vx. the interpreter doesn't run
+// these and the disassembler shows them as data.
+use crate::cpu::opcodes::OpCode;
+use std::collections::HashMap;
+pub mod octo;
+use std::fmt;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+#[derive(Debug, PartialEq)]
+pub struct AsmError {
+    pub file: String,
+    pub line: usize,
+    pub column: usize,
+    pub message: String,
+}
+impl fmt::Display for AsmError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}:{}:{}: {}", self.file, self.line, self.column, self.message)
+    }
+}
+
+// a piece of a source line along with the column it started at
+#[derive(Debug, Clone)]
+struct Token {
+    text: String,
+    column: usize,
+}
+
+#[derive(Debug)]
+enum Statement {
+    Instruction { mnemonic: Token, operands: Vec<Token> },
+    Bytes(Vec<Token>),
+    Words(Vec<Token>),
+}
+
+// where a statement came from so errors can point back at it
+struct Located {
+    file: String,
+    line: usize,
+    address: u16,
+    statement: Statement,
+}
+
+#[derive(Debug, PartialEq)]
+enum Operand {
+    V(u8),
+    I,
+    IndirectI,
+    DT,
+    ST,
+    K,
+    F,
+    B,
+    HF,
+    R,
+    Audio,
+    Pitch,
+    Value(u16),
+    // the 16 bit address after `LONG`
+    Long(u16),
+}
+
+// splits a line into the label, mnemonic and comma separated operands
+fn split_line(line: &str) -> (Option<Token>, Option<Token>, Vec<Token>) {
+    let code = match line.find(';') {
+        Some(i) => &line[..i],
+        None => line,
+    };
+    let mut rest = code;
+    let mut offset = 0;
+    let skip_ws = |rest: &mut &str, offset: &mut usize| {
+        let trimmed = rest.trim_start();
+        *offset += rest.len() - trimmed.len();
+        *rest = trimmed;
+    };
+    skip_ws(&mut rest, &mut offset);
+    let mut label = None;
+    let word_end = rest.find(char::is_whitespace).unwrap_or(rest.len());
+    if let Some(colon) = rest[..word_end].find(':') {
+        label = Some(Token { text: rest[..colon].to_string(), column: offset + 1 });
+        offset += colon + 1;
+        rest = &rest[colon + 1..];
+        skip_ws(&mut rest, &mut offset);
+    }
+    if rest.is_empty() {
+        return (label, None, Vec::new());
+    }
+    let end = rest.find(char::is_whitespace).unwrap_or(rest.len());
+    let mnemonic = Token { text: rest[..end].to_string(), column: offset + 1 };
+    offset += end;
+    rest = &rest[end..];
+    let mut operands = Vec::new();
+    if !rest.trim().is_empty() {
+        let mut in_string = false;
+        let mut start = 0;
+        let mut pieces = Vec::new();
+        for (i, c) in rest.char_indices() {
+            match c {
+                '"' => in_string = !in_string,
+                ',' if !in_string => {
+                    pieces.push((start, &rest[start..i]));
+                    start = i + 1;
+                }
+                _ => {}
+            }
+        }
+        pieces.push((start, &rest[start..]));
+        for (start, piece) in pieces {
+            let leading = piece.len() - piece.trim_start().len();
+            operands.push(Token { text: piece.trim().to_string(), column: offset + start + leading + 1 });
+        }
+    }
+    (label, Some(mnemonic), operands)
+}
+
+pub fn parse_number(text: &str) -> Option<u32> {
+    let text = text.trim();
+    if let Some(hex) = text.strip_prefix("0x").or_else(|| text.strip_prefix("0X")).or_else(|| text.strip_prefix('$')) {
+        u32::from_str_radix(hex, 16).ok()
+    } else if let Some(bin) = text.strip_prefix("0b").or_else(|| text.strip_prefix("0B")) {
+        u32::from_str_radix(bin, 2).ok()
+    } else {
+        text.parse().ok()
+    }
+}
+
+fn is_identifier(text: &str) -> bool {
+    let mut chars = text.chars();
+    matches!(chars.next(), Some(c) if c.is_ascii_alphabetic() || c == '_')
+        && chars.all(|c| c.is_ascii_alphanumeric() || c == '_')
+}
+
+// F000 NNNN is the one instruction that takes 4 bytes
+fn is_long_load(mnemonic: &Token, operands: &[Token]) -> bool {
+    mnemonic.text.eq_ignore_ascii_case("LD")
+        && operands.len() == 2
+        && operands[1].text.get(..5).is_some_and(|prefix| prefix.eq_ignore_ascii_case("LONG "))
+}
+
+// operands with these names are registers, so a label or constant called one could
+// never be referred to
+fn is_register_name(text: &str) -> bool {
+    let upper = text.to_ascii_uppercase();
+    matches!(upper.as_str(), "I" | "DT" | "ST" | "K" | "F" | "B" | "HF" | "R" | "AUDIO" | "PITCH")
+        || (upper.len() == 2 && upper.starts_with('V') && upper.as_bytes()[1].is_ascii_hexdigit())
+}
+
+pub struct Assembler {
+    origin: u16,
+    statements: Vec<Located>,
+    symbols: HashMap<String, u32>,
+    address: u16,
+    // files currently being read, used to catch include cycles
+    include_stack: Vec<PathBuf>,
+}
+impl Assembler {
+    pub fn new(origin: u16) -> Self {
+        Assembler {
+            origin,
+            statements: Vec::new(),
+            symbols: HashMap::new(),
+            address: origin,
+            include_stack: Vec::new(),
+        }
+    }
+
+    fn error(file: &str, line: usize, column: usize, message: String) -> AsmError {
+        AsmError { file: file.to_string(), line, column, message }
+    }
+
+    // first pass: collect statements, labels and constants
+    pub fn add_source(&mut self, file: &str, source: &str, base_dir: &Path) -> Result<(), AsmError> {
+        for (index, line) in source.lines().enumerate() {
+            let line_no = index + 1;
+            let (label, mnemonic, operands) = split_line(line);
+            let err = |column, message| Self::error(file, line_no, column, message);
+            // `name equ value` has no colon so it shows up as the mnemonic
+            if let (Some(name), Some(op)) = (&mnemonic, operands.first()) {
+                if let Some(value) = op.text.strip_prefix("equ ").or_else(|| op.text.strip_prefix("EQU ")) {
+                    if operands.len() != 1 || !is_identifier(&name.text) {
+                        return Err(err(name.column, "malformed constant definition".to_string()));
+                    }
+                    if is_register_name(&name.text) {
+                        return Err(err(name.column, format!("`{}` is a register, it can't be a constant", name.text)));
+                    }
+                    let column = op.column + 4;
+                    let value = self.evaluate(&Token { text: value.trim().to_string(), column })
+                        .map_err(|(column, message)| err(column, message))?;
+                    if self.symbols.insert(name.text.clone(), value).is_some() {
+                        return Err(err(name.column, format!("`{}` is already defined", name.text)));
+                    }
+                    continue;
+                }
+            }
+            if let Some(label) = label {
+                if !is_identifier(&label.text) {
+                    return Err(err(label.column, format!("invalid label name `{}`", label.text)));
+                }
+                if is_register_name(&label.text) {
+                    return Err(err(label.column, format!("`{}` is a register, it can't be a label", label.text)));
+                }
+                if self.symbols.insert(label.text.clone(), self.address as u32).is_some() {
+                    return Err(err(label.column, format!("`{}` is already defined", label.text)));
+                }
+            }
+            let mnemonic = match mnemonic {
+                Some(mnemonic) => mnemonic,
+                None => continue,
+            };
+            let (statement, size) = match mnemonic.text.to_ascii_lowercase().as_str() {
+                "include" => {
+                    let path = operands
+                        .first()
+                        .and_then(|op| op.text.strip_prefix('"')?.strip_suffix('"'))
+                        .ok_or_else(|| err(mnemonic.column, "include expects a quoted path".to_string()))?;
+                    let path = base_dir.join(path);
+                    if self.include_stack.contains(&path) {
+                        return Err(err(operands[0].column, format!("{} includes itself", path.display())));
+                    }
+                    let source = fs::read_to_string(&path)
+                        .map_err(|e| err(operands[0].column, format!("can't read {}: {}", path.display(), e)))?;
+                    let dir = path.parent().map(Path::to_path_buf).unwrap_or_default();
+                    self.include_stack.push(path.clone());
+                    self.add_source(&path.display().to_string(), &source, &dir)?;
+                    self.include_stack.pop();
+                    continue;
+                }
+                "db" => (Statement::Bytes(operands.clone()), operands.len()),
+                "dw" => (Statement::Words(operands.clone()), operands.len() * 2),
+                _ => {
+                    let size = if is_long_load(&mnemonic, &operands) { 4 } else { 2 };
+                    (Statement::Instruction { mnemonic: mnemonic.clone(), operands }, size)
+                }
+            };
+            self.statements.push(Located { file: file.to_string(), line: line_no, address: self.address, statement });
+            self.address = match self.address.checked_add(size as u16) {
+                Some(address) if address <= 0x1000 => address,
+                _ => return Err(err(mnemonic.column, "program doesn't fit in memory".to_string())),
+            };
+        }
+        Ok(())
+    }
+
+    // numbers and symbols joined with + and -
+    fn evaluate(&self, token: &Token) -> Result<u32, (usize, String)> {
+        let text = &token.text;
+        if text.is_empty() {
+            return Err((token.column, "missing value".to_string()));
+        }
+        let mut total: i64 = 0;
+        let mut sign = 1;
+        let mut start = 0;
+        // byte offsets so slicing stays on char boundaries, + and - are single bytes
+        let bytes = text.as_bytes();
+        for i in 0..=bytes.len() {
+            let at_end = i == bytes.len();
+            if at_end || ((bytes[i] == b'+' || bytes[i] == b'-') && i > start) {
+                let term = text[start..i].trim();
+                let column = token.column + start;
+                let value = match parse_number(term) {
+                    Some(value) => value,
+                    None if is_identifier(term) => *self
+                        .symbols
+                        .get(term)
+                        .ok_or_else(|| (column, format!("undefined symbol `{}`", term)))?,
+                    None => return Err((column, format!("invalid value `{}`", term))),
+                };
+                total += sign * value as i64;
+                if !at_end {
+                    sign = if bytes[i] == b'+' { 1 } else { -1 };
+                    start = i + 1;
+                }
+            }
+        }
+        u32::try_from(total).map_err(|_| (token.column, format!("`{}` is negative", text)))
+    }
+
+    fn operand(&self, token: &Token) -> Result<Operand, (usize, String)> {
+        let upper = token.text.to_ascii_uppercase();
+        let operand = match upper.as_str() {
+            "I" => Operand::I,
+            "[I]" => Operand::IndirectI,
+            "DT" => Operand::DT,
+            "ST" => Operand::ST,
+            "K" => Operand::K,
+            "F" => Operand::F,
+            "B" => Operand::B,
+            "HF" => Operand::HF,
+            "R" => Operand::R,
+            "AUDIO" => Operand::Audio,
+            "PITCH" => Operand::Pitch,
+            long if long.starts_with("LONG ") => {
+                let text = &token.text[5..];
+                let value = self.evaluate(&Token { text: text.trim().to_string(), column: token.column + 5 })?;
+                if value > 0xFFFF {
+                    return Err((token.column, format!("{:#x} doesn't fit in 16 bits", value)));
+                }
+                Operand::Long(value as u16)
+            }
+            reg if reg.len() == 2 && reg.starts_with('V') && reg.as_bytes()[1].is_ascii_hexdigit() => {
+                Operand::V(u8::from_str_radix(&reg[1..], 16).unwrap())
+            }
+            _ => {
+                let value = self.evaluate(token)?;
+                if value > 0xFFFF {
+                    return Err((token.column, format!("{:#x} doesn't fit in 16 bits", value)));
+                }
+                Operand::Value(value as u16)
+            }
+        };
+        Ok(operand)
+    }
+
+    fn instruction(&self, mnemonic: &Token, tokens: &[Token]) -> Result<OpCode, (usize, String)> {
+        let operands = tokens.iter().map(|t| self.operand(t)).collect::<Result<Vec<_>, _>>()?;
+        // checks a value fits in the given number of bits
+        let fit = |index: usize, value: u16, bits: u32| {
+            if (value as u32) < (1 << bits) {
+                Ok(value)
+            } else {
+                Err((tokens[index].column, format!("{:#x} doesn't fit in {} bits", value, bits)))
+            }
+        };
+        let byte = |index, value| fit(index, value, 8).map(|v| v as u8);
+        let addr = |index, value| fit(index, value, 12);
+        use Operand::*;
+        let name = mnemonic.text.to_ascii_uppercase();
+        let op = match (name.as_str(), operands.as_slice()) {
+            ("CLS", []) => OpCode::CLR,
+            ("RET", []) => OpCode::RET,
+            ("JP", [Value(nnn)]) => OpCode::JMP(addr(0, *nnn)?),
+            ("JP", [V(0), Value(nnn)]) => OpCode::JumpPlusV0(addr(1, *nnn)?),
+            ("CALL", [Value(nnn)]) => OpCode::CALL(addr(0, *nnn)?),
+            ("SE", [V(x), Value(kk)]) => OpCode::SkipEqualNN(*x, byte(1, *kk)?),
+            ("SE", [V(x), V(y)]) => OpCode::SkipEqualXY(*x, *y),
+            ("SNE", [V(x), Value(kk)]) => OpCode::SkipNotEqualNN(*x, byte(1, *kk)?),
+            ("SNE", [V(x), V(y)]) => OpCode::SkipNotEqualXY(*x, *y),
+            ("LD", [V(x), Value(kk)]) => OpCode::SET(*x, byte(1, *kk)?),
+            ("LD", [V(x), V(y)]) => OpCode::LDXY(*x, *y),
+            ("LD", [I, Value(nnn)]) => OpCode::SetAddrReg(addr(1, *nnn)?),
+            ("LD", [V(x), DT]) => OpCode::SetVxToDelayTimer(*x),
+            ("LD", [V(x), K]) => OpCode::WaitForKeyPress(*x),
+            ("LD", [DT, V(x)]) => OpCode::SetDelayTimer(*x),
+            ("LD", [ST, V(x)]) => OpCode::SetSoundTimer(*x),
+            ("LD", [F, V(x)]) => OpCode::SetIToSprite(*x),
+            ("LD", [B, V(x)]) => OpCode::SaveBCD(*x),
+            ("LD", [IndirectI, V(x)]) => OpCode::StoreV0ToVx(*x),
+            ("LD", [V(x), IndirectI]) => OpCode::ReadV0ToVx(*x),
+            ("ADD", [V(x), Value(kk)]) => OpCode::ADD(*x, byte(1, *kk)?),
+            ("ADD", [V(x), V(y)]) => OpCode::AddXY(*x, *y),
+            ("ADD", [I, V(x)]) => OpCode::AddVxToI(*x),
+            ("OR", [V(x), V(y)]) => OpCode::BOR(*x, *y),
+            ("AND", [V(x), V(y)]) => OpCode::BAND(*x, *y),
+            ("XOR", [V(x), V(y)]) => OpCode::BXOR(*x, *y),
+            ("SUB", [V(x), V(y)]) => OpCode::SubXY(*x, *y),
+            ("SUBN", [V(x), V(y)]) => OpCode::SUBN(*x, *y),
+            ("SHR", [V(x)]) => OpCode::SHR(*x, *x),
+            ("SHR", [V(x), V(y)]) => OpCode::SHR(*x, *y),
+            ("SHL", [V(x)]) => OpCode::SHL(*x, *x),
+            ("SHL", [V(x), V(y)]) => OpCode::SHL(*x, *y),
+            ("RND", [V(x), Value(kk)]) => OpCode::RAND(*x, byte(1, *kk)?),
+            ("DRW", [V(x), V(y), Value(n)]) => OpCode::DXYN(*x, *y, fit(2, *n, 4)? as u8),
+            ("SKP", [V(x)]) => OpCode::SkipKeyPressed(*x),
+            ("SKNP", [V(x)]) => OpCode::SkipKeyNotPressed(*x),
+            ("CLS" | "RET" | "JP" | "CALL" | "SE" | "SNE" | "LD" | "ADD" | "OR" | "AND" | "XOR" | "SUB"
+            | "SUBN" | "SHR" | "SHL" | "RND" | "DRW" | "SKP" | "SKNP", _) => {
+                return Err((mnemonic.column, format!("invalid operands for {}", name)))
+            }
+            _ => return Err((mnemonic.column, format!("unknown instruction `{}`", mnemonic.text))),
+        };
+        Ok(op)
+    }
+
+    // SUPER-CHIP and XO-CHIP instructions, straight to words as OpCode has no variants
+    // for them. None when the mnemonic and operands aren't one of them
+    fn extension(&self, mnemonic: &Token, tokens: &[Token]) -> Result<Option<Vec<u16>>, (usize, String)> {
+        let operands = tokens.iter().map(|t| self.operand(t)).collect::<Result<Vec<_>, _>>()?;
+        let nibble = |index: usize, value: u16| {
+            if value < 0x10 {
+                Ok(value)
+            } else {
+                Err((tokens[index].column, format!("{:#x} doesn't fit in 4 bits", value)))
+            }
+        };
+        use Operand::*;
+        let xy = |x: u8, y: u8| (x as u16) << 8 | (y as u16) << 4;
+        let name = mnemonic.text.to_ascii_uppercase();
+        let words = match (name.as_str(), operands.as_slice()) {
+            ("SCD", [Value(n)]) => vec![0x00C0 | nibble(0, *n)?],
+            ("SCU", [Value(n)]) => vec![0x00D0 | nibble(0, *n)?],
+            ("SCR", []) => vec![0x00FB],
+            ("SCL", []) => vec![0x00FC],
+            ("EXIT", []) => vec![0x00FD],
+            ("LOW", []) => vec![0x00FE],
+            ("HIGH", []) => vec![0x00FF],
+            ("SAVE", [V(x), V(y)]) => vec![0x5002 | xy(*x, *y)],
+            ("LOAD", [V(x), V(y)]) => vec![0x5003 | xy(*x, *y)],
+            ("PLANE", [Value(n)]) => vec![0xF001 | nibble(0, *n)? << 8],
+            ("LD", [HF, V(x)]) => vec![0xF030 | xy(*x, 0)],
+            ("LD", [R, V(x)]) => vec![0xF075 | xy(*x, 0)],
+            ("LD", [V(x), R]) => vec![0xF085 | xy(*x, 0)],
+            ("LD", [I, Long(nnnn)]) => vec![0xF000, *nnnn],
+            ("LD", [Audio, IndirectI]) => vec![0xF002],
+            ("LD", [Pitch, V(x)]) => vec![0xF03A | xy(*x, 0)],
+            ("SCD" | "SCU" | "SCR" | "SCL" | "EXIT" | "LOW" | "HIGH" | "SAVE" | "LOAD" | "PLANE", _) => {
+                return Err((mnemonic.column, format!("invalid operands for {}", name)))
+            }
+            _ => return Ok(None),
+        };
+        Ok(Some(words))
+    }
+
+    // second pass: resolve symbols and emit bytes
+    pub fn finish(self) -> Result<Vec<u8>, AsmError> {
+        let mut rom = Vec::with_capacity((self.address - self.origin) as usize);
+        for located in &self.statements {
+            let err = |(column, message)| Self::error(&located.file, located.line, column, message);
+            debug_assert_eq!(located.address as usize, self.origin as usize + rom.len());
+            match &located.statement {
+                Statement::Instruction { mnemonic, operands } => {
+                    let words = match self.extension(mnemonic, operands).map_err(err)? {
+                        Some(words) => words,
+                        None => vec![self.instruction(mnemonic, operands).map_err(err)?.encode()],
+                    };
+                    for word in words {
+                        rom.extend_from_slice(&word.to_be_bytes());
+                    }
+                }
+                Statement::Bytes(values) => {
+                    for token in values {
+                        let value = self.evaluate(token).map_err(err)?;
+                        if value > 0xFF {
+                            return Err(err((token.column, format!("{:#x} doesn't fit in a byte", value))));
+                        }
+                        rom.push(value as u8);
+                    }
+                }
+                Statement::Words(values) => {
+                    for token in values {
+                        let value = self.evaluate(token).map_err(err)?;
+                        if value > 0xFFFF {
+                            return Err(err((token.column, format!("{:#x} doesn't fit in a word", value))));
+                        }
+                        rom.extend_from_slice(&(value as u16).to_be_bytes());
+                    }
+                }
+            }
+        }
+        Ok(rom)
+    }
+}
+
+// a single instruction in either syntax OpCode::display prints, numbers only since
+// there are no labels to resolve
+impl FromStr for OpCode {
+    type Err = String;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (label, mnemonic, operands) = split_line(s);
+        let cowgod = match (label, mnemonic) {
+            (None, Some(mnemonic)) => Assembler::new(0)
+                .instruction(&mnemonic, &operands)
+                .map_err(|(column, message)| format!("column {}: {}", column, message)),
+            _ => Err(format!("expected a single instruction, found `{}`", s.trim())),
+        };
+        // octo statements never parse as cowgod, so only try them when that fails
+        cowgod.or_else(|e| match octo::assemble_statement(s) {
+            Ok(rom) if rom.len() == 2 => Ok(OpCode::decode(u16::from_be_bytes([rom[0], rom[1]]))),
+            _ => Err(e),
+        })
+    }
+}
+
+// assembles source that isn't backed by a file, includes are relative to the working directory
+pub fn assemble(source: &str, origin: u16) -> Result<Vec<u8>, AsmError> {
+    let mut assembler = Assembler::new(origin);
+    assembler.add_source("<input>", source, Path::new("."))?;
+    assembler.finish()
+}
+
+pub fn assemble_file(path: &Path, origin: u16) -> Result<Vec<u8>, AsmError> {
+    let source = fs::read_to_string(path).map_err(|e| AsmError {
+        file: path.display().to_string(),
+        line: 0,
+        column: 0,
+        message: e.to_string(),
+    })?;
+    let mut assembler = Assembler::new(origin);
+    assembler.include_stack.push(path.to_path_buf());
+    let dir = path.parent().map(Path::to_path_buf).unwrap_or_default();
+    assembler.add_source(&path.display().to_string(), &source, &dir)?;
+    assembler.finish()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cpu::opcodes::Syntax;
+    use crate::disasm::disassemble;
+
+    #[test]
+    fn parse_single_instruction() {
+        assert_eq!("SE V3, 0x11".parse::<OpCode>(), Ok(OpCode::SkipEqualNN(3, 0x11)));
+        assert_eq!("drw v1, v2, 5".parse::<OpCode>(), Ok(OpCode::DXYN(1, 2, 5)));
+        assert_eq!("if v3 != 0x11 then".parse::<OpCode>(), Ok(OpCode::SkipEqualNN(3, 0x11)));
+        assert_eq!("i := hex v4".parse::<OpCode>(), Ok(OpCode::SetIToSprite(4)));
+        assert_eq!("LD V0, 0x100".parse::<OpCode>(), Err("column 8: 0x100 doesn't fit in 8 bits".to_string()));
+        assert!("loop: JP loop".parse::<OpCode>().is_err());
+        // octo statements can't define or refer to labels, there's nothing to resolve them
+        assert!("jump main".parse::<OpCode>().is_err());
+        assert!("main".parse::<OpCode>().is_err());
+        assert!(": main clear".parse::<OpCode>().is_err());
+        assert!("i := sprite".parse::<OpCode>().is_err());
+        assert!("clear clear".parse::<OpCode>().is_err());
+        assert_eq!("jump 0x234".parse::<OpCode>(), Ok(OpCode::JMP(0x234)));
+        assert!("".parse::<OpCode>().is_err());
+    }
+    #[test]
+    fn every_instruction_parses_back_in_both_syntaxes() {
+        for word in 0..=u16::MAX {
+            let op = OpCode::decode(word);
+            if op == OpCode::UNFINISHED {
+                continue;
+            }
+            for syntax in [Syntax::Cowgod, Syntax::Octo] {
+                let text = op.display(syntax).to_string();
+                assert_eq!(text.parse::<OpCode>(), Ok(op), "{:04X} printed as `{}`", word, text);
+            }
+        }
+    }
+    #[test]
+    fn assemble_instructions() {
+        let source = "
+            start:  CLS             ; clear
+                    LD V0, 0x0C
+                    LD I, sprite
+                    DRW V0, V1, 5
+                    SHR V3
+                    LD [I], VA
+                    JP start
+            sprite: db 0xF0, 0x90, $F0
+                    dw 0x1234
+        ";
+        let rom = assemble(source, 0x200).unwrap();
+        assert_eq!(
+            rom,
+            [0x00, 0xE0, 0x60, 0x0C, 0xA2, 0x0E, 0xD0, 0x15, 0x83, 0x36, 0xFA, 0x55, 0x12, 0x00, 0xF0, 0x90, 0xF0, 0x12, 0x34]
+        );
+    }
+    #[test]
+    fn assemble_extensions() {
+        let source = "
+                    HIGH
+                    SCD 4
+                    SCR
+                    SCL
+                    LD HF, V2
+                    LD R, V7
+                    LD V7, R
+                    LOW
+                    EXIT
+                    SCU 0xF
+                    SAVE V1, V3
+                    LOAD V1, V3
+                    LD I, long data
+                    PLANE 3
+                    LD AUDIO, [I]
+                    LD PITCH, VA
+            data:   db 1
+        ";
+        let rom = assemble(source, 0x200).unwrap();
+        let words: Vec<u16> = rom.chunks(2).map(|pair| u16::from_be_bytes([pair[0], pair.get(1).copied().unwrap_or(0)])).collect();
+        assert_eq!(
+            words,
+            [
+                0x00FF, 0x00C4, 0x00FB, 0x00FC, 0xF230, 0xF775, 0xF785, 0x00FE, 0x00FD, 0x00DF, 0x5132, 0x5133, 0xF000,
+                0x0222, 0xF301, 0xF002, 0xFA3A, 0x0100
+            ]
+        );
+        let err = assemble("SCD 16", 0x200).unwrap_err();
+        assert_eq!((err.column, err.message.as_str()), (5, "0x10 doesn't fit in 4 bits"));
+        assert_eq!(assemble("HIGH V0", 0x200).unwrap_err().message, "invalid operands for HIGH");
+        assert!(assemble("LD I, long 0x10000", 0x200).is_err());
+    }
+    #[test]
+    fn constants_and_expressions() {
+        let source = "
+            SPEED equ 3
+            TOP equ SPEED + 0x10
+                LD V1, TOP - 1
+                JP end + 2
+            end: RET
+        ";
+        let rom = assemble(source, 0x200).unwrap();
+        assert_eq!(rom, [0x61, 0x12, 0x12, 0x06, 0x00, 0xEE]);
+    }
+    #[test]
+    fn errors_have_positions() {
+        let err = assemble("CLS\n  LD V0, 0x100", 0x200).unwrap_err();
+        assert_eq!((err.line, err.column), (2, 10));
+        let err = assemble("  JP nowhere", 0x200).unwrap_err();
+        assert_eq!((err.line, err.column), (1, 6));
+        assert_eq!(err.message, "undefined symbol `nowhere`");
+        let err = assemble("FOO V1", 0x200).unwrap_err();
+        assert_eq!((err.line, err.column), (1, 1));
+        // non-ascii operands are errors, not panics
+        let err = assemble("CLS\nLD V0, é+1", 0x200).unwrap_err();
+        assert_eq!((err.line, err.column), (2, 8));
+        assert_eq!(err.message, "invalid value `é`");
+        assert!("LD V0, 1+é".parse::<OpCode>().is_err());
+        let err = assemble("x: CLS\nx: RET", 0x200).unwrap_err();
+        assert_eq!(err.to_string(), "<input>:2:1: `x` is already defined");
+        // names operands read as registers
+        for name in ["B", "f", "K", "i", "DT", "st", "V0", "va", "VF"] {
+            let err = assemble(&format!("CLS\n{}: JP {}", name, name), 0x200).unwrap_err();
+            assert_eq!(err.message, format!("`{}` is a register, it can't be a label", name));
+            assert!(assemble(&format!("{} equ 5", name), 0x200).is_err());
+        }
+        assert!(assemble("vg: JP vg\nbx: JP bx\ndt2 equ 3", 0x200).is_ok());
+    }
+    #[test]
+    fn includes() {
+        let dir = std::env::temp_dir().join(format!("chip8-asm-test-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(dir.join("main.asm"), "include \"lib.asm\"\nCALL sub\n").unwrap();
+        fs::write(dir.join("lib.asm"), "sub: RET\n").unwrap();
+        let rom = assemble_file(&dir.join("main.asm"), 0x200).unwrap();
+        assert_eq!(rom, [0x00, 0xEE, 0x22, 0x00]);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+    #[test]
+    fn round_trips_with_disassembler() {
+        for path in ["1-chip8-logo.ch8", "2-ibm-logo.ch8", "3-corax+.ch8", "4-flags.ch8"] {
+            let rom = fs::read(path).unwrap();
+            let source = disassemble(&rom, 0x200).to_source();
+            assert_eq!(assemble(&source, 0x200).unwrap(), rom, "{}", path);
+        }
+    }
+}
@@ -0,0 +1,792 @@
+// front end for Octo assembly. tokens are whitespace separated, `#` starts a
+// comment and structured control flow is lowered to skips and jumps following
+// the Octo manual's description of how its compiler does it.
+use super::AsmError;
+use crate::cpu::opcodes::OpCode;
+use std::collections::HashMap;
+
+#[derive(Debug, Clone)]
+struct Tok {
+    text: String,
+    line: usize,
+    column: usize,
+}
+
+fn tokenize(source: &str) -> Vec<Tok> {
+    let mut tokens = Vec::new();
+    for (index, line) in source.lines().enumerate() {
+        let mut chars = line.char_indices().peekable();
+        while let Some(&(start, c)) = chars.peek() {
+            if c == '#' {
+                break;
+            }
+            if c.is_whitespace() {
+                chars.next();
+                continue;
+            }
+            let mut end = line.len();
+            while let Some(&(i, c)) = chars.peek() {
+                if c.is_whitespace() {
+                    end = i;
+                    break;
+                }
+                chars.next();
+            }
+            tokens.push(Tok { text: line[start..end].to_string(), line: index + 1, column: start + 1 });
+        }
+    }
+    tokens
+}
+
+fn parse_literal(text: &str) -> Option<i64> {
+    let (negative, digits) = match text.strip_prefix('-') {
+        Some(rest) => (true, rest),
+        None => (false, text),
+    };
+    let value = if let Some(hex) = digits.strip_prefix("0x").or_else(|| digits.strip_prefix("0X")) {
+        i64::from_str_radix(hex, 16).ok()?
+    } else if let Some(bin) = digits.strip_prefix("0b").or_else(|| digits.strip_prefix("0B")) {
+        i64::from_str_radix(bin, 2).ok()?
+    } else {
+        digits.parse().ok()?
+    };
+    Some(if negative { -value } else { value })
+}
+
+fn register_name(text: &str) -> Option<u8> {
+    let mut chars = text.chars();
+    match (chars.next(), chars.next(), chars.next()) {
+        (Some('v') | Some('V'), Some(d), None) => d.to_digit(16).map(|d| d as u8),
+        _ => None,
+    }
+}
+
+struct Macro {
+    args: Vec<String>,
+    body: Vec<Tok>,
+}
+
+// a 12 bit address that gets patched once the label is defined
+struct Fixup {
+    address: u16,
+    label: Tok,
+}
+
+// what a comparison in `if`/`while` turns into
+enum Compare {
+    Equal(u8, Rhs),
+    NotEqual(u8, Rhs),
+    Key(u8),
+    NotKey(u8),
+    // relational tests are computed into vf first, `then` checks vf == 1 when the test holds
+    Flag(Vec<u16>, bool),
+}
+enum Rhs {
+    Register(u8),
+    Byte(u8),
+}
+
+pub struct Octo {
+    tokens: Vec<Tok>,
+    pos: usize,
+    rom: Vec<u8>,
+    here: u16,
+    labels: HashMap<String, u16>,
+    constants: HashMap<String, f64>,
+    aliases: HashMap<String, u8>,
+    macros: HashMap<String, Macro>,
+    fixups: Vec<Fixup>,
+    // open `begin`/`else` blocks, holding the jump to patch
+    branches: Vec<(u16, Tok)>,
+    // open loops with their start address and pending `while` exits
+    loops: Vec<(u16, Vec<u16>, Tok)>,
+    // cleared when `: main` is the first thing so no initial jump is needed
+    jump_to_main: bool,
+    file: String,
+}
+impl Octo {
+    pub fn new(file: &str, source: &str) -> Self {
+        Octo {
+            tokens: tokenize(source),
+            pos: 0,
+            rom: Vec::new(),
+            // 0x200 is reserved for the jump to main
+            here: 0x202,
+            labels: HashMap::new(),
+            constants: HashMap::new(),
+            aliases: HashMap::new(),
+            macros: HashMap::new(),
+            fixups: Vec::new(),
+            branches: Vec::new(),
+            loops: Vec::new(),
+            jump_to_main: true,
+            file: file.to_string(),
+        }
+    }
+
+    fn error_at(&self, tok: &Tok, message: String) -> AsmError {
+        AsmError { file: self.file.clone(), line: tok.line, column: tok.column, message }
+    }
+    fn error_at_end(&self, message: String) -> AsmError {
+        let (line, column) = match self.tokens.last() {
+            Some(tok) => (tok.line, tok.column + tok.text.len()),
+            None => (1, 1),
+        };
+        AsmError { file: self.file.clone(), line, column, message }
+    }
+
+    fn next(&mut self) -> Result<Tok, AsmError> {
+        match self.tokens.get(self.pos) {
+            Some(tok) => {
+                self.pos += 1;
+                Ok(tok.clone())
+            }
+            None => Err(self.error_at_end("unexpected end of file".to_string())),
+        }
+    }
+    fn peek_is(&self, text: &str) -> bool {
+        self.tokens.get(self.pos).is_some_and(|tok| tok.text == text)
+    }
+    fn expect(&mut self, text: &str) -> Result<Tok, AsmError> {
+        let tok = self.next()?;
+        if tok.text != text {
+            return Err(self.error_at(&tok, format!("expected `{}`, got `{}`", text, tok.text)));
+        }
+        Ok(tok)
+    }
+
+    fn emit_byte(&mut self, byte: u8, tok: &Tok) -> Result<(), AsmError> {
+        if self.here >= 0x1000 {
+            return Err(self.error_at(tok, "program doesn't fit in memory".to_string()));
+        }
+        let index = (self.here - 0x200) as usize;
+        if self.rom.len() <= index {
+            self.rom.resize(index + 1, 0);
+        }
+        self.rom[index] = byte;
+        self.here += 1;
+        Ok(())
+    }
+    fn emit_word(&mut self, word: u16, tok: &Tok) -> Result<(), AsmError> {
+        self.emit_byte((word >> 8) as u8, tok)?;
+        self.emit_byte(word as u8, tok)
+    }
+    fn emit(&mut self, op: OpCode, tok: &Tok) -> Result<(), AsmError> {
+        self.emit_word(op.encode(), tok)
+    }
+    fn patch_address(&mut self, address: u16, target: u16) {
+        let index = (address - 0x200) as usize;
+        self.rom[index] = (self.rom[index] & 0xF0) | (target >> 8) as u8;
+        self.rom[index + 1] = target as u8;
+    }
+
+    fn register(&mut self) -> Result<u8, AsmError> {
+        let tok = self.next()?;
+        self.register_of(&tok).ok_or_else(|| self.error_at(&tok, format!("expected a register, got `{}`", tok.text)))
+    }
+    fn register_of(&self, tok: &Tok) -> Option<u8> {
+        register_name(&tok.text).or_else(|| self.aliases.get(&tok.text).copied())
+    }
+    fn is_register(&self) -> bool {
+        self.tokens.get(self.pos).is_some_and(|tok| self.register_of(tok).is_some())
+    }
+
+    // numbers and constants, used where forward references aren't allowed
+    fn number(&mut self) -> Result<(i64, Tok), AsmError> {
+        let tok = self.next()?;
+        if tok.text == "{" {
+            let value = self.calc()?;
+            return Ok((value.floor() as i64, tok));
+        }
+        if let Some(value) = parse_literal(&tok.text) {
+            return Ok((value, tok));
+        }
+        if let Some(value) = self.constants.get(&tok.text) {
+            return Ok((value.floor() as i64, tok));
+        }
+        if let Some(&value) = self.labels.get(&tok.text) {
+            return Ok((value as i64, tok));
+        }
+        Err(self.error_at(&tok, format!("undefined name `{}`", tok.text)))
+    }
+    fn value_8bit(&mut self) -> Result<u8, AsmError> {
+        let (value, tok) = self.number()?;
+        if !(-128..=255).contains(&value) {
+            return Err(self.error_at(&tok, format!("value `{}` doesn't fit in 8 bits", tok.text)));
+        }
+        Ok(value as u8)
+    }
+    fn value_4bit(&mut self) -> Result<u8, AsmError> {
+        let (value, tok) = self.number()?;
+        if !(0..=15).contains(&value) {
+            return Err(self.error_at(&tok, format!("value `{}` doesn't fit in 4 bits", tok.text)));
+        }
+        Ok(value as u8)
+    }
+    // addresses may name labels defined later in the file
+    fn value_12bit(&mut self, instruction_address: u16) -> Result<u16, AsmError> {
+        let tok = self.tokens.get(self.pos).cloned();
+        match tok {
+            Some(tok)
+                if parse_literal(&tok.text).is_none()
+                    && tok.text != "{"
+                    && !self.constants.contains_key(&tok.text)
+                    && !self.labels.contains_key(&tok.text) =>
+            {
+                self.pos += 1;
+                self.fixups.push(Fixup { address: instruction_address, label: tok });
+                Ok(0)
+            }
+            _ => {
+                let (value, tok) = self.number()?;
+                if !(0..=0xFFF).contains(&value) {
+                    return Err(self.error_at(&tok, format!("value `{}` doesn't fit in 12 bits", tok.text)));
+                }
+                Ok(value as u16)
+            }
+        }
+    }
+
+    // `{ ... }` expressions: no precedence, evaluated right to left like Octo
+    fn calc(&mut self) -> Result<f64, AsmError> {
+        let value = self.calc_expr()?;
+        self.expect("}")?;
+        Ok(value)
+    }
+    fn calc_expr(&mut self) -> Result<f64, AsmError> {
+        let lhs = self.calc_term()?;
+        let op = match self.tokens.get(self.pos) {
+            Some(tok) if tok.text != "}" && tok.text != ")" => self.next()?,
+            _ => return Ok(lhs),
+        };
+        let rhs = self.calc_expr()?;
+        let (a, b) = (lhs as i64, rhs as i64);
+        let bool_value = |b: bool| if b { 1.0 } else { 0.0 };
+        let value = match op.text.as_str() {
+            "+" => lhs + rhs,
+            "-" => lhs - rhs,
+            "*" => lhs * rhs,
+            "/" => lhs / rhs,
+            "%" => lhs % rhs,
+            "&" => (a & b) as f64,
+            "|" => (a | b) as f64,
+            "^" => (a ^ b) as f64,
+            "<<" => (a << b) as f64,
+            ">>" => (a >> b) as f64,
+            "pow" => lhs.powf(rhs),
+            "min" => lhs.min(rhs),
+            "max" => lhs.max(rhs),
+            "<" => bool_value(lhs < rhs),
+            "<=" => bool_value(lhs <= rhs),
+            ">" => bool_value(lhs > rhs),
+            ">=" => bool_value(lhs >= rhs),
+            "==" => bool_value(lhs == rhs),
+            "!=" => bool_value(lhs != rhs),
+            _ => return Err(self.error_at(&op, format!("unknown operator `{}`", op.text))),
+        };
+        Ok(value)
+    }
+    fn calc_term(&mut self) -> Result<f64, AsmError> {
+        let tok = self.next()?;
+        let value = match tok.text.as_str() {
+            "(" => {
+                let value = self.calc_expr()?;
+                self.expect(")")?;
+                value
+            }
+            "-" => -self.calc_term()?,
+            "~" => !(self.calc_term()? as i64) as f64,
+            "!" => if self.calc_term()? == 0.0 { 1.0 } else { 0.0 },
+            "floor" => self.calc_term()?.floor(),
+            "ceil" => self.calc_term()?.ceil(),
+            "abs" => self.calc_term()?.abs(),
+            "sqrt" => self.calc_term()?.sqrt(),
+            "sin" => self.calc_term()?.sin(),
+            "cos" => self.calc_term()?.cos(),
+            "HERE" => self.here as f64,
+            "PI" => std::f64::consts::PI,
+            "E" => std::f64::consts::E,
+            name => {
+                if let Some(value) = parse_literal(name) {
+                    value as f64
+                } else if let Some(&value) = self.constants.get(name) {
+                    value
+                } else if let Some(&value) = self.labels.get(name) {
+                    value as f64
+                } else {
+                    return Err(self.error_at(&tok, format!("undefined name `{}`", name)));
+                }
+            }
+        };
+        Ok(value)
+    }
+
+    fn identifier(&mut self, what: &str) -> Result<Tok, AsmError> {
+        let tok = self.next()?;
+        let valid = tok.text.chars().next().is_some_and(|c| c.is_ascii_alphabetic() || c == '_')
+            && tok.text.chars().all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-');
+        if !valid || register_name(&tok.text).is_some() {
+            return Err(self.error_at(&tok, format!("invalid {} name `{}`", what, tok.text)));
+        }
+        Ok(tok)
+    }
+    fn check_unused(&self, tok: &Tok) -> Result<(), AsmError> {
+        if self.labels.contains_key(&tok.text)
+            || self.constants.contains_key(&tok.text)
+            || self.aliases.contains_key(&tok.text)
+        {
+            return Err(self.error_at(tok, format!("`{}` is already defined", tok.text)));
+        }
+        Ok(())
+    }
+
+    // parses the test after `if` or `while` without emitting anything
+    fn comparison(&mut self) -> Result<Compare, AsmError> {
+        let x = self.register()?;
+        let op = self.next()?;
+        let rhs = |octo: &mut Octo| -> Result<Rhs, AsmError> {
+            if octo.is_register() {
+                Ok(Rhs::Register(octo.register()?))
+            } else {
+                Ok(Rhs::Byte(octo.value_8bit()?))
+            }
+        };
+        let load_vf = |rhs: Rhs| match rhs {
+            Rhs::Register(y) => OpCode::LDXY(0xF, y).encode(),
+            Rhs::Byte(kk) => OpCode::SET(0xF, kk).encode(),
+        };
+        let compare = match op.text.as_str() {
+            "==" => Compare::Equal(x, rhs(self)?),
+            "!=" => Compare::NotEqual(x, rhs(self)?),
+            "key" => Compare::Key(x),
+            "-key" => Compare::NotKey(x),
+            // vf -= vx leaves vf = 1 when rhs >= vx, vf =- vx leaves vf = 1 when vx >= rhs
+            ">" => Compare::Flag(vec![load_vf(rhs(self)?), OpCode::SubXY(0xF, x).encode()], false),
+            "<=" => Compare::Flag(vec![load_vf(rhs(self)?), OpCode::SubXY(0xF, x).encode()], true),
+            "<" => Compare::Flag(vec![load_vf(rhs(self)?), OpCode::SUBN(0xF, x).encode()], false),
+            ">=" => Compare::Flag(vec![load_vf(rhs(self)?), OpCode::SUBN(0xF, x).encode()], true),
+            _ => return Err(self.error_at(&op, format!("unknown comparison `{}`", op.text))),
+        };
+        Ok(compare)
+    }
+    // emits a skip that jumps over the next instruction when the test is `holds`
+    fn emit_skip(&mut self, compare: Compare, holds: bool, tok: &Tok) -> Result<(), AsmError> {
+        let op = match (compare, holds) {
+            (Compare::Equal(x, Rhs::Byte(kk)), true) | (Compare::NotEqual(x, Rhs::Byte(kk)), false) => {
+                OpCode::SkipEqualNN(x, kk)
+            }
+            (Compare::Equal(x, Rhs::Byte(kk)), false) | (Compare::NotEqual(x, Rhs::Byte(kk)), true) => {
+                OpCode::SkipNotEqualNN(x, kk)
+            }
+            (Compare::Equal(x, Rhs::Register(y)), true) | (Compare::NotEqual(x, Rhs::Register(y)), false) => {
+                OpCode::SkipEqualXY(x, y)
+            }
+            (Compare::Equal(x, Rhs::Register(y)), false) | (Compare::NotEqual(x, Rhs::Register(y)), true) => {
+                OpCode::SkipNotEqualXY(x, y)
+            }
+            (Compare::Key(x), true) | (Compare::NotKey(x), false) => OpCode::SkipKeyPressed(x),
+            (Compare::Key(x), false) | (Compare::NotKey(x), true) => OpCode::SkipKeyNotPressed(x),
+            (Compare::Flag(setup, flag_when_true), holds) => {
+                for word in setup {
+                    self.emit_word(word, tok)?;
+                }
+                // vf is 1 exactly when the test matches flag_when_true
+                if holds == flag_when_true {
+                    OpCode::SkipEqualNN(0xF, 1)
+                } else {
+                    OpCode::SkipNotEqualNN(0xF, 1)
+                }
+            }
+        };
+        self.emit(op, tok)
+    }
+
+    fn statement(&mut self) -> Result<(), AsmError> {
+        let tok = self.next()?;
+        if let Some(x) = self.register_of(&tok) {
+            return self.register_statement(x, &tok);
+        }
+        if let Some(value) = parse_literal(&tok.text) {
+            if !(-128..=255).contains(&value) {
+                return Err(self.error_at(&tok, format!("value `{}` doesn't fit in 8 bits", tok.text)));
+            }
+            return self.emit_byte(value as u8, &tok);
+        }
+        match tok.text.as_str() {
+            ":" => {
+                let name = self.identifier("label")?;
+                self.check_unused(&name)?;
+                if name.text == "main" && self.here == 0x202 && self.rom.is_empty() {
+                    self.here = 0x200;
+                    self.jump_to_main = false;
+                }
+                self.labels.insert(name.text, self.here);
+            }
+            ":alias" => {
+                let name = self.identifier("alias")?;
+                self.check_unused(&name)?;
+                let x = self.register()?;
+                self.aliases.insert(name.text, x);
+            }
+            ":const" => {
+                let name = self.identifier("constant")?;
+                self.check_unused(&name)?;
+                let (value, _) = self.number()?;
+                self.constants.insert(name.text, value as f64);
+            }
+            ":calc" => {
+                let name = self.identifier("constant")?;
+                if self.labels.contains_key(&name.text) || self.aliases.contains_key(&name.text) {
+                    return Err(self.error_at(&name, format!("`{}` is already defined", name.text)));
+                }
+                self.expect("{")?;
+                let value = self.calc()?;
+                self.constants.insert(name.text, value);
+            }
+            ":macro" => {
+                let name = self.identifier("macro")?;
+                let mut args = Vec::new();
+                while !self.peek_is("{") {
+                    args.push(self.next()?.text);
+                }
+                self.expect("{")?;
+                let mut depth = 1;
+                let mut body = Vec::new();
+                loop {
+                    let tok = self.next()?;
+                    match tok.text.as_str() {
+                        "{" => depth += 1,
+                        "}" => depth -= 1,
+                        _ => {}
+                    }
+                    if depth == 0 {
+                        break;
+                    }
+                    body.push(tok);
+                }
+                self.macros.insert(name.text, Macro { args, body });
+            }
+            ":org" => {
+                let (value, org) = self.number()?;
+                if !(0x200..=0xFFF).contains(&value) {
+                    return Err(self.error_at(&org, format!("`:org` address {:#x} is out of range", value)));
+                }
+                self.here = value as u16;
+            }
+            ":byte" => {
+                let (value, _) = self.number()?;
+                self.emit_byte(value as u8, &tok)?;
+            }
+            ":unpack" => {
+                // v0 := high nibble | address >> 8, v1 := address low byte
+                let nibble = self.value_4bit()?;
+                let (address, _) = self.number()?;
+                let address = address as u16;
+                self.emit(OpCode::SET(0, nibble << 4 | (address >> 8) as u8 & 0xF), &tok)?;
+                self.emit(OpCode::SET(1, address as u8), &tok)?;
+            }
+            ":call" => {
+                let address = self.here;
+                let nnn = self.value_12bit(address)?;
+                self.emit(OpCode::CALL(nnn), &tok)?;
+            }
+            // debugger hints that don't emit anything
+            ":breakpoint" => {
+                self.next()?;
+            }
+            ":monitor" => {
+                self.next()?;
+                self.next()?;
+            }
+            ";" | "return" => self.emit(OpCode::RET, &tok)?,
+            "clear" => self.emit(OpCode::CLR, &tok)?,
+            "bcd" => {
+                let x = self.register()?;
+                self.emit(OpCode::SaveBCD(x), &tok)?;
+            }
+            "save" => {
+                let x = self.register()?;
+                self.emit(OpCode::StoreV0ToVx(x), &tok)?;
+            }
+            "load" => {
+                let x = self.register()?;
+                self.emit(OpCode::ReadV0ToVx(x), &tok)?;
+            }
+            "sprite" => {
+                let x = self.register()?;
+                let y = self.register()?;
+                let n = self.value_4bit()?;
+                self.emit(OpCode::DXYN(x, y, n), &tok)?;
+            }
+            "jump" | "jump0" | "native" => {
+                let address = self.here;
+                let nnn = self.value_12bit(address)?;
+                let word = match tok.text.as_str() {
+                    "jump" => 0x1000,
+                    "jump0" => 0xB000,
+                    _ => 0x0000,
+                };
+                self.emit_word(word | nnn, &tok)?;
+            }
+            "delay" | "buzzer" => {
+                self.expect(":=")?;
+                let x = self.register()?;
+                let op = if tok.text == "delay" { OpCode::SetDelayTimer(x) } else { OpCode::SetSoundTimer(x) };
+                self.emit(op, &tok)?;
+            }
+            "i" => {
+                let op = self.next()?;
+                match op.text.as_str() {
+                    ":=" if self.peek_is("hex") => {
+                        self.next()?;
+                        let x = self.register()?;
+                        self.emit(OpCode::SetIToSprite(x), &tok)?;
+                    }
+                    ":=" => {
+                        let address = self.here;
+                        let nnn = self.value_12bit(address)?;
+                        self.emit(OpCode::SetAddrReg(nnn), &tok)?;
+                    }
+                    "+=" => {
+                        let x = self.register()?;
+                        self.emit(OpCode::AddVxToI(x), &tok)?;
+                    }
+                    _ => return Err(self.error_at(&op, format!("unknown operator `{}` for i", op.text))),
+                }
+            }
+            "if" => {
+                let compare = self.comparison()?;
+                let kind = self.next()?;
+                match kind.text.as_str() {
+                    "then" => self.emit_skip(compare, false, &tok)?,
+                    "begin" => {
+                        self.emit_skip(compare, true, &tok)?;
+                        self.branches.push((self.here, tok.clone()));
+                        self.emit(OpCode::JMP(0), &tok)?;
+                    }
+                    _ => return Err(self.error_at(&kind, format!("expected `then` or `begin`, got `{}`", kind.text))),
+                }
+            }
+            "else" => {
+                let (jump, _) = self.branches.pop().ok_or_else(|| self.error_at(&tok, "`else` without `begin`".to_string()))?;
+                self.branches.push((self.here, tok.clone()));
+                self.emit(OpCode::JMP(0), &tok)?;
+                let here = self.here;
+                self.patch_address(jump, here);
+            }
+            "end" => {
+                let (jump, _) = self.branches.pop().ok_or_else(|| self.error_at(&tok, "`end` without `begin`".to_string()))?;
+                let here = self.here;
+                self.patch_address(jump, here);
+            }
+            "loop" => self.loops.push((self.here, Vec::new(), tok.clone())),
+            "while" => {
+                if self.loops.is_empty() {
+                    return Err(self.error_at(&tok, "`while` outside of a loop".to_string()));
+                }
+                let compare = self.comparison()?;
+                self.emit_skip(compare, true, &tok)?;
+                let exit = self.here;
+                self.loops.last_mut().unwrap().1.push(exit);
+                self.emit(OpCode::JMP(0), &tok)?;
+            }
+            "again" => {
+                let (start, exits, _) = self.loops.pop().ok_or_else(|| self.error_at(&tok, "`again` without `loop`".to_string()))?;
+                self.emit(OpCode::JMP(start), &tok)?;
+                let here = self.here;
+                for exit in exits {
+                    self.patch_address(exit, here);
+                }
+            }
+            name => {
+                if let Some(mac) = self.macros.get(name) {
+                    let count = mac.args.len();
+                    let mut values = Vec::new();
+                    for _ in 0..count {
+                        values.push(self.next()?);
+                    }
+                    let mac = &self.macros[name];
+                    let expanded: Vec<Tok> = mac
+                        .body
+                        .iter()
+                        .map(|body_tok| match mac.args.iter().position(|arg| *arg == body_tok.text) {
+                            Some(i) => values[i].clone(),
+                            None => body_tok.clone(),
+                        })
+                        .collect();
+                    self.tokens.splice(self.pos..self.pos, expanded);
+                    return Ok(());
+                }
+                if name.starts_with(':') || name == "{" || name == "}" || name == ":=" {
+                    return Err(self.error_at(&tok, format!("unexpected `{}`", name)));
+                }
+                // any other name is a subroutine call, possibly to a label defined later
+                self.pos -= 1;
+                let address = self.here;
+                let nnn = self.value_12bit(address)?;
+                self.emit(OpCode::CALL(nnn), &tok)?;
+            }
+        }
+        Ok(())
+    }
+
+    fn register_statement(&mut self, x: u8, tok: &Tok) -> Result<(), AsmError> {
+        let op = self.next()?;
+        if self.is_register() {
+            let y = self.register()?;
+            let opcode = match op.text.as_str() {
+                ":=" => OpCode::LDXY(x, y),
+                "|=" => OpCode::BOR(x, y),
+                "&=" => OpCode::BAND(x, y),
+                "^=" => OpCode::BXOR(x, y),
+                "+=" => OpCode::AddXY(x, y),
+                "-=" => OpCode::SubXY(x, y),
+                "=-" => OpCode::SUBN(x, y),
+                ">>=" => OpCode::SHR(x, y),
+                "<<=" => OpCode::SHL(x, y),
+                _ => return Err(self.error_at(&op, format!("unknown operator `{}`", op.text))),
+            };
+            return self.emit(opcode, tok);
+        }
+        let opcode = match op.text.as_str() {
+            ":=" if self.peek_is("random") => {
+                self.next()?;
+                OpCode::RAND(x, self.value_8bit()?)
+            }
+            ":=" if self.peek_is("key") => {
+                self.next()?;
+                OpCode::WaitForKeyPress(x)
+            }
+            ":=" if self.peek_is("delay") => {
+                self.next()?;
+                OpCode::SetVxToDelayTimer(x)
+            }
+            ":=" => OpCode::SET(x, self.value_8bit()?),
+            "+=" => OpCode::ADD(x, self.value_8bit()?),
+            "-=" => OpCode::ADD(x, self.value_8bit()?.wrapping_neg()),
+            _ => return Err(self.error_at(&op, format!("unknown operator `{}`", op.text))),
+        };
+        self.emit(opcode, tok)
+    }
+
+    pub fn assemble(mut self) -> Result<Vec<u8>, AsmError> {
+        while self.pos < self.tokens.len() {
+            self.statement()?;
+        }
+        if let Some((_, tok)) = self.branches.last() {
+            return Err(self.error_at(tok, "`begin` without `end`".to_string()));
+        }
+        if let Some((_, _, tok)) = self.loops.last() {
+            return Err(self.error_at(tok, "`loop` without `again`".to_string()));
+        }
+        for fixup in std::mem::take(&mut self.fixups) {
+            let target = match self.labels.get(&fixup.label.text) {
+                Some(&target) => target,
+                None => return Err(self.error_at(&fixup.label, format!("undefined name `{}`", fixup.label.text))),
+            };
+            self.patch_address(fixup.address, target);
+        }
+        if self.jump_to_main {
+            let main = match self.labels.get("main") {
+                Some(&main) => main,
+                None => return Err(self.error_at_end("this program is missing a `main` label".to_string())),
+            };
+            if self.rom.len() < 2 {
+                self.rom.resize(2, 0);
+            }
+            self.rom[0] = 0x10 | (main >> 8) as u8;
+            self.rom[1] = main as u8;
+        }
+        Ok(self.rom)
+    }
+}
+
+pub fn assemble_octo(file: &str, source: &str) -> Result<Vec<u8>, AsmError> {
+    Octo::new(file, source).assemble()
+}
+
+// one statement on its own at 0x200, for parsing a single instruction. nothing can be
+// defined, so any name it uses is undefined
+pub fn assemble_statement(source: &str) -> Result<Vec<u8>, AsmError> {
+    let mut octo = Octo::new("<input>", source);
+    octo.here = 0x200;
+    octo.jump_to_main = false;
+    let defines = [":", ":alias", ":calc", ":const", ":macro", ":org"];
+    if let Some(tok) = octo.tokens.first().filter(|tok| defines.contains(&tok.text.as_str())) {
+        return Err(octo.error_at(tok, format!("`{}` can't be used in a single statement", tok.text)));
+    }
+    octo.statement()?;
+    if let Some(tok) = octo.tokens.get(octo.pos) {
+        return Err(octo.error_at(tok, format!("expected a single statement, `{}` follows", tok.text)));
+    }
+    octo.assemble()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    fn octo(source: &str) -> Vec<u8> {
+        assemble_octo("<input>", source).unwrap()
+    }
+
+    #[test]
+    fn main_first_has_no_jump() {
+        assert_eq!(octo(": main clear loop again"), [0x00, 0xE0, 0x12, 0x02]);
+        assert_eq!(octo(": sub ; : main sub"), [0x12, 0x04, 0x00, 0xEE, 0x22, 0x02]);
+    }
+    #[test]
+    fn registers_and_aliases() {
+        let rom = octo(":alias px v3 :const SPEED 2 : main px := SPEED px += 1 px -= 1 px := v4 v1 >>= v1 vf := random 0x0F");
+        assert_eq!(rom, [0x63, 0x02, 0x73, 0x01, 0x73, 0xFF, 0x83, 0x40, 0x81, 0x16, 0xCF, 0x0F]);
+    }
+    #[test]
+    fn conditionals() {
+        assert_eq!(octo(": main if v0 == 5 then v1 := 1"), [0x40, 0x05, 0x61, 0x01]);
+        assert_eq!(octo(": main if v0 key then ;"), [0xE0, 0xA1, 0x00, 0xEE]);
+        assert_eq!(
+            octo(": main if v0 != v1 begin v2 := 1 else v2 := 2 end"),
+            [0x90, 0x10, 0x12, 0x08, 0x62, 0x01, 0x12, 0x0A, 0x62, 0x02]
+        );
+        assert_eq!(octo(": main if v2 > 9 then ;"), [0x6F, 0x09, 0x8F, 0x25, 0x3F, 0x01, 0x00, 0xEE]);
+    }
+    #[test]
+    fn loops() {
+        assert_eq!(
+            octo(": main loop v0 += 1 while v0 != 10 again"),
+            [0x70, 0x01, 0x40, 0x0A, 0x12, 0x08, 0x12, 0x00]
+        );
+    }
+    #[test]
+    fn macros_and_calc() {
+        let rom = octo(":macro twice reg { reg += reg } :calc TOP { 2 * 3 + 1 } : main twice v2 v0 := TOP :byte { TOP - 1 }");
+        // 2 * (3 + 1) because Octo evaluates right to left
+        assert_eq!(rom, [0x82, 0x24, 0x60, 0x08, 0x07]);
+    }
+    #[test]
+    fn org_and_forward_labels() {
+        let rom = octo(": main i := data jump main :org 0x208 : data 0xFF");
+        assert_eq!(rom, [0xA2, 0x08, 0x12, 0x00, 0x00, 0x00, 0x00, 0x00, 0xFF]);
+    }
+    #[test]
+    fn errors_point_at_tokens() {
+        let err = assemble_octo("<input>", ": main\n  v0 := 300").unwrap_err();
+        assert_eq!((err.line, err.column), (2, 9));
+        let err = assemble_octo("<input>", ": main\n  jump nowhere").unwrap_err();
+        assert_eq!((err.line, err.column), (2, 8));
+        let err = assemble_octo("<input>", "v0 := 1").unwrap_err();
+        assert!(err.message.contains("main"));
+    }
+    // the .ch8 files were worked out by hand from the manual, not made by the Octo
+    // compiler, so they only show this front end hasn't changed. they should be
+    // regenerated with the compiler, with its version noted here, when it can be run
+    #[test]
+    fn sample_corpus() {
+        for name in ["counter", "bounce", "keys"] {
+            let source = fs::read_to_string(format!("tests/octo/{}.8o", name)).unwrap();
+            let expected = fs::read(format!("tests/octo/{}.ch8", name)).unwrap();
+            assert_eq!(assemble_octo(name, &source).unwrap(), expected, "{}", name);
+        }
+    }
+}
@@ -0,0 +1,142 @@
+// the sound timer's tone, a square wave that plays while the timer is above zero.
+// the wave is worked out here so it can be tested without a sound card, the stream
+// that plays it only exists with the `sound` feature
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct AudioConfig {
+    // 0 to 1
+    pub volume: f32,
+    // pitch of the tone in Hz
+    pub frequency: f32,
+    pub muted: bool,
+}
+impl Default for AudioConfig {
+    fn default() -> Self {
+        AudioConfig { volume: 0.25, frequency: 440.0, muted: false }
+    }
+}
+
+// one sample at a time, keeping the phase so the wave carries on across buffers
+#[derive(Debug, Clone)]
+pub struct Tone {
+    volume: f32,
+    // fraction of a period each sample moves on
+    step: f32,
+    phase: f32,
+}
+impl Tone {
+    pub fn new(config: &AudioConfig, sample_rate: f32) -> Self {
+        Tone { volume: config.volume, step: config.frequency / sample_rate, phase: 0.0 }
+    }
+    // silence while not playing, starting the wave over so every beep begins the same
+    pub fn next_sample(&mut self, playing: bool) -> f32 {
+        if !playing {
+            self.phase = 0.0;
+            return 0.0;
+        }
+        let sample = if self.phase < 0.5 { self.volume } else { -self.volume };
+        self.phase = (self.phase + self.step).fract();
+        sample
+    }
+}
+
+// what the frontends switch on and off, playing from cpal's thread.
+// muted or built without sound it only keeps the flag
+pub struct Beeper {
+    playing: Arc<AtomicBool>,
+    #[cfg(feature = "sound")]
+    _stream: Option<cpal::Stream>,
+}
+impl Beeper {
+    pub fn silent() -> Self {
+        Beeper {
+            playing: Arc::new(AtomicBool::new(false)),
+            #[cfg(feature = "sound")]
+            _stream: None,
+        }
+    }
+    #[cfg(feature = "sound")]
+    pub fn open(config: &AudioConfig) -> Result<Self, String> {
+        use cpal::traits::{DeviceTrait, HostTrait, StreamTrait};
+        use cpal::SampleFormat;
+        let mut beeper = Beeper::silent();
+        if config.muted {
+            return Ok(beeper);
+        }
+        let device = cpal::default_host().default_output_device().ok_or("no audio output device")?;
+        let supported = device.default_output_config().map_err(|e| e.to_string())?;
+        let format = supported.sample_format();
+        let stream_config = supported.into();
+        let playing = beeper.playing.clone();
+        let stream = match format {
+            SampleFormat::F32 => stream::<f32>(&device, &stream_config, config, playing),
+            SampleFormat::I16 => stream::<i16>(&device, &stream_config, config, playing),
+            SampleFormat::U16 => stream::<u16>(&device, &stream_config, config, playing),
+            other => Err(format!("unsupported sample format {}", other)),
+        }?;
+        stream.play().map_err(|e| e.to_string())?;
+        beeper._stream = Some(stream);
+        Ok(beeper)
+    }
+    #[cfg(not(feature = "sound"))]
+    pub fn open(_config: &AudioConfig) -> Result<Self, String> {
+        Ok(Beeper::silent())
+    }
+    pub fn set_playing(&self, playing: bool) {
+        self.playing.store(playing, Ordering::Relaxed);
+    }
+    pub fn is_playing(&self) -> bool {
+        self.playing.load(Ordering::Relaxed)
+    }
+}
+
+// the same tone on every channel
+#[cfg(feature = "sound")]
+fn stream<T: cpal::SizedSample + cpal::FromSample<f32>>(
+    device: &cpal::Device,
+    stream_config: &cpal::StreamConfig,
+    config: &AudioConfig,
+    playing: Arc<AtomicBool>,
+) -> Result<cpal::Stream, String> {
+    use cpal::traits::DeviceTrait;
+    let channels = stream_config.channels as usize;
+    let mut tone = Tone::new(config, stream_config.sample_rate.0 as f32);
+    device
+        .build_output_stream(
+            stream_config,
+            move |data: &mut [T], _: &cpal::OutputCallbackInfo| {
+                let playing = playing.load(Ordering::Relaxed);
+                for frame in data.chunks_mut(channels) {
+                    frame.fill(T::from_sample(tone.next_sample(playing)));
+                }
+            },
+            |e| eprintln!("audio error: {}", e),
+            None,
+        )
+        .map_err(|e| e.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn tone_is_a_square_wave_at_the_frequency() {
+        let config = AudioConfig { volume: 0.5, frequency: 2.0, muted: false };
+        let mut tone = Tone::new(&config, 8.0);
+        let samples: Vec<f32> = (0..8).map(|_| tone.next_sample(true)).collect();
+        assert_eq!(samples, [0.5, 0.5, -0.5, -0.5, 0.5, 0.5, -0.5, -0.5]);
+    }
+    #[test]
+    fn tone_is_silent_and_starts_over_when_stopped() {
+        let config = AudioConfig { volume: 0.5, frequency: 2.0, muted: false };
+        let mut tone = Tone::new(&config, 8.0);
+        for _ in 0..3 {
+            tone.next_sample(true);
+        }
+        assert_eq!(tone.next_sample(false), 0.0);
+        assert_eq!(tone.next_sample(true), 0.5);
+    }
+}
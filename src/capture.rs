@@ -0,0 +1,185 @@
+// recording a run to an animated GIF or a numbered PNG sequence. frames are added once
+// per emulated 60Hz vblank, never by wall clock, so a headless recording comes out the
+// same every time and a slow window doesn't stretch the animation
+use crate::display::DisplayConfig;
+use crate::screenshot;
+use crate::Chip8;
+use std::fs::{self, File};
+use std::io::BufWriter;
+use std::path::{Path, PathBuf};
+
+pub const CAPTURE_FPS: u32 = 60;
+
+enum Sink {
+    // identical frames are merged into one with a longer delay, kept here until a
+    // different one shows up along with the frame number it started on
+    Gif { encoder: gif::Encoder<BufWriter<File>>, pending: Option<(Vec<u8>, u32)> },
+    Png { dir: PathBuf },
+}
+
+pub struct Recorder {
+    display: DisplayConfig,
+    sink: Sink,
+    frames: u32,
+}
+impl Recorder {
+    // `.gif` paths get an animation, anything else is a directory for 00000.png, 00001.png..
+    pub fn create(path: &Path, display: &DisplayConfig) -> Result<Recorder, String> {
+        let error = |e: &dyn std::fmt::Display| format!("{}: {}", path.display(), e);
+        let is_gif = path.extension().is_some_and(|ext| ext.eq_ignore_ascii_case("gif"));
+        let sink = if is_gif {
+            let (width, height) = (display.width(), display.height());
+            if width > u16::MAX as usize || height > u16::MAX as usize {
+                return Err(error(&"too large for a gif, lower the scale"));
+            }
+            let file = BufWriter::new(File::create(path).map_err(|e| error(&e))?);
+            let palette: Vec<u8> =
+                palette(display).iter().flat_map(|&color| [(color >> 16) as u8, (color >> 8) as u8, color as u8]).collect();
+            let mut encoder = gif::Encoder::new(file, width as u16, height as u16, &palette).map_err(|e| error(&e))?;
+            encoder.set_repeat(gif::Repeat::Infinite).map_err(|e| error(&e))?;
+            Sink::Gif { encoder, pending: None }
+        } else {
+            fs::create_dir_all(path).map_err(|e| error(&e))?;
+            Sink::Png { dir: path.to_path_buf() }
+        };
+        Ok(Recorder { display: display.clone(), sink, frames: 0 })
+    }
+    pub fn frames(&self) -> u32 {
+        self.frames
+    }
+
+    // call once per vblank while recording
+    pub fn capture(&mut self, chip8: &Chip8) -> Result<(), String> {
+        let screen = chip8.get_screen();
+        match &mut self.sink {
+            Sink::Gif { encoder, pending } => {
+                let colors = palette(&self.display);
+                let pixels: Vec<u8> = self
+                    .display
+                    .render(&screen)
+                    .iter()
+                    .map(|color| colors.iter().position(|c| c == color).unwrap_or(0) as u8)
+                    .collect();
+                match pending {
+                    Some((previous, _)) if *previous == pixels => {}
+                    _ => {
+                        if let Some((previous, start)) = pending.replace((pixels, self.frames)) {
+                            write_gif_frame(encoder, &self.display, previous, start, self.frames)?;
+                        }
+                    }
+                }
+            }
+            Sink::Png { dir } => {
+                let image = screenshot::png(&screen, &self.display)?;
+                let path = dir.join(format!("{:05}.png", self.frames));
+                fs::write(&path, image).map_err(|e| format!("{}: {}", path.display(), e))?;
+            }
+        }
+        self.frames += 1;
+        Ok(())
+    }
+
+    // writes out whatever is still buffered, returns how many frames were captured
+    pub fn finish(self) -> Result<u32, String> {
+        if let Sink::Gif { mut encoder, pending: Some((pixels, start)) } = self.sink {
+            write_gif_frame(&mut encoder, &self.display, pixels, start, self.frames)?;
+            encoder.into_inner().map_err(|e| e.to_string())?;
+        }
+        Ok(self.frames)
+    }
+}
+
+// background, foreground and the grid if there is one, in that order
+fn palette(display: &DisplayConfig) -> Vec<u32> {
+    let mut colors = vec![display.background, display.foreground];
+    colors.extend(display.grid);
+    colors
+}
+
+// gif delays are in hundredths of a second, which 60Hz doesn't divide into. each frame
+// ends on the hundredth nearest its real end time so the total stays exact
+fn centiseconds(frame: u32) -> u32 {
+    (frame * 100 + CAPTURE_FPS / 2) / CAPTURE_FPS
+}
+fn write_gif_frame(
+    encoder: &mut gif::Encoder<BufWriter<File>>,
+    display: &DisplayConfig,
+    pixels: Vec<u8>,
+    start: u32,
+    end: u32,
+) -> Result<(), String> {
+    let mut frame = gif::Frame::from_indexed_pixels(display.width() as u16, display.height() as u16, pixels, None);
+    frame.delay = (centiseconds(end) - centiseconds(start)) as u16;
+    encoder.write_frame(&frame).map_err(|e| e.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cpu::opcodes::OpCode;
+
+    // a rom that draws the top left pixel on and off every instruction
+    fn blinker() -> Chip8 {
+        let mut chip8 = Chip8::new();
+        chip8.i_reg = 0x300;
+        chip8.ram.write_byte(0x300, 0x80);
+        for (i, op) in [OpCode::DXYN(0, 0, 1), OpCode::JMP(0x200)].iter().enumerate() {
+            for (j, byte) in op.encode().to_be_bytes().iter().enumerate() {
+                chip8.ram.write_byte(0x200 + (i * 2 + j) as u16, *byte);
+            }
+        }
+        chip8.pc = 0x200;
+        chip8
+    }
+
+    #[test]
+    fn gif_merges_repeated_frames() {
+        let path = std::env::temp_dir().join(format!("chip8-capture-{}.gif", std::process::id()));
+        let display = DisplayConfig { scale: 2, ..DisplayConfig::default() };
+        let mut recorder = Recorder::create(&path, &display).unwrap();
+        let mut chip8 = blinker();
+        // 30 frames with the pixel on, then 30 more after it's switched off
+        chip8.cycle();
+        for _ in 0..30 {
+            recorder.capture(&chip8).unwrap();
+        }
+        chip8.cycle();
+        chip8.cycle();
+        for _ in 0..30 {
+            recorder.capture(&chip8).unwrap();
+        }
+        assert_eq!(recorder.finish(), Ok(60));
+
+        let mut options = gif::DecodeOptions::new();
+        options.set_color_output(gif::ColorOutput::Indexed);
+        let mut decoder = options.read_info(File::open(&path).unwrap()).unwrap();
+        assert_eq!((decoder.width(), decoder.height()), (128, 64));
+        let mut delays = Vec::new();
+        while let Some(frame) = decoder.read_next_frame().unwrap() {
+            delays.push(frame.delay);
+            assert_eq!(frame.buffer[0], (delays.len() == 1) as u8);
+        }
+        assert_eq!(delays, vec![50, 50]);
+        fs::remove_file(&path).unwrap();
+    }
+    #[test]
+    fn png_sequence_has_a_file_per_frame() {
+        let dir = std::env::temp_dir().join(format!("chip8-capture-{}", std::process::id()));
+        let mut recorder = Recorder::create(&dir, &DisplayConfig::default()).unwrap();
+        let chip8 = blinker();
+        for _ in 0..3 {
+            recorder.capture(&chip8).unwrap();
+        }
+        assert_eq!(recorder.frames(), 3);
+        assert_eq!(recorder.finish(), Ok(3));
+        assert!(dir.join("00002.png").exists());
+        assert!(!dir.join("00003.png").exists());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+    #[test]
+    fn gif_delays_add_up_to_real_time() {
+        let delays: Vec<u32> = (0..6).map(|f| centiseconds(f + 1) - centiseconds(f)).collect();
+        assert_eq!(delays, vec![2, 1, 2, 2, 1, 2]);
+        assert_eq!(delays.iter().sum::<u32>(), 10);
+    }
+}
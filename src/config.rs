@@ -0,0 +1,408 @@
+// settings file, TOML with one table per area:
+//
+//   [display]
+//   scale = 10
+//   palette = "amber"
+//   foreground = "#FFB000"
+//   grid = true
+//   filter = "phosphor:150"
+//   crt = "scanlines,bloom:8:0.5,vignette"
+//
+//   [input]
+//   keys = "x123qweasdzc4rfv"
+//
+//   [machine]
+//   speed = 12
+//   platform = "cosmac"
+//   clip_sprites = false
+//   load_address = 0x600
+//
+//   [audio]
+//   volume = 0.5
+//   frequency = 440
+//   muted = false
+//
+//   # any of the tables above for a single rom, keyed by the SHA-1 of its bytes
+//   [rom.0df2789f661358d8f7370e6cf93490c5bcd44b01.machine]
+//   speed = 30
+//
+// every key is optional. rom tables override the ones above them and command line
+// flags override whatever is set here
+use crate::audio::AudioConfig;
+use crate::cpu::quirks::{Layout, Platform, Quirks};
+use crate::crt::parse_passes;
+use crate::display::{parse_color, DisplayConfig};
+use crate::terminal::KEYPAD;
+use serde::Deserialize;
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+#[derive(Debug, Default, Clone, Deserialize, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    pub display: DisplaySection,
+    pub input: InputSection,
+    pub machine: MachineSection,
+    pub audio: AudioSection,
+    pub rom: BTreeMap<String, RomSection>,
+}
+
+#[derive(Debug, Default, Clone, Deserialize, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct RomSection {
+    // only there to say which rom a hash is, not used
+    pub title: Option<String>,
+    pub display: DisplaySection,
+    pub input: InputSection,
+    pub machine: MachineSection,
+    pub audio: AudioSection,
+}
+
+#[derive(Debug, Default, Clone, Deserialize, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct DisplaySection {
+    pub scale: Option<usize>,
+    pub palette: Option<String>,
+    pub foreground: Option<String>,
+    pub background: Option<String>,
+    pub grid: Option<bool>,
+    pub grid_color: Option<String>,
+    pub filter: Option<String>,
+    pub crt: Option<String>,
+}
+impl DisplaySection {
+    // with anything set in `other` taking over
+    pub fn overridden_by(&self, other: &DisplaySection) -> DisplaySection {
+        DisplaySection {
+            scale: other.scale.or(self.scale),
+            palette: other.palette.clone().or_else(|| self.palette.clone()),
+            foreground: other.foreground.clone().or_else(|| self.foreground.clone()),
+            background: other.background.clone().or_else(|| self.background.clone()),
+            grid: other.grid.or(self.grid),
+            grid_color: other.grid_color.clone().or_else(|| self.grid_color.clone()),
+            filter: other.filter.clone().or_else(|| self.filter.clone()),
+            crt: other.crt.clone().or_else(|| self.crt.clone()),
+        }
+    }
+    // palette first so explicit colors can adjust it
+    pub fn apply(&self, display: &mut DisplayConfig) -> Result<(), String> {
+        if let Some(scale) = self.scale {
+            if scale == 0 {
+                return Err("scale must be at least 1".to_string());
+            }
+            display.scale = scale;
+        }
+        if let Some(name) = &self.palette {
+            display.set_palette(name)?;
+        }
+        if let Some(color) = &self.foreground {
+            display.foreground = parse_color(color)?;
+        }
+        if let Some(color) = &self.background {
+            display.background = parse_color(color)?;
+        }
+        match (self.grid, &self.grid_color) {
+            (Some(false), _) => display.grid = None,
+            (_, Some(color)) => display.grid = Some(parse_color(color)?),
+            (Some(true), None) => display.grid = Some(display.default_grid_color()),
+            (None, None) => {}
+        }
+        if let Some(filter) = &self.filter {
+            display.filter = if filter == "none" { None } else { Some(filter.parse()?) };
+        }
+        if let Some(crt) = &self.crt {
+            display.crt = if crt == "none" { Vec::new() } else { parse_passes(crt)? };
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, Default, Clone, Deserialize, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct InputSection {
+    // the host keys for chip-8 keys 0 to F, in that order
+    pub keys: Option<String>,
+}
+impl InputSection {
+    pub fn overridden_by(&self, other: &InputSection) -> InputSection {
+        InputSection { keys: other.keys.clone().or_else(|| self.keys.clone()) }
+    }
+    pub fn keys(&self) -> Result<[char; 16], String> {
+        let Some(text) = &self.keys else { return Ok(KEYPAD) };
+        let keys: Vec<char> = text.chars().map(|c| c.to_ascii_lowercase()).collect();
+        let keys: [char; 16] =
+            keys.try_into().map_err(|_| format!("keys `{}` should be 16 characters, one for each of 0 to F", text))?;
+        for (i, c) in keys.iter().enumerate() {
+            if let Some(first) = keys[..i].iter().position(|k| k == c) {
+                return Err(format!("keys `{}` use `{}` for both {:X} and {:X}", text, c, first, i));
+            }
+        }
+        Ok(keys)
+    }
+}
+
+#[derive(Debug, Default, Clone, Deserialize, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct AudioSection {
+    // 0 to 1
+    pub volume: Option<f32>,
+    // pitch of the tone in Hz
+    pub frequency: Option<f32>,
+    pub muted: Option<bool>,
+}
+impl AudioSection {
+    pub fn overridden_by(&self, other: &AudioSection) -> AudioSection {
+        AudioSection {
+            volume: other.volume.or(self.volume),
+            frequency: other.frequency.or(self.frequency),
+            muted: other.muted.or(self.muted),
+        }
+    }
+    pub fn apply(&self, audio: &mut AudioConfig) -> Result<(), String> {
+        if let Some(volume) = self.volume {
+            if !(0.0..=1.0).contains(&volume) {
+                return Err(format!("volume {} should be between 0 and 1", volume));
+            }
+            audio.volume = volume;
+        }
+        if let Some(frequency) = self.frequency {
+            if !(20.0..=20000.0).contains(&frequency) {
+                return Err(format!("frequency {} should be between 20 and 20000 Hz", frequency));
+            }
+            audio.frequency = frequency;
+        }
+        if let Some(muted) = self.muted {
+            audio.muted = muted;
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, Default, Clone, Deserialize, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct MachineSection {
+    // instructions per 60Hz frame
+    pub speed: Option<u32>,
+    // quirks and layout to start from, cosmac, schip, eti660 or hybrid
+    pub platform: Option<String>,
+    // single quirks on top of the platform's, see cpu::quirks
+    pub shift_vx: Option<bool>,
+    pub memory_increments_i: Option<bool>,
+    pub jump_vx: Option<bool>,
+    pub vf_reset: Option<bool>,
+    pub clip_sprites: Option<bool>,
+    // where the program goes in memory and where it starts, both the platform's otherwise.
+    // the entry point follows the load address unless it's set too
+    pub load_address: Option<u16>,
+    pub entry: Option<u16>,
+}
+impl MachineSection {
+    pub fn overridden_by(&self, other: &MachineSection) -> MachineSection {
+        MachineSection {
+            speed: other.speed.or(self.speed),
+            // a platform replaces every quirk and the layout, so one set here clears single
+            // settings from below
+            platform: other.platform.clone().or_else(|| self.platform.clone()),
+            shift_vx: other.shift_vx.or(self.shift_vx.filter(|_| other.platform.is_none())),
+            memory_increments_i: other.memory_increments_i.or(self.memory_increments_i.filter(|_| other.platform.is_none())),
+            jump_vx: other.jump_vx.or(self.jump_vx.filter(|_| other.platform.is_none())),
+            vf_reset: other.vf_reset.or(self.vf_reset.filter(|_| other.platform.is_none())),
+            clip_sprites: other.clip_sprites.or(self.clip_sprites.filter(|_| other.platform.is_none())),
+            load_address: other.load_address.or(self.load_address.filter(|_| other.platform.is_none())),
+            entry: other.entry.or(self.entry.filter(|_| other.platform.is_none())),
+        }
+    }
+    pub fn layout(&self) -> Result<Layout, String> {
+        let mut layout = match &self.platform {
+            Some(name) => name.parse::<Platform>()?.layout(),
+            None => Layout::default(),
+        };
+        if let Some(address) = self.load_address {
+            layout = Layout { load_address: address, entry: address, ..layout };
+        }
+        if let Some(entry) = self.entry {
+            layout.entry = entry;
+        }
+        for (name, address) in [("load address", layout.load_address), ("entry point", layout.entry)] {
+            if address > 0xFFF {
+                return Err(format!("{} {:X} is past the end of memory", name, address));
+            }
+        }
+        Ok(layout)
+    }
+    pub fn quirks(&self) -> Result<Quirks, String> {
+        let mut quirks = match &self.platform {
+            Some(name) => name.parse::<Platform>()?.quirks(),
+            None => Quirks::default(),
+        };
+        let flags = [
+            (self.shift_vx, &mut quirks.shift_vx),
+            (self.memory_increments_i, &mut quirks.memory_increments_i),
+            (self.jump_vx, &mut quirks.jump_vx),
+            (self.vf_reset, &mut quirks.vf_reset),
+            (self.clip_sprites, &mut quirks.clip_sprites),
+        ];
+        for (setting, quirk) in flags {
+            if let Some(on) = setting {
+                *quirk = on;
+            }
+        }
+        Ok(quirks)
+    }
+}
+
+// lowercase hex, what the rom tables are keyed by
+pub fn rom_hash(rom: &[u8]) -> String {
+    sha1_smol::Sha1::from(rom).digest().to_string()
+}
+
+// chip-8/config.toml in the platform's config directory
+pub fn user_config_path() -> Option<PathBuf> {
+    let env = |name: &str| std::env::var_os(name).filter(|value| !value.is_empty()).map(PathBuf::from);
+    let dir = if cfg!(windows) {
+        env("APPDATA")?
+    } else if cfg!(target_os = "macos") {
+        env("HOME")?.join("Library/Application Support")
+    } else {
+        env("XDG_CONFIG_HOME").or_else(|| Some(env("HOME")?.join(".config")))?
+    };
+    Some(dir.join("chip-8").join("config.toml"))
+}
+
+impl Config {
+    pub fn parse(text: &str) -> Result<Config, String> {
+        toml::from_str(text).map_err(|e| e.to_string())
+    }
+    pub fn load(path: &Path) -> Result<Config, String> {
+        let text = fs::read_to_string(path).map_err(|e| format!("{}: {}", path.display(), e))?;
+        Config::parse(&text).map_err(|e| format!("{}: {}", path.display(), e))
+    }
+    // the settings for one rom. what's known about it from elsewhere goes over the
+    // defaults and its own table, if there is one, over that
+    pub fn for_rom(&self, rom: &[u8], detected: Option<&RomSection>) -> Config {
+        let hash = rom_hash(rom);
+        let table = self.rom.iter().find(|(key, _)| key.eq_ignore_ascii_case(&hash)).map(|(_, section)| section);
+        let mut config = Config { rom: BTreeMap::new(), ..self.clone() };
+        for overrides in detected.into_iter().chain(table) {
+            config = Config {
+                display: config.display.overridden_by(&overrides.display),
+                input: config.input.overridden_by(&overrides.input),
+                machine: config.machine.overridden_by(&overrides.machine),
+                audio: config.audio.overridden_by(&overrides.audio),
+                rom: BTreeMap::new(),
+            };
+        }
+        config
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::crt::Pass;
+    use crate::filter::Filter;
+
+    #[test]
+    fn parse_display_section() {
+        let config =
+            Config::parse("[display]\nscale = 10\npalette = \"amber\"\ngrid = true\nfilter = \"blend:3\"\ncrt = \"scanlines\"\n").unwrap();
+        let mut display = DisplayConfig::default();
+        config.display.apply(&mut display).unwrap();
+        assert_eq!(display.scale, 10);
+        assert_eq!(display.foreground, 0xFFB000);
+        assert!(display.grid.is_some());
+        assert_eq!(display.filter, Some(Filter::Blend(3)));
+        assert_eq!(display.crt, vec![Pass::Scanlines(0.35)]);
+        assert_eq!(Config::parse("").unwrap(), Config::default());
+    }
+    #[test]
+    fn colors_override_palette() {
+        let section = DisplaySection {
+            palette: Some("green".to_string()),
+            background: Some("#000000".to_string()),
+            ..Default::default()
+        };
+        let mut display = DisplayConfig::default();
+        section.apply(&mut display).unwrap();
+        assert_eq!((display.foreground, display.background), (0x33FF66, 0));
+    }
+    #[test]
+    fn rejects_bad_values() {
+        assert!(Config::parse("[display]\nsacle = 3\n").unwrap_err().contains("sacle"));
+        let mut display = DisplayConfig::default();
+        let bad_scale = DisplaySection { scale: Some(0), ..Default::default() };
+        assert!(bad_scale.apply(&mut display).is_err());
+        let bad_color = DisplaySection { foreground: Some("#12".to_string()), ..Default::default() };
+        assert!(bad_color.apply(&mut display).is_err());
+    }
+    #[test]
+    fn rom_tables_override_defaults() {
+        let rom = [0x12, 0x00];
+        // hashes pasted in upper case still match
+        let hash = rom_hash(&rom).to_uppercase();
+        let text = format!(
+            "[machine]\nspeed = 10\nplatform = \"schip\"\njump_vx = false\n[input]\nkeys = \"0123456789abcdef\"\n\
+             [rom.{hash}]\ntitle = \"loop\"\n[rom.{hash}.machine]\nplatform = \"cosmac\"\n[rom.{hash}.display]\nscale = 4\n"
+        );
+        let config = Config::parse(&text).unwrap();
+        let settings = config.for_rom(&rom, None);
+        assert_eq!(settings.machine.speed, Some(10));
+        assert_eq!(settings.machine.quirks(), Ok(Platform::Cosmac.quirks()));
+        assert_eq!(settings.display.scale, Some(4));
+        assert_eq!(settings.input.keys().unwrap()[0xA], 'a');
+        // anything else only gets the defaults
+        let other = config.for_rom(&[0x00, 0xE0], None);
+        assert_eq!(other.machine.quirks(), Ok(Quirks { jump_vx: false, ..Platform::Schip.quirks() }));
+        assert_eq!(other.display.scale, None);
+        // a detected platform goes over the defaults but not the rom's table
+        let detected = RomSection {
+            machine: MachineSection { speed: Some(20), platform: Some("schip".to_string()), ..Default::default() },
+            ..Default::default()
+        };
+        let settings = config.for_rom(&rom, Some(&detected));
+        assert_eq!((settings.machine.speed, settings.machine.quirks()), (Some(20), Ok(Platform::Cosmac.quirks())));
+        let other = config.for_rom(&[0x00, 0xE0], Some(&detected));
+        assert_eq!(other.machine.quirks(), Ok(Platform::Schip.quirks()));
+    }
+    #[test]
+    fn audio_keys_set_the_tone() {
+        let rom = [0x12, 0x00];
+        let text = format!("[audio]\nvolume = 0.5\nfrequency = 880\n[rom.{}.audio]\nmuted = true\n", rom_hash(&rom));
+        let audio = Config::parse(&text).unwrap().for_rom(&rom, None).audio;
+        assert_eq!(audio, AudioSection { volume: Some(0.5), frequency: Some(880.0), muted: Some(true) });
+        let mut config = AudioConfig::default();
+        audio.apply(&mut config).unwrap();
+        assert_eq!(config, AudioConfig { volume: 0.5, frequency: 880.0, muted: true });
+        assert!(Config::parse("[audio]\nvolume = 2\n").unwrap().audio.apply(&mut config).is_err());
+        assert!(Config::parse("[audio]\nfrequency = 0\n").unwrap().audio.apply(&mut config).is_err());
+        assert!(Config::parse("[audio]\npitch = 440\n").is_err());
+    }
+    #[test]
+    fn layout_from_platform_or_addresses() {
+        let machine = |text: &str| Config::parse(&format!("[machine]\n{}", text)).unwrap().machine.layout();
+        assert_eq!(machine(""), Ok(Layout { load_address: 0x200, entry: 0x200, display: None }));
+        assert_eq!(machine("platform = \"eti660\""), Ok(Layout { load_address: 0x600, entry: 0x600, display: None }));
+        assert_eq!(machine("load_address = 0x300"), Ok(Layout { load_address: 0x300, entry: 0x300, display: None }));
+        assert_eq!(machine("platform = \"eti660\"\nentry = 0x700"), Ok(Layout { load_address: 0x600, entry: 0x700, display: None }));
+        // moving the program keeps the hybrid display where it is
+        assert_eq!(machine("platform = \"hybrid\"\nload_address = 0x300"), Ok(Layout { load_address: 0x300, entry: 0x300, display: Some(0xF00) }));
+        assert!(machine("entry = 0x1000").is_err());
+        // a platform higher up replaces addresses from below
+        let below = MachineSection { load_address: Some(0x300), ..Default::default() };
+        let above = MachineSection { platform: Some("cosmac".to_string()), ..Default::default() };
+        assert_eq!(below.overridden_by(&above).layout(), Ok(Layout::default()));
+    }
+    #[test]
+    fn keys_need_one_each() {
+        assert_eq!(InputSection::default().keys(), Ok(KEYPAD));
+        let keys = |text: &str| InputSection { keys: Some(text.to_string()) }.keys();
+        assert!(keys("x123").is_err());
+        assert!(keys("x123qweasdzc4rfx").unwrap_err().contains("for both 0 and F"));
+        assert_eq!(keys("X123QWEASDZC4RFV"), Ok(KEYPAD));
+    }
+    #[test]
+    fn sha1_of_rom_bytes() {
+        assert_eq!(rom_hash(b"abc"), "a9993e364706816aba3e25717850c26c9cd0d89d");
+    }
+}
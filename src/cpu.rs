@@ -1,11 +1,27 @@
-mod opcodes;
+pub mod opcodes;
 use opcodes::OpCode;
 mod ram;
 use ram::EmulatedRam;
 mod screen;
 use screen::EmulatedScreen;
-use std::{fs, ops::Add};
-use rand;
+pub use screen::Rect;
+pub mod debugger;
+use debugger::{Access, BreakReason, Debugger, MachineState};
+pub mod trace;
+use trace::Tracer;
+mod blocks;
+use blocks::BlockCache;
+use debugger::Fault;
+pub mod quirks;
+mod state;
+use quirks::{Layout, Quirks};
+use crate::rom::{self, Report};
+use std::fs;
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+
+// the original interpreter reserved room for 16 return addresses
+const STACK_DEPTH: u8 = 16;
 
 struct DelayTimer {
     val: u8,
@@ -15,6 +31,14 @@ impl DelayTimer {
         DelayTimer { val: 0 }
     }
 }
+struct SoundTimer {
+    val: u8,
+}
+impl SoundTimer {
+    fn new() -> SoundTimer {
+        SoundTimer { val: 0 }
+    }
+}
 
 pub struct Chip8 {
     pub pc: u16,
@@ -22,91 +46,271 @@ pub struct Chip8 {
     address_stack: Vec<u16>,
     stack_pointer: u8,
     delay_timer: DelayTimer,
+    sound_timer: SoundTimer,
     pub v_registers: [u8; 16],
     screen: EmulatedScreen,
     pub ram: EmulatedRam,
+    pub debugger: Debugger,
+    tracer: Option<Tracer>,
+    keys: [bool; 16],
+    // the key FX0A saw go down, it finishes once that key is let go
+    key_wait: Option<u8>,
+    // set by execute when an instruction can't run, picked up by cycle
+    fault: Option<Fault>,
+    decode_cache: bool,
+    blocks: BlockCache,
+    quirks: Quirks,
+    // CXNN draws from this. the seed is kept so reset replays the same numbers
+    rng: StdRng,
+    seed: u64,
+    layout: Layout,
+    // the loaded program, put back by reset
+    rom: Vec<u8>,
+}
+impl Default for Chip8 {
+    fn default() -> Self {
+        Chip8::new()
+    }
 }
 impl Chip8 {
     pub fn new() -> Chip8 {
+        let seed = rand::random();
         Chip8 {
             pc: 0,
             i_reg: 0,
             address_stack: Vec::new(),
             stack_pointer: 0,
             delay_timer: DelayTimer::new(),
+            sound_timer: SoundTimer::new(),
             v_registers: [0x0; 16],
             screen: EmulatedScreen::new(),
             ram: EmulatedRam::new(),
+            debugger: Debugger::new(),
+            tracer: None,
+            keys: [false; 16],
+            key_wait: None,
+            fault: None,
+            decode_cache: true,
+            blocks: BlockCache::new(),
+            quirks: Quirks::default(),
+            rng: StdRng::seed_from_u64(seed),
+            seed,
+            layout: Layout::default(),
+            rom: Vec::new(),
         }
     }
     pub fn get_screen(&self) -> [[bool; 64]; 32] {
         self.screen.get_screen()
     }
-    pub fn load_program(&mut self, path: &str) {
-        self.ram.load_program_from_file(path);
+    // packed rows, bit 63 is x = 0
+    pub fn screen_rows(&self) -> &[u64; 32] {
+        self.screen.rows()
+    }
+    pub fn is_screen_dirty(&self) -> bool {
+        self.screen.is_dirty()
+    }
+    // what changed on screen since the last call
+    pub fn take_dirty_rects(&mut self) -> Vec<Rect> {
+        self.screen.take_dirty_rects()
+    }
+    // bumped by every 00E0, lets a frontend notice a frame was finished
+    pub fn clear_count(&self) -> u32 {
+        self.screen.clears()
+    }
+    // the packed screen as it was just before the last 00E0
+    pub fn frame_before_clear(&self) -> &[u64; 32] {
+        self.screen.before_clear()
+    }
+    // coordinates wrap like sprite drawing does
+    pub fn get_pixel(&self, x: u8, y: u8) -> bool {
+        self.screen.get_pixel(x, y)
+    }
+    pub fn stack_pointer(&self) -> u8 {
+        self.stack_pointer
+    }
+    // keeps the address stack the same depth as the pointer, new entries are zeroed
+    pub fn set_stack_pointer(&mut self, sp: u8) {
+        self.stack_pointer = sp;
+        self.address_stack.resize(sp as usize, 0);
+    }
+    pub fn stack(&self) -> &[u16] {
+        &self.address_stack
+    }
+    pub fn delay_timer(&self) -> u8 {
+        self.delay_timer.val
+    }
+    pub fn set_delay_timer(&mut self, val: u8) {
+        self.delay_timer.val = val;
+    }
+    pub fn sound_timer(&self) -> u8 {
+        self.sound_timer.val
+    }
+    pub fn set_sound_timer(&mut self, val: u8) {
+        self.sound_timer.val = val;
+    }
+    // keys are 0x0-0xF, anything higher is ignored
+    pub fn set_key(&mut self, key: u8, pressed: bool) {
+        if let Some(state) = self.keys.get_mut(key as usize) {
+            *state = pressed;
+        }
+    }
+    pub fn is_key_pressed(&self, key: u8) -> bool {
+        self.keys[(key & 0xF) as usize]
+    }
+    // both timers count down at 60Hz, the frontend calls this once per tick
+    pub fn tick_timers(&mut self) {
+        self.delay_timer.val = self.delay_timer.val.saturating_sub(1);
+        self.sound_timer.val = self.sound_timer.val.saturating_sub(1);
+    }
+    // on by default, turning it off decodes every instruction as it is fetched
+    pub fn set_decode_cache(&mut self, enabled: bool) {
+        self.decode_cache = enabled;
+    }
+    pub fn quirks(&self) -> Quirks {
+        self.quirks
+    }
+    pub fn set_quirks(&mut self, quirks: Quirks) {
+        self.quirks = quirks;
+    }
+    pub fn layout(&self) -> Layout {
+        self.layout
+    }
+    // takes effect from the next load. both addresses and all of the display have to be
+    // inside the 4K of memory
+    pub fn set_layout(&mut self, layout: Layout) -> Result<(), String> {
+        if layout.load_address > 0xFFF || layout.entry > 0xFFF {
+            return Err(format!("load address {:X} and entry point {:X} have to be at most FFF", layout.load_address, layout.entry));
+        }
+        if layout.display.is_some_and(|address| address > 0xF00) {
+            return Err(format!("the display needs 256 bytes from {:X}", layout.display.unwrap_or_default()));
+        }
+        self.layout = layout;
+        Ok(())
+    }
+    // makes CXNN give the same numbers every run
+    pub fn set_seed(&mut self, seed: u64) {
+        self.seed = seed;
+        self.rng = StdRng::seed_from_u64(seed);
+    }
+    // picked at random unless set_seed was called
+    pub fn seed(&self) -> u64 {
+        self.seed
+    }
+    // tracing is off unless a tracer is set
+    pub fn set_tracer(&mut self, tracer: Option<Tracer>) {
+        self.tracer = tracer;
+    }
+    // checks the rom as plain chip-8 and loads it at the layout's load address if nothing
+    // stops it, ready to run from its entry point. warnings come back in the report, see
+    // rom::validate for checking against a platform
+    pub fn load_rom_bytes(&mut self, rom: &[u8]) -> Result<Report, String> {
+        let report = rom::validate(rom, self.layout.load_address, None);
+        if let Some(error) = report.errors().next() {
+            return Err(error.to_string());
+        }
+        self.ram.load_program(rom, self.layout.load_address);
+        self.store_display();
+        self.pc = self.layout.entry;
+        self.rom = rom.to_vec();
+        Ok(report)
+    }
+    // back to just after the rom was loaded. quirks, layout, breakpoints and the tracer
+    // stay as they are, and CXNN starts over from the same seed
+    pub fn reset(&mut self) {
+        self.pc = self.layout.entry;
+        self.i_reg = 0;
+        self.address_stack.clear();
+        self.stack_pointer = 0;
+        self.delay_timer.val = 0;
+        self.sound_timer.val = 0;
+        self.v_registers = [0; 16];
+        // cleared rather than replaced so the frontend sees every lit pixel change
+        self.screen.clear();
+        self.ram = EmulatedRam::new();
+        self.ram.load_program(&self.rom, self.layout.load_address);
+        self.store_display();
+        self.blocks = BlockCache::new();
+        self.keys = [false; 16];
+        self.key_wait = None;
+        self.fault = None;
+        self.rng = StdRng::seed_from_u64(self.seed);
+    }
+    pub fn load_program(&mut self, path: &str) -> Result<Report, String> {
+        let rom = fs::read(path).map_err(|e| format!("{}: {}", path, e))?;
+        self.load_rom_bytes(&rom).map_err(|e| format!("{}: {}", path, e))
     }
     fn fetch(&mut self) -> u16 {
-        let byte: u16 =
-            (self.ram.read_byte(self.pc) as u16) << 8 | self.ram.read_byte(self.pc + 1) as u16;
-        self.pc += 2;
+        let byte: u16 = (self.ram.read_byte(self.pc & 0xFFF) as u16) << 8
+            | self.ram.read_byte((self.pc + 1) & 0xFFF) as u16;
+        self.skip();
         byte
     }
+    // the program counter wraps at the top of the 4K address space
+    fn skip(&mut self) {
+        self.pc = (self.pc + 2) & 0xFFF;
+    }
+    // ram accesses made by instructions go through these so the debugger can watch them,
+    // addresses past 0xFFF wrap around like the 12 bit address bus would
+    fn read_mem(&mut self, address: u16) -> u8 {
+        let address = address & 0xFFF;
+        let value = self.ram.read_byte(address);
+        self.debugger.on_access(address, Access::Read, value);
+        value
+    }
+    fn write_mem(&mut self, address: u16, value: u8) {
+        let address = address & 0xFFF;
+        self.ram.write_byte(address, value);
+        self.mirror_display(address, value);
+        self.debugger.on_access(address, Access::Write, value);
+    }
+    // writes from a debugger rather than the program. they show on screen the same as an
+    // instruction's would but don't set off watchpoints
+    pub fn debug_write_byte(&mut self, address: u16, value: u8) {
+        let address = address & 0xFFF;
+        self.ram.write_byte(address, value);
+        self.mirror_display(address, value);
+    }
+    // for layouts that keep the screen in memory, a write there changes the screen too
+    fn mirror_display(&mut self, address: u16, value: u8) {
+        if let Some(offset) = self.layout.display.and_then(|display| address.checked_sub(display)).filter(|offset| *offset < 0x100) {
+            self.screen.set_byte(offset as u8 % 8, (offset / 8) as u8, value);
+        }
+    }
+    // copies the screen into memory for layouts that keep it there
+    fn store_display(&mut self) {
+        let Some(display) = self.layout.display else { return };
+        let rows = *self.screen.rows();
+        for (y, row) in rows.iter().enumerate() {
+            for (column, byte) in row.to_be_bytes().into_iter().enumerate() {
+                self.ram.write_byte(display + (y * 8 + column) as u16, byte);
+            }
+        }
+    }
+    pub fn machine_state(&self) -> MachineState {
+        MachineState {
+            v_registers: self.v_registers,
+            i_reg: self.i_reg,
+            pc: self.pc,
+            stack_pointer: self.stack_pointer,
+            delay_timer: self.delay_timer.val,
+            sound_timer: self.sound_timer.val,
+        }
+    }
 
-    fn decode(&mut self, instruction: u16) -> OpCode {
-        let upper_byte = ((instruction & 0xFF00) >> 8) as u8;
-        let lower_byte = (instruction & 0x00FF) as u8;
-        let op = (upper_byte & 0xF0) >> 4;
-        let x = upper_byte & 0x0F;
-        let y = (lower_byte & 0xF0) >> 4;
-        let d = lower_byte & 0x0F;
-        let nnn = instruction & 0x0FFF;
-        // println!("upper byte {:2x}", upper_byte);
-        // println!("lower byte {:2x}", lower_byte);
-        // println!("op {:2x}", op);
-        // println!("x nibble {:1x}", x);
-        // println!("y nibble {:1x}", y);
-        // println!("nnn {:3x}", nnn);
-        match (op, x, y, d) {
-            (0, 0, 0xE, 0) => OpCode::CLR,
-            (0, 0, 0xE, 0xE) => OpCode::RET,
-            (0x1, _, _, _) => OpCode::JMP(nnn),
-            (0x2, _, _, _) => OpCode::CALL(nnn),
-            (0x3, _, _, _) => OpCode::SkipEqualNN(x, lower_byte),
-            (0x4, _, _, _) => OpCode::SkipNotEqualNN(x, lower_byte),
-            (0x5, _, _, _) => OpCode::SkipEqualXY(x, y),
-            (0x6, _, _, _) => OpCode::SET(x, lower_byte),
-            (0x7, _, _, _) => OpCode::ADD(x, lower_byte),
-            (0x8, _, _, 0) => OpCode::LDXY(x, y),
-            (0x8, _, _, 1) => OpCode::BOR(x, y),
-            (0x8, _, _, 2) => OpCode::BAND(x, y),
-            (0x8, _, _, 3) => OpCode::BXOR(x, y),
-            (0x8, _, _, 4) => OpCode::AddXY(x, y),
-            (0x8, _, _, 5) => OpCode::SubXY(x, y),
-            (0x8, _, _, 6) => OpCode::SHR(x, y),
-            (0x8, _, _, 7) => OpCode::SUBN(x, y),
-            (0x8, _, _, 0xE) => OpCode::SHL(x, y),
-            (0x9, _, _, 0) => OpCode::SkipNotEqualXY(x, y),
-            (0xA, _, _, _) => OpCode::SetAddrReg(nnn),
-            (0xB, _, _, _) => OpCode::JumpPlusV0(nnn),
-            (0xC, _, _, _) => OpCode::RAND(x, lower_byte),
-            (0xD, _, _, _) => OpCode::DXYN(x, y, d),
-            // (0xE, _, 9, 0xE) => OpCode::SkipKeyPressed(x),
-            // (0xE, _, 0xA, 1) => OpCode::SkipKeyNotPressed(x),
-            // (0xF, _, 0, 7) => OpCode::SetVxToDelayTimer(x),
-            // (0xF, _, 0, 0xA) => OpCode::WaitForKeyPress(x),
-            // (0xF, _, 1, 5) => OpCode::SetDelayTimer(x),
-            // (0xF, _, 1, 8) => OpCode::SetSoundTimer(x),
-            (0xF, _, 1, 0xE) => OpCode::AddVxToI(x),
-            // (0xF, _, 2, 9) => OpCode::SetIToSprite(x),
-            (0xF, _, 3, 3) => OpCode::SaveBCD(x),
-            (0xF, _, 5, 5) => OpCode::StoreV0ToVx(x),
-            (0xF, _, 6, 5) => OpCode::ReadV0ToVx(x),
-            (_, _, _, _) => OpCode::UNFINISHED,
+    fn reset_vf(&mut self) {
+        if self.quirks.vf_reset {
+            self.v_registers[0xF] = 0;
         }
     }
+    fn decode(&mut self, instruction: u16) -> OpCode {
+        OpCode::decode(instruction)
+    }
     fn execute(&mut self, op_code: OpCode) {
         match op_code {
-            OpCode::CLR => self.screen.clear(),
+            OpCode::CLR => {
+                self.screen.clear();
+                self.store_display();
+            }
             OpCode::JMP(addr) => self.pc = addr,
             OpCode::ADD(v_x, kk) => self.v_registers[v_x as usize] = self.v_registers[v_x as usize].wrapping_add(kk),
             OpCode::SET(v_x, kk) => self.v_registers[v_x as usize] = kk,
@@ -124,29 +328,45 @@ impl Chip8 {
                 // set flag to 0
                 self.v_registers[0xF] = 0x0;
                 for row in 0..n {
-                    let spirte_byte_from_mem = self.ram.read_byte(self.i_reg + row as u16);
-                    self.v_registers[0xF] =
-                        self.screen.write_byte(v_x, v_y + row, spirte_byte_from_mem)
+                    let mut spirte_byte_from_mem = self.read_mem(self.i_reg.wrapping_add(row as u16));
+                    if self.quirks.clip_sprites {
+                        // the starting position still wraps, only the parts hanging off are dropped
+                        let (x, y) = (v_x % 64, v_y % 32);
+                        if y + row >= 32 {
+                            break;
+                        }
+                        spirte_byte_from_mem &= (0xFF00u16 >> (64 - x).min(8)) as u8;
+                        self.v_registers[0xF] |= self.screen.write_byte(x, y + row, spirte_byte_from_mem);
+                        continue;
+                    }
+                    // any row colliding sets it, a later row that doesn't mustn't clear it again
+                    self.v_registers[0xF] |=
+                        self.screen.write_byte(v_x, v_y.wrapping_add(row), spirte_byte_from_mem)
                 }
+                self.store_display();
             }
             OpCode::CALL(nnn) => {
+                if self.stack_pointer >= STACK_DEPTH {
+                    self.fault = Some(Fault::StackOverflow);
+                    return;
+                }
                 self.stack_pointer += 1;
                 self.address_stack.push(self.pc);
                 self.pc = nnn;
             }
             OpCode::SkipEqualNN(x, kk) => {
                 if self.v_registers[x as usize] == kk {
-                    self.pc += 2;
+                    self.skip();
                 }
             }
             OpCode::SkipNotEqualNN(x, kk) => {
                 if self.v_registers[x as usize] != kk {
-                    self.pc += 2;
+                    self.skip();
                 }
             }
             OpCode::SkipEqualXY(x, y) => {
                 if self.v_registers[x as usize] == self.v_registers[y as usize] {
-                    self.pc += 2;
+                    self.skip();
                 }
             }
             OpCode::LDXY(x, y) => {
@@ -154,12 +374,15 @@ impl Chip8 {
             }
             OpCode::BOR(x, y) => {
                 self.v_registers[x as usize] |= self.v_registers[y as usize];
+                self.reset_vf();
             }
             OpCode::BAND(x, y) => {
                 self.v_registers[x as usize] &= self.v_registers[y as usize];
+                self.reset_vf();
             }
             OpCode::BXOR(x, y) => {
                 self.v_registers[x as usize] ^= self.v_registers[y as usize];
+                self.reset_vf();
             }
             OpCode::AddXY(x, y) => {
                 let res = self.v_registers[x as usize] as u16 + self.v_registers[y as usize] as u16;
@@ -180,9 +403,9 @@ impl Chip8 {
                 self.v_registers[0x0f] = borrow;
             }
             OpCode::SHR(x, y) => {
-                let bit = self.v_registers[y as usize] & 0x1;
-                self.v_registers[x as usize] = self.v_registers[y as usize] >> 1;
-                self.v_registers[0xF] = bit;
+                let source = self.v_registers[if self.quirks.shift_vx { x } else { y } as usize];
+                self.v_registers[x as usize] = source >> 1;
+                self.v_registers[0xF] = source & 0x1;
             }
             OpCode::SUBN(x, y) => {
                 let x = x as usize;
@@ -192,46 +415,86 @@ impl Chip8 {
                 self.v_registers[0x0f] = borrow;
             }
             OpCode::SHL(x, y) => {
-                let bit = self.v_registers[y as usize] >> 7 & 0x1;; 
-                self.v_registers[x as usize] = self.v_registers[y as usize] << 1;
-                self.v_registers[0xF] = bit;
+                let source = self.v_registers[if self.quirks.shift_vx { x } else { y } as usize];
+                self.v_registers[x as usize] = source << 1;
+                self.v_registers[0xF] = source >> 7 & 0x1;
             }
             OpCode::SkipNotEqualXY(x, y) => {
                 if self.v_registers[x as usize] != self.v_registers[y as usize] {
-                    self.pc += 2;
+                    self.skip();
                 }
             }
             OpCode::JumpPlusV0(nnn) => {
-                self.pc = nnn + self.v_registers[0] as u16;
+                let x = if self.quirks.jump_vx { nnn >> 8 & 0xF } else { 0 };
+                self.pc = (nnn + self.v_registers[x as usize] as u16) & 0xFFF;
             }
             OpCode::RAND(x, kk) => {
-                let rand = rand::random::<u8>();
+                let rand = self.rng.gen::<u8>();
                 self.v_registers[x as usize] = rand & kk;
             },
-            OpCode::RET => {
-                self.stack_pointer -= 1;
-                self.pc = self.address_stack.pop().unwrap();
+            OpCode::RET => match self.address_stack.pop() {
+                Some(addr) => {
+                    self.stack_pointer -= 1;
+                    self.pc = addr;
+                }
+                None => self.fault = Some(Fault::StackUnderflow),
             },
             OpCode::AddVxToI(x) => {
-                self.i_reg += self.v_registers[x as usize] as u16;
+                self.i_reg = self.i_reg.wrapping_add(self.v_registers[x as usize] as u16);
             },
             OpCode::SaveBCD(x) => {
                 let val = self.v_registers[x as usize];
-                self.ram.write_byte(self.i_reg, val / 100);
-                self.ram.write_byte(self.i_reg + 1, (val / 10) % 10);
-                self.ram.write_byte(self.i_reg + 2, val % 10);
+                self.write_mem(self.i_reg, val / 100);
+                self.write_mem(self.i_reg.wrapping_add(1), (val / 10) % 10);
+                self.write_mem(self.i_reg.wrapping_add(2), val % 10);
             },
             OpCode::StoreV0ToVx(x) => {
                 for i in 0..=x {
-                    self.ram.write_byte(self.i_reg + i as u16, self.v_registers[i as usize]);
+                    self.write_mem(self.i_reg.wrapping_add(i as u16), self.v_registers[i as usize]);
+                }
+                if self.quirks.memory_increments_i {
+                    self.i_reg = self.i_reg.wrapping_add(x as u16 + 1);
                 }
             },
             OpCode::ReadV0ToVx(x) => {
                 for i in 0..=x {
-                    self.v_registers[i as usize] = self.ram.read_byte(self.i_reg + i as u16);
+                    self.v_registers[i as usize] = self.read_mem(self.i_reg.wrapping_add(i as u16));
+                }
+                if self.quirks.memory_increments_i {
+                    self.i_reg = self.i_reg.wrapping_add(x as u16 + 1);
+                }
+            },
+            OpCode::SkipKeyPressed(x) => {
+                if self.is_key_pressed(self.v_registers[x as usize]) {
+                    self.skip();
+                }
+            }
+            OpCode::SkipKeyNotPressed(x) => {
+                if !self.is_key_pressed(self.v_registers[x as usize]) {
+                    self.skip();
+                }
+            }
+            // like the VIP this waits for a key to go down and come back up, so holding
+            // a key doesn't get it read again by the next FX0A
+            OpCode::WaitForKeyPress(x) => match self.key_wait {
+                Some(key) if !self.keys[key as usize] => {
+                    self.v_registers[x as usize] = key;
+                    self.key_wait = None;
+                }
+                _ => {
+                    if self.key_wait.is_none() {
+                        self.key_wait = self.keys.iter().position(|&pressed| pressed).map(|key| key as u8);
+                    }
+                    // run this instruction again until the key is let go
+                    self.pc = self.pc.wrapping_sub(2) & 0xFFF;
                 }
             },
-            
+            OpCode::SetVxToDelayTimer(x) => self.v_registers[x as usize] = self.delay_timer.val,
+            OpCode::SetDelayTimer(x) => self.delay_timer.val = self.v_registers[x as usize],
+            OpCode::SetSoundTimer(x) => self.sound_timer.val = self.v_registers[x as usize],
+            OpCode::SetIToSprite(x) => {
+                self.i_reg = ram::FONT_START_ADDRESS + (self.v_registers[x as usize] & 0xF) as u16 * 5;
+            }
             OpCode::UNFINISHED => {}
         }
     }
@@ -243,22 +506,85 @@ impl Chip8 {
         println!("stack: {:?}", self.address_stack);
         println!("v registers: {:?}", self.v_registers);
     }
-    pub fn cycle(&mut self) {
-        let instruction = self.fetch();
-        println!("{:04x}", instruction);
-        let op_code = self.decode(instruction);
-        println!("{:?}", op_code);
-        self.execute(op_code)
-    }
-    fn run(&mut self) {
-        self.pc = 0x200;
-        for _ in 0..50 {
-            self.cycle();
+    // runs one instruction, returns why the debugger wants to stop if it does
+    pub fn cycle(&mut self) -> Option<BreakReason> {
+        if let Some(reason) = self.debugger.check_breakpoint(self.pc) {
+            return Some(reason);
+        }
+        let pc = self.pc;
+        let before = self.tracer.is_some().then(|| self.machine_state());
+        let (instruction, op_code) = if self.decode_cache {
+            let fetched = self.ram.instruction_at(self.pc);
+            self.skip();
+            fetched
+        } else {
+            let instruction = self.fetch();
+            (instruction, self.decode(instruction))
+        };
+        self.execute(op_code);
+        // leave pc on the instruction that faulted so it can be inspected
+        if let Some(fault) = self.fault.take() {
+            self.pc = pc;
+            return Some(BreakReason::Fault(fault));
+        }
+        if let Some(before) = before {
+            let after = self.machine_state();
+            if let Some(tracer) = self.tracer.as_mut() {
+                tracer.record(pc, instruction, &op_code, &before, &after);
+            }
+        }
+        if !self.debugger.is_active() {
+            return None;
         }
+        let state = self.machine_state();
+        self.debugger.check_after_step(&state)
+    }
+    // same as calling cycle `steps` times but runs whole compiled blocks where it can.
+    // blocks are built from the decode cache, so with that turned off every instruction
+    // goes through cycle. returns how many instructions ran and why it stopped early, if it did
+    pub fn run_steps(&mut self, steps: usize) -> (usize, Option<BreakReason>) {
+        let mut done = 0;
+        while done < steps {
+            // the debugger and tracer need to see every instruction
+            let block = if self.debugger.is_active() || self.tracer.is_some() || !self.decode_cache {
+                None
+            } else {
+                self.blocks.take(self.pc, &mut self.ram)
+            };
+            let block = match block {
+                Some(block) => block,
+                None => {
+                    if let Some(reason) = self.cycle() {
+                        return (done, Some(reason));
+                    }
+                    done += 1;
+                    continue;
+                }
+            };
+            let start = self.pc;
+            let mut stop = None;
+            for op in block.ops.iter().take(steps - done) {
+                let pc = self.pc;
+                self.skip();
+                self.execute(*op);
+                if let Some(fault) = self.fault.take() {
+                    self.pc = pc;
+                    stop = Some(BreakReason::Fault(fault));
+                    break;
+                }
+                done += 1;
+            }
+            self.blocks.put(start, block);
+            if stop.is_some() {
+                return (done, stop);
+            }
+        }
+        (done, None)
     }
 }
 
 #[cfg(test)]
+#[allow(clippy::bool_assert_comparison)]
 mod tests {
     use super::*;
 
@@ -460,19 +786,18 @@ mod tests {
         assert_eq!(cpu.v_registers[0], 255);
         assert_eq!(cpu.v_registers[0xF], 0x0);
     }
-    //TODO: update tests for new implementation
-/*     #[test]
+    #[test]
     fn execute_shr() {
         let mut cpu = Chip8::new();
-        cpu.v_registers[0] = 0b1011;
-        cpu.execute(OpCode::SHR(0));
+        cpu.v_registers[1] = 0b1011;
+        cpu.execute(OpCode::SHR(0, 1));
         assert_eq!(cpu.v_registers[0], 0b0101);
         assert_eq!(cpu.v_registers[0xF], 1);
-        cpu.v_registers[0] = 0b1010;
-        cpu.execute(OpCode::SHR(0));
+        cpu.v_registers[1] = 0b1010;
+        cpu.execute(OpCode::SHR(0, 1));
         assert_eq!(cpu.v_registers[0], 0b0101);
         assert_eq!(cpu.v_registers[0xF], 0);
-    } */
+    }
     #[test]
     fn execute_subn() {
         let mut cpu = Chip8::new();
@@ -480,26 +805,25 @@ mod tests {
         cpu.v_registers[1] = 0b11;
         cpu.execute(OpCode::SUBN(0, 1));
         assert_eq!(cpu.v_registers[0], 1);
-        assert_eq!(cpu.v_registers[0xF], 0);
+        assert_eq!(cpu.v_registers[0xF], 1);
         cpu.v_registers[0] = 0b11;
         cpu.v_registers[1] = 0b10;
         cpu.execute(OpCode::SUBN(0, 1));
         assert_eq!(cpu.v_registers[0], 255);
-        assert_eq!(cpu.v_registers[0xF], 1);
+        assert_eq!(cpu.v_registers[0xF], 0);
     }
-    //TODO: update tests for new implementation
-/*     #[test]
+    #[test]
     fn execute_shl() {
         let mut cpu = Chip8::new();
-        cpu.v_registers[0] = 0b10110000;
-        cpu.execute(OpCode::SHL(0));
+        cpu.v_registers[1] = 0b10110000;
+        cpu.execute(OpCode::SHL(0, 1));
         assert_eq!(cpu.v_registers[0], 0b01100000);
         assert_eq!(cpu.v_registers[0xF], 1);
-        cpu.v_registers[0] = 0b00110000;
-        cpu.execute(OpCode::SHL(0));
+        cpu.v_registers[1] = 0b00110000;
+        cpu.execute(OpCode::SHL(0, 1));
         assert_eq!(cpu.v_registers[0], 0b01100000);
         assert_eq!(cpu.v_registers[0xF], 0);
-    } */
+    }
     #[test]
     fn execute_sne_xy() {
         let mut cpu = Chip8::new();
@@ -526,6 +850,114 @@ mod tests {
         //assert_ne!(cpu.v_registers[0], 0);
     }
     #[test]
+    fn seeded_rand_repeats() {
+        let run = |seed| {
+            let mut cpu = Chip8::new();
+            cpu.set_seed(seed);
+            (0..8).map(|_| {
+                cpu.execute(OpCode::RAND(0, 0xFF));
+                cpu.v_registers[0]
+            }).collect::<Vec<u8>>()
+        };
+        assert_eq!(run(7), run(7));
+        assert_ne!(run(7), run(8));
+        // reset starts the same sequence over, seeded or not
+        let mut cpu = Chip8::new();
+        let draw = |cpu: &mut Chip8| {
+            cpu.execute(OpCode::RAND(0, 0xFF));
+            cpu.v_registers[0]
+        };
+        let first: Vec<u8> = (0..8).map(|_| draw(&mut cpu)).collect();
+        cpu.reset();
+        assert_eq!((0..8).map(|_| draw(&mut cpu)).collect::<Vec<u8>>(), first);
+        // the mask still applies
+        let mut cpu = Chip8::new();
+        cpu.execute(OpCode::RAND(0, 0x0F));
+        assert!(cpu.v_registers[0] <= 0x0F);
+    }
+    #[test]
+    fn quirks_change_shifts_jumps_and_logic() {
+        let mut cpu = Chip8::new();
+        cpu.set_quirks(quirks::Platform::Schip.quirks());
+        cpu.v_registers[0] = 0b1000_0011;
+        cpu.v_registers[1] = 0b0100_0000;
+        cpu.execute(OpCode::SHR(0, 1));
+        assert_eq!((cpu.v_registers[0], cpu.v_registers[0xF]), (0b0100_0001, 1));
+        cpu.execute(OpCode::SHL(0, 1));
+        assert_eq!((cpu.v_registers[0], cpu.v_registers[0xF]), (0b1000_0010, 0));
+        // B210 jumps to 210 + V2
+        cpu.v_registers[2] = 0x05;
+        cpu.execute(OpCode::JumpPlusV0(0x210));
+        assert_eq!(cpu.pc, 0x215);
+
+        let mut cpu = Chip8::new();
+        cpu.set_quirks(quirks::Platform::Cosmac.quirks());
+        cpu.v_registers[0xF] = 1;
+        cpu.execute(OpCode::BOR(0, 1));
+        assert_eq!(cpu.v_registers[0xF], 0);
+        cpu.i_reg = 0x300;
+        cpu.execute(OpCode::StoreV0ToVx(3));
+        assert_eq!(cpu.i_reg, 0x304);
+        cpu.execute(OpCode::ReadV0ToVx(0));
+        assert_eq!(cpu.i_reg, 0x305);
+    }
+    #[test]
+    fn clipped_sprites_stop_at_the_edges() {
+        let mut cpu = Chip8::new();
+        cpu.set_quirks(Quirks { clip_sprites: true, ..Quirks::default() });
+        cpu.i_reg = 0x300;
+        cpu.ram.write_byte(0x300, 0xFF);
+        cpu.ram.write_byte(0x301, 0xFF);
+        // two rows at (60, 31), and the start position itself wraps from (124, 63)
+        for (x, y) in [(60, 31), (124, 63)] {
+            cpu.v_registers[0] = x;
+            cpu.v_registers[1] = y;
+            cpu.execute(OpCode::DXYN(0, 1, 2));
+        }
+        assert_eq!(cpu.screen_rows()[31], 0);
+        assert!(cpu.screen_rows()[0] == 0 && cpu.screen_rows()[30] == 0);
+        assert_eq!(cpu.v_registers[0xF], 1);
+        cpu.v_registers[1] = 0;
+        cpu.execute(OpCode::DXYN(0, 1, 1));
+        assert_eq!(cpu.screen_rows()[0], 0xF);
+    }
+    #[test]
+    fn hybrid_display_lives_in_memory() {
+        let mut cpu = Chip8::new();
+        cpu.set_layout(quirks::Platform::HybridVip.layout()).unwrap();
+        // LD I, 0xF08 (row 1); LD V0, 0xAA; LD [I], V0; LD I, font 0; DRW V1, V1, 1
+        cpu.load_rom_bytes(&[0xAF, 0x08, 0x60, 0xAA, 0xF0, 0x55, 0xA0, 0x00, 0xD1, 0x11]).unwrap();
+        cpu.run_steps(3);
+        assert_eq!(cpu.screen_rows()[1], 0xAA << 56);
+        cpu.run_steps(2);
+        assert_eq!(cpu.ram.read_byte(0xF00), 0xF0);
+        assert_eq!(cpu.screen_rows()[0], 0xF0 << 56);
+        cpu.execute(OpCode::CLR);
+        assert_eq!((cpu.ram.read_byte(0xF00), cpu.ram.read_byte(0xF08)), (0, 0));
+        // other layouts leave the top of memory alone
+        let mut cpu = Chip8::new();
+        cpu.load_rom_bytes(&[0xAF, 0x08, 0x60, 0xAA, 0xF0, 0x55]).unwrap();
+        cpu.run_steps(3);
+        assert_eq!(cpu.screen_rows()[1], 0);
+        assert!(cpu.set_layout(Layout { display: Some(0xF01), ..Layout::default() }).is_err());
+    }
+    #[test]
+    fn collision_on_any_row_sets_vf() {
+        for clip_sprites in [false, true] {
+            let mut cpu = Chip8::new();
+            cpu.set_quirks(Quirks { clip_sprites, ..Quirks::default() });
+            cpu.i_reg = 0x300;
+            cpu.ram.write_byte(0x300, 0x80);
+            cpu.ram.write_byte(0x301, 0x80);
+            cpu.execute(OpCode::DXYN(0, 0, 1));
+            // the second row lands on empty screen, only the first collides
+            cpu.execute(OpCode::DXYN(0, 0, 2));
+            assert_eq!(cpu.v_registers[0xF], 1, "clip_sprites {}", clip_sprites);
+            assert_eq!(cpu.screen_rows()[0], 0);
+            assert_eq!(cpu.screen_rows()[1], 1 << 63);
+        }
+    }
+    #[test]
     fn execute_return() {
         let mut cpu = Chip8::new();
         cpu.stack_pointer = 1;
@@ -584,4 +1016,214 @@ mod tests {
         assert_eq!(cpu.v_registers[4], 0x14);
         assert_eq!(cpu.v_registers[5], 0x15);
     }
-}
\ No newline at end of file
+    #[test]
+    fn decode_cache_sees_self_modifying_code() {
+        let mut cpu = Chip8::new();
+        cpu.pc = 0x200;
+        // LD V0, 0x01 then JP 0x200
+        for (i, byte) in [0x60, 0x01, 0x12, 0x00].iter().enumerate() {
+            cpu.ram.write_byte(0x200 + i as u16, *byte);
+        }
+        cpu.cycle();
+        cpu.cycle();
+        assert_eq!(cpu.v_registers[0], 0x01);
+        // patch the low byte of the cached instruction
+        cpu.ram.write_byte(0x201, 0x02);
+        cpu.cycle();
+        assert_eq!(cpu.v_registers[0], 0x02);
+        // and the high byte, turning it into ADD V0, 0x02
+        cpu.pc = 0x200;
+        cpu.ram.write_byte(0x200, 0x70);
+        cpu.cycle();
+        assert_eq!(cpu.v_registers[0], 0x04);
+    }
+    #[test]
+    fn decode_cache_matches_uncached() {
+        let boot = |cached| {
+            let mut cpu = Chip8::new();
+            cpu.load_program("4-flags.ch8").unwrap();
+            cpu.pc = 0x200;
+            cpu.set_decode_cache(cached);
+            crate::difftest::record(&mut cpu, 1000)
+        };
+        assert_eq!(boot(true), boot(false));
+    }
+    #[test]
+    fn blocks_follow_the_decode_cache_setting() {
+        let mut cpu = Chip8::new();
+        // LD V0, 0x01 then JP 0x200
+        cpu.load_rom_bytes(&[0x60, 0x01, 0x12, 0x00]).unwrap();
+        cpu.set_decode_cache(false);
+        assert_eq!(cpu.run_steps(10), (10, None));
+        assert!(!cpu.blocks.contains(0x200));
+        cpu.set_decode_cache(true);
+        cpu.run_steps(10);
+        assert!(cpu.blocks.contains(0x200));
+    }
+    #[test]
+    fn oversized_roms_are_refused() {
+        let path = std::env::temp_dir().join(format!("chip8-large-{}.ch8", std::process::id()));
+        fs::write(&path, vec![0x12; 0x1000]).unwrap();
+        let mut cpu = Chip8::new();
+        let error = cpu.load_program(&path.to_string_lossy()).unwrap_err();
+        assert!(error.contains("4096 bytes is too large"), "{}", error);
+        assert_eq!(cpu.ram.read_byte(0x200), 0);
+        fs::write(&path, [0x00, 0xE0, 0x12]).unwrap();
+        let report = cpu.load_program(&path.to_string_lossy()).unwrap();
+        assert_eq!(report.problems, vec![crate::rom::Problem::OddLength]);
+        assert_eq!(cpu.ram.read_byte(0x202), 0x12);
+        fs::remove_file(&path).unwrap();
+        assert!(cpu.load_program(&path.to_string_lossy()).is_err());
+        assert!(cpu.set_layout(Layout { load_address: 0x1000, ..Layout::default() }).is_err());
+        assert!(cpu.set_layout(Layout { entry: 0xFFFF, ..Layout::default() }).is_err());
+        assert_eq!(cpu.layout(), Layout::default());
+    }
+    #[test]
+    fn ret_on_empty_stack_faults() {
+        let mut cpu = Chip8::new();
+        cpu.pc = 0x200;
+        cpu.ram.write_byte(0x200, 0x00);
+        cpu.ram.write_byte(0x201, 0xEE);
+        assert_eq!(cpu.cycle(), Some(BreakReason::Fault(Fault::StackUnderflow)));
+        assert_eq!(cpu.pc, 0x200);
+        assert_eq!(cpu.stack_pointer(), 0);
+    }
+    #[test]
+    fn call_past_stack_depth_faults() {
+        let mut cpu = Chip8::new();
+        cpu.pc = 0x200;
+        // CALL 0x200 forever
+        cpu.ram.write_byte(0x200, 0x22);
+        cpu.ram.write_byte(0x201, 0x00);
+        for _ in 0..16 {
+            assert_eq!(cpu.cycle(), None);
+        }
+        assert_eq!(cpu.cycle(), Some(BreakReason::Fault(Fault::StackOverflow)));
+        assert_eq!(cpu.stack_pointer(), 16);
+    }
+    #[test]
+    fn memory_wraps_at_4k() {
+        let mut cpu = Chip8::new();
+        cpu.v_registers[0] = 0xAA;
+        cpu.v_registers[1] = 0xBB;
+        cpu.i_reg = 0xFFF;
+        cpu.execute(OpCode::StoreV0ToVx(1));
+        assert_eq!(cpu.ram.read_byte(0xFFF), 0xAA);
+        assert_eq!(cpu.ram.read_byte(0x000), 0xBB);
+        cpu.v_registers[0] = 0xFF;
+        cpu.execute(OpCode::JumpPlusV0(0xFFF));
+        assert_eq!(cpu.pc, 0x0FE);
+    }
+    #[test]
+    fn execute_key_skips() {
+        let mut cpu = Chip8::new();
+        cpu.v_registers[0] = 0xA;
+        cpu.execute(OpCode::SkipKeyPressed(0));
+        assert_eq!(cpu.pc, 0);
+        cpu.execute(OpCode::SkipKeyNotPressed(0));
+        assert_eq!(cpu.pc, 2);
+        cpu.set_key(0xA, true);
+        cpu.execute(OpCode::SkipKeyPressed(0));
+        assert_eq!(cpu.pc, 4);
+        cpu.execute(OpCode::SkipKeyNotPressed(0));
+        assert_eq!(cpu.pc, 4);
+    }
+    #[test]
+    fn wait_for_key_waits_for_release() {
+        let mut cpu = Chip8::new();
+        cpu.pc = 0x200;
+        cpu.ram.write_byte(0x200, 0xF3);
+        cpu.ram.write_byte(0x201, 0x0A);
+        cpu.cycle();
+        assert_eq!(cpu.pc, 0x200);
+        // held down isn't enough, it has to come back up
+        cpu.set_key(0x7, true);
+        cpu.cycle();
+        cpu.cycle();
+        assert_eq!(cpu.pc, 0x200);
+        cpu.set_key(0x7, false);
+        cpu.cycle();
+        assert_eq!(cpu.pc, 0x202);
+        assert_eq!(cpu.v_registers[3], 0x7);
+    }
+    #[test]
+    fn execute_timers() {
+        let mut cpu = Chip8::new();
+        cpu.v_registers[0] = 2;
+        cpu.execute(OpCode::SetDelayTimer(0));
+        cpu.execute(OpCode::SetSoundTimer(0));
+        cpu.tick_timers();
+        cpu.execute(OpCode::SetVxToDelayTimer(1));
+        assert_eq!(cpu.v_registers[1], 1);
+        cpu.tick_timers();
+        cpu.tick_timers();
+        assert_eq!(cpu.delay_timer(), 0);
+        assert_eq!(cpu.sound_timer(), 0);
+    }
+    #[test]
+    fn execute_set_i_to_sprite() {
+        let mut cpu = Chip8::new();
+        cpu.v_registers[0] = 0xA;
+        cpu.execute(OpCode::SetIToSprite(0));
+        assert_eq!(cpu.i_reg, ram::FONT_START_ADDRESS + 50);
+        // the sprite for A starts 0xF0 0x90
+        assert_eq!(cpu.ram.read_byte(cpu.i_reg), 0xF0);
+        assert_eq!(cpu.ram.read_byte(cpu.i_reg + 1), 0x90);
+    }
+
+    mod properties {
+        use super::*;
+        use proptest::collection::vec;
+        use proptest::prelude::*;
+
+        proptest! {
+            // any single instruction from any machine state either runs or faults
+            #[test]
+            fn cycle_never_panics(
+                word in any::<u16>(),
+                pc in 0..0x1000u16,
+                i_reg in any::<u16>(),
+                v_registers in any::<[u8; 16]>(),
+                sp in 0..=STACK_DEPTH,
+                keys in any::<u16>(),
+            ) {
+                let mut cpu = Chip8::new();
+                cpu.pc = pc;
+                cpu.i_reg = i_reg;
+                cpu.v_registers = v_registers;
+                cpu.set_stack_pointer(sp);
+                for key in 0..16 {
+                    cpu.set_key(key, keys >> key & 1 == 1);
+                }
+                cpu.ram.write_byte(pc, (word >> 8) as u8);
+                cpu.ram.write_byte((pc + 1) & 0xFFF, word as u8);
+                let reason = cpu.cycle();
+                prop_assert!(matches!(reason, None | Some(BreakReason::Fault(_))));
+                prop_assert!(cpu.pc <= 0xFFF);
+                prop_assert!(cpu.stack_pointer() <= STACK_DEPTH);
+            }
+
+            #[test]
+            fn random_roms_never_panic(rom in vec(any::<u8>(), 0..0xE00), keys in vec(any::<u16>(), 1..32)) {
+                let mut cpu = Chip8::new();
+                cpu.pc = 0x200;
+                for (i, byte) in rom.iter().enumerate() {
+                    cpu.ram.write_byte(0x200 + i as u16, *byte);
+                }
+                for step in 0..1000 {
+                    let held = keys[step % keys.len()];
+                    for key in 0..16 {
+                        cpu.set_key(key, held >> key & 1 == 1);
+                    }
+                    if step % 8 == 0 {
+                        cpu.tick_timers();
+                    }
+                    // a faulted machine stays put, carry on from the next instruction
+                    if cpu.cycle().is_some() {
+                        cpu.pc = (cpu.pc + 2) & 0xFFF;
+                    }
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,141 @@
+// cached interpreter: straight-line runs of instructions are decoded once into a
+// block and executed back to back without going through fetch/decode per step
+use super::opcodes::OpCode;
+use super::ram::{EmulatedRam, PAGE_SIZE};
+
+// short enough that a block never covers more than two pages
+const MAX_BLOCK_LEN: usize = 16;
+
+pub struct Block {
+    pub ops: Vec<OpCode>,
+    // write counters of the first and last page the block was read from
+    first_page: (usize, u32),
+    last_page: (usize, u32),
+}
+impl Block {
+    fn is_valid(&self, ram: &EmulatedRam) -> bool {
+        ram.page_version(self.first_page.0) == self.first_page.1 && ram.page_version(self.last_page.0) == self.last_page.1
+    }
+}
+
+// instructions after which the next pc isn't simply the following address, or
+// which may have just rewritten the rest of the block
+fn ends_block(op: &OpCode) -> bool {
+    matches!(
+        op,
+        OpCode::JMP(_)
+            | OpCode::CALL(_)
+            | OpCode::RET
+            | OpCode::JumpPlusV0(_)
+            | OpCode::SkipEqualNN(..)
+            | OpCode::SkipNotEqualNN(..)
+            | OpCode::SkipEqualXY(..)
+            | OpCode::SkipNotEqualXY(..)
+            | OpCode::SkipKeyPressed(_)
+            | OpCode::SkipKeyNotPressed(_)
+            | OpCode::WaitForKeyPress(_)
+            | OpCode::SaveBCD(_)
+            | OpCode::StoreV0ToVx(_)
+    )
+}
+
+pub struct BlockCache {
+    blocks: Vec<Option<Block>>,
+}
+impl Default for BlockCache {
+    fn default() -> Self {
+        BlockCache::new()
+    }
+}
+impl BlockCache {
+    pub fn new() -> Self {
+        BlockCache { blocks: (0..0x1000).map(|_| None).collect() }
+    }
+
+    // takes the block starting at pc out of the cache, compiling it if there isn't a
+    // valid one. hand it back with put once it has run. instructions that wrap around
+    // the end of memory are left to the plain interpreter
+    pub fn take(&mut self, pc: u16, ram: &mut EmulatedRam) -> Option<Block> {
+        if pc >= 0xFFF {
+            return None;
+        }
+        match self.blocks[pc as usize].take() {
+            Some(block) if block.is_valid(ram) => Some(block),
+            _ => Some(compile(pc, ram)),
+        }
+    }
+    pub fn put(&mut self, pc: u16, block: Block) {
+        self.blocks[pc as usize] = Some(block);
+    }
+    #[cfg(test)]
+    pub fn contains(&self, pc: u16) -> bool {
+        self.blocks[pc as usize].is_some()
+    }
+}
+
+fn compile(start: u16, ram: &mut EmulatedRam) -> Block {
+    let mut ops = Vec::new();
+    let mut address = start;
+    loop {
+        let (_, op) = ram.instruction_at(address);
+        ops.push(op);
+        address += 2;
+        if ends_block(&op) || ops.len() == MAX_BLOCK_LEN || address >= 0xFFF {
+            break;
+        }
+    }
+    let first = start as usize / PAGE_SIZE;
+    let last = (address - 1) as usize / PAGE_SIZE;
+    Block {
+        ops,
+        first_page: (first, ram.page_version(first)),
+        last_page: (last, ram.page_version(last)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ram_with(program: &[u8]) -> EmulatedRam {
+        let mut ram = EmulatedRam::new();
+        for (i, byte) in program.iter().enumerate() {
+            ram.write_byte(0x200 + i as u16, *byte);
+        }
+        ram
+    }
+
+    #[test]
+    fn block_ends_at_branch() {
+        // LD V0, 1; ADD V0, 2; JP 0x200; CLS
+        let mut ram = ram_with(&[0x60, 0x01, 0x70, 0x02, 0x12, 0x00, 0x00, 0xE0]);
+        let mut cache = BlockCache::new();
+        let block = cache.take(0x200, &mut ram).unwrap();
+        assert_eq!(block.ops, vec![OpCode::SET(0, 1), OpCode::ADD(0, 2), OpCode::JMP(0x200)]);
+        // page 8 after the eight writes that loaded the program
+        assert_eq!(block.first_page, (8, 8));
+        assert_eq!(block.last_page, (8, 8));
+    }
+    #[test]
+    fn write_to_code_page_recompiles() {
+        let mut ram = ram_with(&[0x60, 0x01, 0x12, 0x00]);
+        let mut cache = BlockCache::new();
+        let block = cache.take(0x200, &mut ram).unwrap();
+        cache.put(0x200, block);
+        // far away pages don't matter
+        ram.write_byte(0x800, 0xFF);
+        assert!(cache.take(0x200, &mut ram).unwrap().is_valid(&ram));
+        ram.write_byte(0x201, 0x05);
+        let block = cache.take(0x200, &mut ram).unwrap();
+        assert_eq!(block.ops[0], OpCode::SET(0, 5));
+    }
+    #[test]
+    fn blocks_are_bounded() {
+        let mut ram = EmulatedRam::new();
+        let mut cache = BlockCache::new();
+        // all zeros decodes to UNFINISHED, which runs on
+        assert_eq!(cache.take(0x200, &mut ram).unwrap().ops.len(), MAX_BLOCK_LEN);
+        assert_eq!(cache.take(0xFFC, &mut ram).unwrap().ops.len(), 2);
+        assert!(cache.take(0xFFF, &mut ram).is_none());
+    }
+}
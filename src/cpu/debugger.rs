@@ -0,0 +1,373 @@
+use std::fmt;
+use std::str::FromStr;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Access {
+    Read,
+    Write,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum WatchKind {
+    Read,
+    Write,
+    ReadWrite,
+}
+impl WatchKind {
+    fn matches(&self, access: Access) -> bool {
+        matches!(
+            (self, access),
+            (WatchKind::ReadWrite, _) | (WatchKind::Read, Access::Read) | (WatchKind::Write, Access::Write)
+        )
+    }
+}
+
+// break when any address in start..=end is accessed
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Watchpoint {
+    pub start: u16,
+    pub end: u16,
+    pub kind: WatchKind,
+}
+
+// machine values a condition can look at
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Operand {
+    V(u8),
+    I,
+    PC,
+    SP,
+    DT,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Comparison {
+    Eq,
+    Ne,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+}
+impl Comparison {
+    fn compare(&self, lhs: u16, rhs: u16) -> bool {
+        match self {
+            Comparison::Eq => lhs == rhs,
+            Comparison::Ne => lhs != rhs,
+            Comparison::Lt => lhs < rhs,
+            Comparison::Le => lhs <= rhs,
+            Comparison::Gt => lhs > rhs,
+            Comparison::Ge => lhs >= rhs,
+        }
+    }
+    fn symbol(&self) -> &'static str {
+        match self {
+            Comparison::Eq => "==",
+            Comparison::Ne => "!=",
+            Comparison::Lt => "<",
+            Comparison::Le => "<=",
+            Comparison::Gt => ">",
+            Comparison::Ge => ">=",
+        }
+    }
+}
+
+// register condition such as `V3 == 0x10` or `I > 0xE00`
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Condition {
+    pub operand: Operand,
+    pub comparison: Comparison,
+    pub value: u16,
+}
+impl Condition {
+    pub fn evaluate(&self, state: &MachineState) -> bool {
+        let lhs = match self.operand {
+            Operand::V(x) => state.v_registers[x as usize] as u16,
+            Operand::I => state.i_reg,
+            Operand::PC => state.pc,
+            Operand::SP => state.stack_pointer as u16,
+            Operand::DT => state.delay_timer as u16,
+        };
+        self.comparison.compare(lhs, self.value)
+    }
+}
+impl fmt::Display for Condition {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let operand = match self.operand {
+            Operand::V(x) => format!("V{:X}", x),
+            Operand::I => "I".to_string(),
+            Operand::PC => "PC".to_string(),
+            Operand::SP => "SP".to_string(),
+            Operand::DT => "DT".to_string(),
+        };
+        write!(f, "{} {} {:#x}", operand, self.comparison.symbol(), self.value)
+    }
+}
+impl FromStr for Condition {
+    type Err = String;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        // longest symbols first so `<=` isn't read as `<`
+        let symbols = [
+            ("==", Comparison::Eq),
+            ("!=", Comparison::Ne),
+            ("<=", Comparison::Le),
+            (">=", Comparison::Ge),
+            ("<", Comparison::Lt),
+            (">", Comparison::Gt),
+        ];
+        let (index, symbol, comparison) = symbols
+            .iter()
+            .find_map(|(symbol, comparison)| s.find(symbol).map(|i| (i, *symbol, *comparison)))
+            .ok_or_else(|| format!("no comparison operator in condition `{}`", s))?;
+        let lhs = s[..index].trim().to_ascii_uppercase();
+        let rhs = s[index + symbol.len()..].trim();
+        let operand = match lhs.as_str() {
+            "I" => Operand::I,
+            "PC" => Operand::PC,
+            "SP" => Operand::SP,
+            "DT" => Operand::DT,
+            reg if reg.len() == 2 && reg.starts_with('V') => {
+                let x = u8::from_str_radix(&reg[1..], 16)
+                    .map_err(|_| format!("unknown register `{}`", reg))?;
+                Operand::V(x)
+            }
+            other => return Err(format!("unknown register `{}`", other)),
+        };
+        let value = parse_number(rhs).ok_or_else(|| format!("invalid value `{}`", rhs))?;
+        Ok(Condition { operand, comparison, value })
+    }
+}
+
+// accepts `0x1F`, `$1F` or plain decimal
+pub fn parse_number(s: &str) -> Option<u16> {
+    let s = s.trim();
+    if let Some(hex) = s.strip_prefix("0x").or_else(|| s.strip_prefix("0X")).or_else(|| s.strip_prefix('$')) {
+        u16::from_str_radix(hex, 16).ok()
+    } else {
+        s.parse().ok()
+    }
+}
+
+// snapshot of the registers a condition is evaluated against
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct MachineState {
+    pub v_registers: [u8; 16],
+    pub i_reg: u16,
+    pub pc: u16,
+    pub stack_pointer: u8,
+    pub delay_timer: u8,
+    pub sound_timer: u8,
+}
+
+// instructions the machine can't carry out
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Fault {
+    // CALL with all 16 stack slots in use
+    StackOverflow,
+    // RET with nothing to return to
+    StackUnderflow,
+}
+impl fmt::Display for Fault {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Fault::StackOverflow => write!(f, "stack overflow"),
+            Fault::StackUnderflow => write!(f, "stack underflow"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum BreakReason {
+    Breakpoint(u16),
+    Watchpoint { address: u16, access: Access, value: u8 },
+    Condition(Condition),
+    Fault(Fault),
+}
+impl fmt::Display for BreakReason {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            BreakReason::Breakpoint(pc) => write!(f, "breakpoint at {:#05x}", pc),
+            BreakReason::Watchpoint { address, access, value } => {
+                let verb = match access {
+                    Access::Read => "read",
+                    Access::Write => "write",
+                };
+                write!(f, "watchpoint: {} of {:#04x} at {:#05x}", verb, value, address)
+            }
+            BreakReason::Condition(condition) => write!(f, "condition `{}` became true", condition),
+            BreakReason::Fault(fault) => write!(f, "fault: {}", fault),
+        }
+    }
+}
+
+pub struct Debugger {
+    breakpoints: Vec<u16>,
+    watchpoints: Vec<Watchpoint>,
+    // each condition remembers whether it held after the previous instruction
+    conditions: Vec<(Condition, bool)>,
+    // set when we stop on a breakpoint so the next cycle can step past it
+    resume_pc: Option<u16>,
+    pending: Option<BreakReason>,
+}
+impl Default for Debugger {
+    fn default() -> Self {
+        Debugger::new()
+    }
+}
+impl Debugger {
+    pub fn new() -> Self {
+        Debugger {
+            breakpoints: Vec::new(),
+            watchpoints: Vec::new(),
+            conditions: Vec::new(),
+            resume_pc: None,
+            pending: None,
+        }
+    }
+    pub fn add_breakpoint(&mut self, address: u16) {
+        if !self.breakpoints.contains(&address) {
+            self.breakpoints.push(address);
+        }
+    }
+    pub fn remove_breakpoint(&mut self, address: u16) {
+        self.breakpoints.retain(|&bp| bp != address);
+    }
+    pub fn breakpoints(&self) -> &[u16] {
+        &self.breakpoints
+    }
+    pub fn add_watchpoint(&mut self, start: u16, end: u16, kind: WatchKind) {
+        self.watchpoints.push(Watchpoint { start, end, kind });
+    }
+    pub fn remove_watchpoint(&mut self, start: u16, end: u16) {
+        self.watchpoints.retain(|w| !(w.start == start && w.end == end));
+    }
+    pub fn watchpoints(&self) -> &[Watchpoint] {
+        &self.watchpoints
+    }
+    pub fn add_condition(&mut self, condition: Condition) {
+        self.conditions.push((condition, false));
+    }
+    pub fn clear(&mut self) {
+        self.breakpoints.clear();
+        self.watchpoints.clear();
+        self.conditions.clear();
+        self.resume_pc = None;
+        self.pending = None;
+    }
+    pub fn is_active(&self) -> bool {
+        !(self.breakpoints.is_empty() && self.watchpoints.is_empty() && self.conditions.is_empty())
+    }
+
+    // called before fetching the instruction at pc
+    pub fn check_breakpoint(&mut self, pc: u16) -> Option<BreakReason> {
+        if self.resume_pc.take() == Some(pc) {
+            return None;
+        }
+        if self.breakpoints.contains(&pc) {
+            self.resume_pc = Some(pc);
+            return Some(BreakReason::Breakpoint(pc));
+        }
+        None
+    }
+    // hook for every ram access made on behalf of an instruction
+    pub fn on_access(&mut self, address: u16, access: Access, value: u8) {
+        if self.pending.is_some() {
+            return;
+        }
+        let hit = self
+            .watchpoints
+            .iter()
+            .any(|w| w.kind.matches(access) && (w.start..=w.end).contains(&address));
+        if hit {
+            self.pending = Some(BreakReason::Watchpoint { address, access, value });
+        }
+    }
+    // called after an instruction has executed
+    pub fn check_after_step(&mut self, state: &MachineState) -> Option<BreakReason> {
+        let mut reason = self.pending.take();
+        for (condition, was_true) in self.conditions.iter_mut() {
+            let is_true = condition.evaluate(state);
+            if is_true && !*was_true && reason.is_none() {
+                reason = Some(BreakReason::Condition(*condition));
+            }
+            *was_true = is_true;
+        }
+        reason
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Chip8;
+
+    #[test]
+    fn condition_parse() {
+        let cond: Condition = "V3 == 0x10".parse().unwrap();
+        assert_eq!(cond.operand, Operand::V(3));
+        assert_eq!(cond.comparison, Comparison::Eq);
+        assert_eq!(cond.value, 0x10);
+        let cond: Condition = "i>=3584".parse().unwrap();
+        assert_eq!(cond.operand, Operand::I);
+        assert_eq!(cond.comparison, Comparison::Ge);
+        assert_eq!(cond.value, 0xE00);
+        assert!("VG == 1".parse::<Condition>().is_err());
+        assert!("V1 1".parse::<Condition>().is_err());
+    }
+    #[test]
+    fn breakpoint_stops_then_resumes() {
+        let mut chip8 = Chip8::new();
+        chip8.pc = 0x200;
+        chip8.ram.write_byte(0x200, 0x60);
+        chip8.ram.write_byte(0x201, 0x05);
+        chip8.debugger.add_breakpoint(0x200);
+        assert_eq!(chip8.cycle(), Some(BreakReason::Breakpoint(0x200)));
+        assert_eq!(chip8.pc, 0x200);
+        assert_eq!(chip8.cycle(), None);
+        assert_eq!(chip8.v_registers[0], 0x05);
+    }
+    #[test]
+    fn watchpoint_on_write() {
+        let mut chip8 = Chip8::new();
+        chip8.pc = 0x200;
+        // FX33 with V0 = 123, I = 0x300
+        chip8.ram.write_byte(0x200, 0xF0);
+        chip8.ram.write_byte(0x201, 0x33);
+        chip8.v_registers[0] = 123;
+        chip8.i_reg = 0x300;
+        chip8.debugger.add_watchpoint(0x301, 0x302, WatchKind::Write);
+        let reason = chip8.cycle();
+        assert_eq!(
+            reason,
+            Some(BreakReason::Watchpoint { address: 0x301, access: Access::Write, value: 2 })
+        );
+    }
+    #[test]
+    fn watchpoint_on_sprite_read() {
+        let mut chip8 = Chip8::new();
+        chip8.pc = 0x200;
+        chip8.ram.write_byte(0x200, 0xD0);
+        chip8.ram.write_byte(0x201, 0x11);
+        chip8.i_reg = 0x400;
+        chip8.debugger.add_watchpoint(0x400, 0x400, WatchKind::Write);
+        assert_eq!(chip8.cycle(), None);
+        chip8.pc = 0x200;
+        chip8.debugger.add_watchpoint(0x400, 0x400, WatchKind::Read);
+        assert!(matches!(chip8.cycle(), Some(BreakReason::Watchpoint { access: Access::Read, .. })));
+    }
+    #[test]
+    fn condition_triggers_on_edge() {
+        let mut chip8 = Chip8::new();
+        chip8.pc = 0x200;
+        // 7301 three times
+        for i in 0..3 {
+            chip8.ram.write_byte(0x200 + i * 2, 0x73);
+            chip8.ram.write_byte(0x201 + i * 2, 0x01);
+        }
+        chip8.v_registers[3] = 0x0F;
+        chip8.debugger.add_condition("V3 >= 0x10".parse().unwrap());
+        assert!(matches!(chip8.cycle(), Some(BreakReason::Condition(_))));
+        // still true, so no new break
+        assert_eq!(chip8.cycle(), None);
+    }
+}
@@ -1,4 +1,7 @@
-#[derive(Debug, PartialEq)]
+use std::fmt;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+#[allow(clippy::upper_case_acronyms)]
 pub enum OpCode {
     CLR,              //clear screen
     JMP(u16),         //1NNN jmp to NNN
@@ -23,9 +26,260 @@ pub enum OpCode {
     JumpPlusV0(u16),      //BNNN jump to NNN + V0
     RAND(u8, u8),     //CXNN set VX to random byte & NN
     DXYN(u8, u8, u8), //display/draw sprite at VX, VY with height N
+    SkipKeyPressed(u8),    //EX9E skip next instruction if key VX is pressed
+    SkipKeyNotPressed(u8), //EXA1 skip next instruction if key VX is not pressed
+    SetVxToDelayTimer(u8), //FX07 set VX to the delay timer
+    WaitForKeyPress(u8),   //FX0A wait for a key press and store it in VX
+    SetDelayTimer(u8),     //FX15 set the delay timer to VX
+    SetSoundTimer(u8),     //FX18 set the sound timer to VX
     AddVxToI(u8),         //FX1E add VX to I
+    SetIToSprite(u8),      //FX29 set I to the font sprite for digit VX
     SaveBCD(u8),      //FX33 store BCD representation of VX in memory locations I, I+1, I+2
     StoreV0ToVx(u8),      //FX55 save registers V0-VX to memory starting at I
     ReadV0ToVx(u8),      //FX65 load registers V0-VX from memory starting at I
     UNFINISHED,
 }
+impl OpCode {
+    pub fn decode(instruction: u16) -> OpCode {
+        let upper_byte = ((instruction & 0xFF00) >> 8) as u8;
+        let lower_byte = (instruction & 0x00FF) as u8;
+        let op = (upper_byte & 0xF0) >> 4;
+        let x = upper_byte & 0x0F;
+        let y = (lower_byte & 0xF0) >> 4;
+        let d = lower_byte & 0x0F;
+        let nnn = instruction & 0x0FFF;
+        match (op, x, y, d) {
+            (0, 0, 0xE, 0) => OpCode::CLR,
+            (0, 0, 0xE, 0xE) => OpCode::RET,
+            (0x1, _, _, _) => OpCode::JMP(nnn),
+            (0x2, _, _, _) => OpCode::CALL(nnn),
+            (0x3, _, _, _) => OpCode::SkipEqualNN(x, lower_byte),
+            (0x4, _, _, _) => OpCode::SkipNotEqualNN(x, lower_byte),
+            (0x5, _, _, 0) => OpCode::SkipEqualXY(x, y),
+            (0x6, _, _, _) => OpCode::SET(x, lower_byte),
+            (0x7, _, _, _) => OpCode::ADD(x, lower_byte),
+            (0x8, _, _, 0) => OpCode::LDXY(x, y),
+            (0x8, _, _, 1) => OpCode::BOR(x, y),
+            (0x8, _, _, 2) => OpCode::BAND(x, y),
+            (0x8, _, _, 3) => OpCode::BXOR(x, y),
+            (0x8, _, _, 4) => OpCode::AddXY(x, y),
+            (0x8, _, _, 5) => OpCode::SubXY(x, y),
+            (0x8, _, _, 6) => OpCode::SHR(x, y),
+            (0x8, _, _, 7) => OpCode::SUBN(x, y),
+            (0x8, _, _, 0xE) => OpCode::SHL(x, y),
+            (0x9, _, _, 0) => OpCode::SkipNotEqualXY(x, y),
+            (0xA, _, _, _) => OpCode::SetAddrReg(nnn),
+            (0xB, _, _, _) => OpCode::JumpPlusV0(nnn),
+            (0xC, _, _, _) => OpCode::RAND(x, lower_byte),
+            (0xD, _, _, _) => OpCode::DXYN(x, y, d),
+            (0xE, _, 9, 0xE) => OpCode::SkipKeyPressed(x),
+            (0xE, _, 0xA, 1) => OpCode::SkipKeyNotPressed(x),
+            (0xF, _, 0, 7) => OpCode::SetVxToDelayTimer(x),
+            (0xF, _, 0, 0xA) => OpCode::WaitForKeyPress(x),
+            (0xF, _, 1, 5) => OpCode::SetDelayTimer(x),
+            (0xF, _, 1, 8) => OpCode::SetSoundTimer(x),
+            (0xF, _, 1, 0xE) => OpCode::AddVxToI(x),
+            (0xF, _, 2, 9) => OpCode::SetIToSprite(x),
+            (0xF, _, 3, 3) => OpCode::SaveBCD(x),
+            (0xF, _, 5, 5) => OpCode::StoreV0ToVx(x),
+            (0xF, _, 6, 5) => OpCode::ReadV0ToVx(x),
+            (_, _, _, _) => OpCode::UNFINISHED,
+        }
+    }
+    // inverse of decode, UNFINISHED has no encoding of its own and gives 0x0000
+    pub fn encode(&self) -> u16 {
+        let xy = |op: u16, x: u8, y: u8, n: u16| op << 12 | (x as u16) << 8 | (y as u16) << 4 | n;
+        let xkk = |op: u16, x: u8, kk: u8| op << 12 | (x as u16) << 8 | kk as u16;
+        let fx = |x: u8, low: u16| 0xF000 | (x as u16) << 8 | low;
+        match *self {
+            OpCode::CLR => 0x00E0,
+            OpCode::RET => 0x00EE,
+            OpCode::JMP(nnn) => 0x1000 | nnn,
+            OpCode::CALL(nnn) => 0x2000 | nnn,
+            OpCode::SkipEqualNN(x, kk) => xkk(0x3, x, kk),
+            OpCode::SkipNotEqualNN(x, kk) => xkk(0x4, x, kk),
+            OpCode::SkipEqualXY(x, y) => xy(0x5, x, y, 0),
+            OpCode::SET(x, kk) => xkk(0x6, x, kk),
+            OpCode::ADD(x, kk) => xkk(0x7, x, kk),
+            OpCode::LDXY(x, y) => xy(0x8, x, y, 0),
+            OpCode::BOR(x, y) => xy(0x8, x, y, 1),
+            OpCode::BAND(x, y) => xy(0x8, x, y, 2),
+            OpCode::BXOR(x, y) => xy(0x8, x, y, 3),
+            OpCode::AddXY(x, y) => xy(0x8, x, y, 4),
+            OpCode::SubXY(x, y) => xy(0x8, x, y, 5),
+            OpCode::SHR(x, y) => xy(0x8, x, y, 6),
+            OpCode::SUBN(x, y) => xy(0x8, x, y, 7),
+            OpCode::SHL(x, y) => xy(0x8, x, y, 0xE),
+            OpCode::SkipNotEqualXY(x, y) => xy(0x9, x, y, 0),
+            OpCode::SetAddrReg(nnn) => 0xA000 | nnn,
+            OpCode::JumpPlusV0(nnn) => 0xB000 | nnn,
+            OpCode::RAND(x, kk) => xkk(0xC, x, kk),
+            OpCode::DXYN(x, y, n) => xy(0xD, x, y, n as u16),
+            OpCode::SkipKeyPressed(x) => xkk(0xE, x, 0x9E),
+            OpCode::SkipKeyNotPressed(x) => xkk(0xE, x, 0xA1),
+            OpCode::SetVxToDelayTimer(x) => fx(x, 0x07),
+            OpCode::WaitForKeyPress(x) => fx(x, 0x0A),
+            OpCode::SetDelayTimer(x) => fx(x, 0x15),
+            OpCode::SetSoundTimer(x) => fx(x, 0x18),
+            OpCode::AddVxToI(x) => fx(x, 0x1E),
+            OpCode::SetIToSprite(x) => fx(x, 0x29),
+            OpCode::SaveBCD(x) => fx(x, 0x33),
+            OpCode::StoreV0ToVx(x) => fx(x, 0x55),
+            OpCode::ReadV0ToVx(x) => fx(x, 0x65),
+            OpCode::UNFINISHED => 0x0000,
+        }
+    }
+    pub fn display(&self, syntax: Syntax) -> Mnemonic {
+        Mnemonic { op: *self, syntax }
+    }
+}
+
+// mnemonic styles an instruction can be printed in
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Syntax {
+    // `SE V3, 0x11`, the style of Cowgod's technical reference
+    Cowgod,
+    // `if v3 != 0x11 then`, the statements Octo compiles to the same word
+    Octo,
+}
+
+// an instruction paired with the syntax to print it in, see OpCode::display
+pub struct Mnemonic {
+    op: OpCode,
+    syntax: Syntax,
+}
+impl fmt::Display for Mnemonic {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.syntax {
+            Syntax::Cowgod => self.op.fmt(f),
+            Syntax::Octo => fmt_octo(&self.op, f),
+        }
+    }
+}
+
+// conventional (Cowgod) mnemonics
+impl fmt::Display for OpCode {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            OpCode::CLR => write!(f, "CLS"),
+            OpCode::RET => write!(f, "RET"),
+            OpCode::JMP(nnn) => write!(f, "JP 0x{:03X}", nnn),
+            OpCode::CALL(nnn) => write!(f, "CALL 0x{:03X}", nnn),
+            OpCode::SkipEqualNN(x, kk) => write!(f, "SE V{:X}, 0x{:02X}", x, kk),
+            OpCode::SkipNotEqualNN(x, kk) => write!(f, "SNE V{:X}, 0x{:02X}", x, kk),
+            OpCode::SkipEqualXY(x, y) => write!(f, "SE V{:X}, V{:X}", x, y),
+            OpCode::SET(x, kk) => write!(f, "LD V{:X}, 0x{:02X}", x, kk),
+            OpCode::ADD(x, kk) => write!(f, "ADD V{:X}, 0x{:02X}", x, kk),
+            OpCode::LDXY(x, y) => write!(f, "LD V{:X}, V{:X}", x, y),
+            OpCode::BOR(x, y) => write!(f, "OR V{:X}, V{:X}", x, y),
+            OpCode::BAND(x, y) => write!(f, "AND V{:X}, V{:X}", x, y),
+            OpCode::BXOR(x, y) => write!(f, "XOR V{:X}, V{:X}", x, y),
+            OpCode::AddXY(x, y) => write!(f, "ADD V{:X}, V{:X}", x, y),
+            OpCode::SubXY(x, y) => write!(f, "SUB V{:X}, V{:X}", x, y),
+            OpCode::SHR(x, y) => write!(f, "SHR V{:X}, V{:X}", x, y),
+            OpCode::SUBN(x, y) => write!(f, "SUBN V{:X}, V{:X}", x, y),
+            OpCode::SHL(x, y) => write!(f, "SHL V{:X}, V{:X}", x, y),
+            OpCode::SkipNotEqualXY(x, y) => write!(f, "SNE V{:X}, V{:X}", x, y),
+            OpCode::SetAddrReg(nnn) => write!(f, "LD I, 0x{:03X}", nnn),
+            OpCode::JumpPlusV0(nnn) => write!(f, "JP V0, 0x{:03X}", nnn),
+            OpCode::RAND(x, kk) => write!(f, "RND V{:X}, 0x{:02X}", x, kk),
+            OpCode::DXYN(x, y, n) => write!(f, "DRW V{:X}, V{:X}, {}", x, y, n),
+            OpCode::SkipKeyPressed(x) => write!(f, "SKP V{:X}", x),
+            OpCode::SkipKeyNotPressed(x) => write!(f, "SKNP V{:X}", x),
+            OpCode::SetVxToDelayTimer(x) => write!(f, "LD V{:X}, DT", x),
+            OpCode::WaitForKeyPress(x) => write!(f, "LD V{:X}, K", x),
+            OpCode::SetDelayTimer(x) => write!(f, "LD DT, V{:X}", x),
+            OpCode::SetSoundTimer(x) => write!(f, "LD ST, V{:X}", x),
+            OpCode::AddVxToI(x) => write!(f, "ADD I, V{:X}", x),
+            OpCode::SetIToSprite(x) => write!(f, "LD F, V{:X}", x),
+            OpCode::SaveBCD(x) => write!(f, "LD B, V{:X}", x),
+            OpCode::StoreV0ToVx(x) => write!(f, "LD [I], V{:X}", x),
+            OpCode::ReadV0ToVx(x) => write!(f, "LD V{:X}, [I]", x),
+            OpCode::UNFINISHED => write!(f, "???"),
+        }
+    }
+}
+
+// skips read inverted in octo, `if .. then` runs the next instruction when the test holds
+fn fmt_octo(op: &OpCode, f: &mut fmt::Formatter) -> fmt::Result {
+    match *op {
+        OpCode::CLR => write!(f, "clear"),
+        OpCode::RET => write!(f, "return"),
+        OpCode::JMP(nnn) => write!(f, "jump 0x{:03X}", nnn),
+        OpCode::CALL(nnn) => write!(f, ":call 0x{:03X}", nnn),
+        OpCode::SkipEqualNN(x, kk) => write!(f, "if v{:x} != 0x{:02X} then", x, kk),
+        OpCode::SkipNotEqualNN(x, kk) => write!(f, "if v{:x} == 0x{:02X} then", x, kk),
+        OpCode::SkipEqualXY(x, y) => write!(f, "if v{:x} != v{:x} then", x, y),
+        OpCode::SET(x, kk) => write!(f, "v{:x} := 0x{:02X}", x, kk),
+        OpCode::ADD(x, kk) => write!(f, "v{:x} += 0x{:02X}", x, kk),
+        OpCode::LDXY(x, y) => write!(f, "v{:x} := v{:x}", x, y),
+        OpCode::BOR(x, y) => write!(f, "v{:x} |= v{:x}", x, y),
+        OpCode::BAND(x, y) => write!(f, "v{:x} &= v{:x}", x, y),
+        OpCode::BXOR(x, y) => write!(f, "v{:x} ^= v{:x}", x, y),
+        OpCode::AddXY(x, y) => write!(f, "v{:x} += v{:x}", x, y),
+        OpCode::SubXY(x, y) => write!(f, "v{:x} -= v{:x}", x, y),
+        OpCode::SHR(x, y) => write!(f, "v{:x} >>= v{:x}", x, y),
+        OpCode::SUBN(x, y) => write!(f, "v{:x} =- v{:x}", x, y),
+        OpCode::SHL(x, y) => write!(f, "v{:x} <<= v{:x}", x, y),
+        OpCode::SkipNotEqualXY(x, y) => write!(f, "if v{:x} == v{:x} then", x, y),
+        OpCode::SetAddrReg(nnn) => write!(f, "i := 0x{:03X}", nnn),
+        OpCode::JumpPlusV0(nnn) => write!(f, "jump0 0x{:03X}", nnn),
+        OpCode::RAND(x, kk) => write!(f, "v{:x} := random 0x{:02X}", x, kk),
+        OpCode::DXYN(x, y, n) => write!(f, "sprite v{:x} v{:x} {}", x, y, n),
+        OpCode::SkipKeyPressed(x) => write!(f, "if v{:x} -key then", x),
+        OpCode::SkipKeyNotPressed(x) => write!(f, "if v{:x} key then", x),
+        OpCode::SetVxToDelayTimer(x) => write!(f, "v{:x} := delay", x),
+        OpCode::WaitForKeyPress(x) => write!(f, "v{:x} := key", x),
+        OpCode::SetDelayTimer(x) => write!(f, "delay := v{:x}", x),
+        OpCode::SetSoundTimer(x) => write!(f, "buzzer := v{:x}", x),
+        OpCode::AddVxToI(x) => write!(f, "i += v{:x}", x),
+        OpCode::SetIToSprite(x) => write!(f, "i := hex v{:x}", x),
+        OpCode::SaveBCD(x) => write!(f, "bcd v{:x}", x),
+        OpCode::StoreV0ToVx(x) => write!(f, "save v{:x}", x),
+        OpCode::ReadV0ToVx(x) => write!(f, "load v{:x}", x),
+        OpCode::UNFINISHED => write!(f, "???"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn decode_is_total() {
+        for word in 0..=u16::MAX {
+            let op = OpCode::decode(word);
+            assert!(!op.to_string().is_empty());
+            // these groups have no spare bits, every word in them is an instruction
+            if matches!(word >> 12, 0x1..=0x4 | 0x6 | 0x7 | 0xA..=0xD) {
+                assert_ne!(op, OpCode::UNFINISHED, "{:04X}", word);
+            }
+        }
+    }
+    #[test]
+    fn encode_inverts_decode_for_every_word() {
+        for word in 0..=u16::MAX {
+            let op = OpCode::decode(word);
+            if op != OpCode::UNFINISHED {
+                assert_eq!(op.encode(), word, "{:04X} decoded to {:?}", word, op);
+            }
+        }
+    }
+    #[test]
+    fn display_syntaxes() {
+        let op = OpCode::decode(0x3311);
+        assert_eq!(op.to_string(), "SE V3, 0x11");
+        assert_eq!(op.display(Syntax::Cowgod).to_string(), "SE V3, 0x11");
+        assert_eq!(op.display(Syntax::Octo).to_string(), "if v3 != 0x11 then");
+        assert_eq!(OpCode::SUBN(0xA, 2).display(Syntax::Octo).to_string(), "va =- v2");
+        assert_eq!(OpCode::SetIToSprite(4).display(Syntax::Octo).to_string(), "i := hex v4");
+    }
+    #[test]
+    fn decode_is_strict_about_spare_bits() {
+        assert_eq!(OpCode::decode(0x5120), OpCode::SkipEqualXY(1, 2));
+        assert_eq!(OpCode::decode(0x5121), OpCode::UNFINISHED);
+        assert_eq!(OpCode::decode(0x9121), OpCode::UNFINISHED);
+        assert_eq!(OpCode::decode(0x8128), OpCode::UNFINISHED);
+        assert_eq!(OpCode::decode(0xE19F), OpCode::UNFINISHED);
+    }
+}
@@ -0,0 +1,84 @@
+// behaviours that differ between the interpreters chip-8 programs were written for.
+// everything off is how this interpreter has always run
+use serde::{Deserialize, Serialize};
+use std::str::FromStr;
+
+#[derive(Debug, Clone, Copy, PartialEq, Default, Serialize, Deserialize)]
+pub struct Quirks {
+    // 8XY6/8XYE shift VX in place instead of shifting VY into VX
+    pub shift_vx: bool,
+    // FX55/FX65 leave I pointing just past the last register they touched
+    pub memory_increments_i: bool,
+    // BNNN jumps to NNN + VX with X the top nibble of NNN, instead of NNN + V0
+    pub jump_vx: bool,
+    // 8XY1/8XY2/8XY3 clear VF
+    pub vf_reset: bool,
+    // sprites are cut off at the edges of the screen instead of wrapping around
+    pub clip_sprites: bool,
+}
+
+// where in memory a program goes and where it starts running
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct Layout {
+    pub load_address: u16,
+    // the first instruction, the initial program counter
+    pub entry: u16,
+    // where the screen shows up in memory, one byte per 8 pixels a row at a time. the VIP
+    // kept it in the top 256 bytes and hybrid programs read and write it there directly
+    pub display: Option<u16>,
+}
+impl Default for Layout {
+    fn default() -> Self {
+        Layout { load_address: 0x200, entry: 0x200, display: None }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Platform {
+    // the original interpreter on the COSMAC VIP
+    Cosmac,
+    // SUPER-CHIP 1.1 on the HP 48
+    Schip,
+    // the ETI 660, a VIP interpreter that keeps programs from 0x600
+    Eti660,
+    // VIP programs that mix chip-8 with 1802 machine code and expect the interpreter's
+    // memory map. the screen is in memory like on the VIP, 0NNN routines still don't run
+    HybridVip,
+}
+impl Platform {
+    pub const ALL: [Platform; 4] = [Platform::Cosmac, Platform::Schip, Platform::Eti660, Platform::HybridVip];
+    pub fn name(&self) -> &'static str {
+        match self {
+            Platform::Cosmac => "cosmac",
+            Platform::Schip => "schip",
+            Platform::Eti660 => "eti660",
+            Platform::HybridVip => "hybrid",
+        }
+    }
+    pub fn quirks(&self) -> Quirks {
+        match self {
+            Platform::Cosmac => Quirks { memory_increments_i: true, vf_reset: true, clip_sprites: true, ..Quirks::default() },
+            Platform::Schip => Quirks { shift_vx: true, jump_vx: true, clip_sprites: true, ..Quirks::default() },
+            Platform::Eti660 | Platform::HybridVip => Platform::Cosmac.quirks(),
+        }
+    }
+    pub fn layout(&self) -> Layout {
+        match self {
+            Platform::Cosmac | Platform::Schip => Layout::default(),
+            Platform::Eti660 => Layout { load_address: 0x600, entry: 0x600, display: None },
+            Platform::HybridVip => Layout { display: Some(0xF00), ..Layout::default() },
+        }
+    }
+}
+impl FromStr for Platform {
+    type Err = String;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Platform::ALL
+            .into_iter()
+            .find(|platform| platform.name().eq_ignore_ascii_case(s))
+            .ok_or_else(|| {
+                let names: Vec<&str> = Platform::ALL.iter().map(Platform::name).collect();
+                format!("unknown platform `{}`, expected one of {}", s, names.join(", "))
+            })
+    }
+}
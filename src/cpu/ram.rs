@@ -1,5 +1,7 @@
-use std::fs;
-const FONT_START_ADDRESS: u16 = 0x00;
+use super::opcodes::OpCode;
+pub const FONT_START_ADDRESS: u16 = 0x00;
+// granularity the block cache tracks writes at
+pub const PAGE_SIZE: usize = 64;
 fn load_fonts() -> [u8; 80] {
     [
         0xF0, 0x90, 0x90, 0x90, 0xF0, // 0
@@ -21,17 +23,23 @@ fn load_fonts() -> [u8; 80] {
     ]
 }
 pub struct EmulatedRam {
-    pub data: [u8; 0x1000 as usize], // 4096 bytes of memory
+    // private so every write goes through write_byte and the decode cache stays valid
+    data: [u8; 0x1000], // 4096 bytes of memory
+    // word and decoded instruction starting at each address, filled in when first fetched
+    decoded: Vec<Option<(u16, OpCode)>>,
+    // bumped on every write to the page, compiled blocks compare against it
+    page_versions: [u32; 0x1000 / PAGE_SIZE],
 }
 impl EmulatedRam {
     pub fn new() -> Self {
-        let mut ram = EmulatedRam { data: [0; 0x1000] };
+        let mut ram = EmulatedRam {
+            data: [0; 0x1000],
+            decoded: vec![None; 0x1000],
+            page_versions: [0; 0x1000 / PAGE_SIZE],
+        };
         let fonts = load_fonts();
-        let mut address = FONT_START_ADDRESS;
-        for font in fonts.iter() {
-            // println!("{:#02x}", font);
-            ram.write_byte(address, *font);
-            address += 1
+        for (offset, font) in fonts.iter().enumerate() {
+            ram.write_byte(FONT_START_ADDRESS + offset as u16, *font);
         }
         ram
     }
@@ -44,22 +52,36 @@ impl EmulatedRam {
     }
     pub fn write_byte(&mut self, address: u16, value: u8) {
         self.data[address as usize] = value;
+        // the byte is the high half of the instruction here and the low half of the one before
+        self.decoded[address as usize] = None;
+        self.decoded[(address.wrapping_sub(1) & 0xFFF) as usize] = None;
+        let page = &mut self.page_versions[address as usize / PAGE_SIZE];
+        *page = page.wrapping_add(1);
     }
-
-    fn load_program(&mut self, data: Vec<u8>) {
-        let mut counter = 0x200;
-        for byte in data {
-            self.write_byte(counter, byte);
-            counter += 1;
+    pub fn page_version(&self, page: usize) -> u32 {
+        self.page_versions[page]
+    }
+    // the instruction starting at address, wrapping at the end of memory
+    pub fn instruction_at(&mut self, address: u16) -> (u16, OpCode) {
+        let address = address & 0xFFF;
+        if let Some(cached) = self.decoded[address as usize] {
+            return cached;
         }
+        let word = (self.data[address as usize] as u16) << 8 | self.data[((address + 1) & 0xFFF) as usize] as u16;
+        let decoded = (word, OpCode::decode(word));
+        self.decoded[address as usize] = Some(decoded);
+        decoded
     }
 
-    pub fn load_program_from_file(&mut self, file_path: &str) {
-        let contents = fs::read(file_path).expect("failed to open program from file");
-        self.load_program(contents);
+    // anything past the end of memory is dropped, roms are checked for size before this
+    pub fn load_program(&mut self, data: &[u8], load_address: u16) {
+        for (address, &byte) in (load_address..0x1000).zip(data) {
+            self.write_byte(address, byte);
+        }
     }
 }
 #[cfg(test)]
+#[allow(clippy::explicit_counter_loop)]
 mod test {
     use super::*;
     // Ram Tests
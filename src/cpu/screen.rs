@@ -1,48 +1,117 @@
+// area of the screen in pixels that changed since the frontend last looked
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Rect {
+    pub x: usize,
+    pub y: usize,
+    pub width: usize,
+    pub height: usize,
+}
+
+// one u64 per row, bit 63 is the leftmost pixel so sprite bytes line up with a shift
 pub struct EmulatedScreen {
-    pixels: [[bool; 64]; 32],
+    rows: [u64; 32],
+    // pixels that flipped since take_dirty_rects, same layout as rows
+    dirty: [u64; 32],
+    // what was on screen when it was last cleared, and how many clears there have been
+    before_clear: [u64; 32],
+    clears: u32,
 }
 impl EmulatedScreen {
     pub fn new() -> Self {
-        EmulatedScreen {
-            pixels: [[false; 64]; 32],
-        }
+        EmulatedScreen { rows: [0; 32], dirty: [0; 32], before_clear: [0; 32], clears: 0 }
     }
+    #[cfg(test)]
     pub fn put_pixel(&mut self, x: u8, y: u8, pix: bool) {
-        self.pixels[(y % 32) as usize][(x % 64) as usize] = pix;
+        let bit = 1u64 << (63 - x % 64);
+        let row = &mut self.rows[(y % 32) as usize];
+        if pix {
+            *row |= bit;
+        } else {
+            *row &= !bit;
+        }
     }
     pub fn get_pixel(&self, x: u8, y: u8) -> bool {
-        self.pixels[y as usize][x as usize]
+        self.rows[(y % 32) as usize] >> (63 - x % 64) & 1 == 1
     }
+    pub fn rows(&self) -> &[u64; 32] {
+        &self.rows
+    }
+    // unpacked copy for code that wants one bool per pixel
     pub fn get_screen(&self) -> [[bool; 64]; 32] {
-        self.pixels
+        let mut pixels = [[false; 64]; 32];
+        for (row, bits) in pixels.iter_mut().zip(self.rows.iter()) {
+            for (x, pixel) in row.iter_mut().enumerate() {
+                *pixel = bits >> (63 - x) & 1 == 1;
+            }
+        }
+        pixels
     }
+    // XORs a sprite row in, wrapping around both edges. returns 1 if a set pixel was erased
     pub fn write_byte(&mut self, x: u8, y_plus_row: u8, byte: u8) -> u8 {
-        let mut v_f: u8 = 0;
-        for col in 0..8 {
-            // shift to right and get last bit
-            let pixel = (byte >> (7 - col)) & 1;
-            // wrap around
-            let screen_x = ((x + col) % 64) as usize;
-            let screen_y = (y_plus_row % 32) as usize;
-            // set flag
-            if pixel == 1 {
-                if self.pixels[screen_y][screen_x] {
-                    v_f = 0x1;
-                }
-                self.pixels[screen_y][screen_x] ^= true;
-            }
+        let sprite = ((byte as u64) << 56).rotate_right((x % 64) as u32);
+        let row = &mut self.rows[(y_plus_row % 32) as usize];
+        let collision = *row & sprite != 0;
+        *row ^= sprite;
+        self.dirty[(y_plus_row % 32) as usize] |= sprite;
+        collision as u8
+    }
+    // replaces the 8 pixels from x = 8 * column, for a screen that's also in memory
+    pub fn set_byte(&mut self, column: u8, y: u8, byte: u8) {
+        let shift = 56 - 8 * (column % 8) as u32;
+        let row = &mut self.rows[(y % 32) as usize];
+        let before = *row;
+        *row = (*row & !(0xFFu64 << shift)) | (byte as u64) << shift;
+        self.dirty[(y % 32) as usize] |= before ^ *row;
+    }
+    // puts back a saved screen, what differs from the current one is dirty
+    pub fn restore(&mut self, rows: [u64; 32]) {
+        for ((dirty, row), saved) in self.dirty.iter_mut().zip(self.rows.iter()).zip(rows.iter()) {
+            *dirty |= row ^ saved;
         }
-        v_f
+        self.rows = rows;
     }
     pub fn clear(&mut self) {
-        for x in 0..64 {
-            for y in 0..32 {
-                self.pixels[y][x] = false;
+        for (dirty, row) in self.dirty.iter_mut().zip(self.rows.iter()) {
+            *dirty |= row;
+        }
+        self.before_clear = self.rows;
+        self.clears = self.clears.wrapping_add(1);
+        self.rows = [0; 32];
+    }
+    pub fn clears(&self) -> u32 {
+        self.clears
+    }
+    pub fn before_clear(&self) -> &[u64; 32] {
+        &self.before_clear
+    }
+    pub fn is_dirty(&self) -> bool {
+        self.dirty.iter().any(|&row| row != 0)
+    }
+    // changed areas as rectangles, one per run of changed pixels in a row with
+    // identical runs on neighbouring rows merged. resets the tracking
+    pub fn take_dirty_rects(&mut self) -> Vec<Rect> {
+        let mut rects: Vec<Rect> = Vec::new();
+        for (y, dirty) in self.dirty.iter_mut().enumerate() {
+            let first_of_row = rects.len();
+            let mut bits = std::mem::take(dirty);
+            while bits != 0 {
+                let x = bits.leading_zeros() as usize;
+                let width = (bits << x).leading_ones() as usize;
+                bits &= !(u64::MAX << (64 - width) >> x);
+                let above = rects[..first_of_row]
+                    .iter_mut()
+                    .find(|r| r.y + r.height == y && r.x == x && r.width == width);
+                match above {
+                    Some(rect) => rect.height += 1,
+                    None => rects.push(Rect { x, y, width, height: 1 }),
+                }
             }
         }
+        rects
     }
 }
 #[cfg(test)]
+#[allow(clippy::bool_assert_comparison, clippy::let_unit_value, unused_variables)]
 mod tests {
     use super::*;
     use crate::Chip8;
@@ -62,6 +131,56 @@ mod tests {
         }
     }
     #[test]
+    fn sprite_wraps_horizontally() {
+        let mut screen = EmulatedScreen::new();
+        assert_eq!(screen.write_byte(60, 0, 0xFF), 0);
+        for x in [60, 61, 62, 63, 0, 1, 2, 3] {
+            assert!(screen.get_pixel(x, 0));
+        }
+        assert!(!screen.get_pixel(4, 0));
+        assert_eq!(screen.rows()[0], 0xF000_0000_0000_000F);
+    }
+    #[test]
+    fn collision_only_when_a_pixel_is_erased() {
+        let mut screen = EmulatedScreen::new();
+        assert_eq!(screen.write_byte(0, 3, 0b1100_0000), 0);
+        assert_eq!(screen.write_byte(2, 3, 0b1100_0000), 0);
+        assert_eq!(screen.write_byte(1, 3, 0b1000_0000), 1);
+        assert!(!screen.get_pixel(1, 3));
+        screen.clear();
+        assert!(screen.rows().iter().all(|&row| row == 0));
+    }
+    #[test]
+    fn dirty_rects_cover_changes() {
+        let mut screen = EmulatedScreen::new();
+        assert!(!screen.is_dirty());
+        // 8x3 block at (4, 2) and a sprite wrapping off the right edge
+        for y in 2..5 {
+            screen.write_byte(4, y, 0xFF);
+        }
+        screen.write_byte(62, 10, 0b1111_0000);
+        // same span as the block but not touching it
+        screen.write_byte(4, 6, 0xFF);
+        assert!(screen.is_dirty());
+        let rects = screen.take_dirty_rects();
+        assert_eq!(
+            rects,
+            vec![
+                Rect { x: 4, y: 2, width: 8, height: 3 },
+                Rect { x: 4, y: 6, width: 8, height: 1 },
+                Rect { x: 0, y: 10, width: 2, height: 1 },
+                Rect { x: 62, y: 10, width: 2, height: 1 },
+            ]
+        );
+        assert!(!screen.is_dirty());
+        assert!(screen.take_dirty_rects().is_empty());
+        // clearing only marks what was lit
+        screen.clear();
+        assert_eq!(screen.take_dirty_rects().len(), 4);
+        screen.clear();
+        assert!(!screen.is_dirty());
+    }
+    #[test]
     fn screen_write_oob() {
         let mut screen = EmulatedScreen::new();
         let bad_res = screen.put_pixel(64, 0, true);
@@ -79,14 +198,14 @@ mod tests {
         let res = chip8.decode(0xD011);
         chip8.execute(res);
 
-        assert_eq!(chip8.screen.pixels[0][0], true);
-        assert_eq!(chip8.screen.pixels[0][1], true);
-        assert_eq!(chip8.screen.pixels[0][2], false);
-        assert_eq!(chip8.screen.pixels[0][3], true);
-        assert_eq!(chip8.screen.pixels[0][4], true);
-        assert_eq!(chip8.screen.pixels[0][5], false);
-        assert_eq!(chip8.screen.pixels[0][6], false);
-        assert_eq!(chip8.screen.pixels[0][7], true);
+        assert_eq!(chip8.screen.get_pixel(0, 0), true);
+        assert_eq!(chip8.screen.get_pixel(1, 0), true);
+        assert_eq!(chip8.screen.get_pixel(2, 0), false);
+        assert_eq!(chip8.screen.get_pixel(3, 0), true);
+        assert_eq!(chip8.screen.get_pixel(4, 0), true);
+        assert_eq!(chip8.screen.get_pixel(5, 0), false);
+        assert_eq!(chip8.screen.get_pixel(6, 0), false);
+        assert_eq!(chip8.screen.get_pixel(7, 0), true);
     }
     #[test]
     fn execute_display_two_rows() {
@@ -100,23 +219,23 @@ mod tests {
         let res = chip8.decode(0xD012);
         chip8.execute(res);
 
-        assert_eq!(chip8.screen.pixels[0][0], true);
-        assert_eq!(chip8.screen.pixels[0][1], true);
-        assert_eq!(chip8.screen.pixels[0][2], false);
-        assert_eq!(chip8.screen.pixels[0][3], true);
-        assert_eq!(chip8.screen.pixels[0][4], true);
-        assert_eq!(chip8.screen.pixels[0][5], false);
-        assert_eq!(chip8.screen.pixels[0][6], false);
-        assert_eq!(chip8.screen.pixels[0][7], true);
+        assert_eq!(chip8.screen.get_pixel(0, 0), true);
+        assert_eq!(chip8.screen.get_pixel(1, 0), true);
+        assert_eq!(chip8.screen.get_pixel(2, 0), false);
+        assert_eq!(chip8.screen.get_pixel(3, 0), true);
+        assert_eq!(chip8.screen.get_pixel(4, 0), true);
+        assert_eq!(chip8.screen.get_pixel(5, 0), false);
+        assert_eq!(chip8.screen.get_pixel(6, 0), false);
+        assert_eq!(chip8.screen.get_pixel(7, 0), true);
 
-        assert_eq!(chip8.screen.pixels[1][0], true);
-        assert_eq!(chip8.screen.pixels[1][1], false);
-        assert_eq!(chip8.screen.pixels[1][2], true);
-        assert_eq!(chip8.screen.pixels[1][3], false);
-        assert_eq!(chip8.screen.pixels[1][4], true);
-        assert_eq!(chip8.screen.pixels[1][5], false);
-        assert_eq!(chip8.screen.pixels[1][6], true);
-        assert_eq!(chip8.screen.pixels[1][7], false);
+        assert_eq!(chip8.screen.get_pixel(0, 1), true);
+        assert_eq!(chip8.screen.get_pixel(1, 1), false);
+        assert_eq!(chip8.screen.get_pixel(2, 1), true);
+        assert_eq!(chip8.screen.get_pixel(3, 1), false);
+        assert_eq!(chip8.screen.get_pixel(4, 1), true);
+        assert_eq!(chip8.screen.get_pixel(5, 1), false);
+        assert_eq!(chip8.screen.get_pixel(6, 1), true);
+        assert_eq!(chip8.screen.get_pixel(7, 1), false);
     }
     #[test]
     fn execute_display_offset() {
@@ -130,22 +249,22 @@ mod tests {
         let res = chip8.decode(0xD012);
         chip8.execute(res);
 
-        assert_eq!(chip8.screen.pixels[5][1], true);
-        assert_eq!(chip8.screen.pixels[5][2], true);
-        assert_eq!(chip8.screen.pixels[5][3], false);
-        assert_eq!(chip8.screen.pixels[5][4], true);
-        assert_eq!(chip8.screen.pixels[5][5], true);
-        assert_eq!(chip8.screen.pixels[5][6], false);
-        assert_eq!(chip8.screen.pixels[5][7], false);
-        assert_eq!(chip8.screen.pixels[5][8], true);
+        assert_eq!(chip8.screen.get_pixel(1, 5), true);
+        assert_eq!(chip8.screen.get_pixel(2, 5), true);
+        assert_eq!(chip8.screen.get_pixel(3, 5), false);
+        assert_eq!(chip8.screen.get_pixel(4, 5), true);
+        assert_eq!(chip8.screen.get_pixel(5, 5), true);
+        assert_eq!(chip8.screen.get_pixel(6, 5), false);
+        assert_eq!(chip8.screen.get_pixel(7, 5), false);
+        assert_eq!(chip8.screen.get_pixel(8, 5), true);
 
-        assert_eq!(chip8.screen.pixels[6][1], true);
-        assert_eq!(chip8.screen.pixels[6][2], false);
-        assert_eq!(chip8.screen.pixels[6][3], true);
-        assert_eq!(chip8.screen.pixels[6][4], false);
-        assert_eq!(chip8.screen.pixels[6][5], true);
-        assert_eq!(chip8.screen.pixels[6][6], false);
-        assert_eq!(chip8.screen.pixels[6][7], true);
-        assert_eq!(chip8.screen.pixels[6][8], false);
+        assert_eq!(chip8.screen.get_pixel(1, 6), true);
+        assert_eq!(chip8.screen.get_pixel(2, 6), false);
+        assert_eq!(chip8.screen.get_pixel(3, 6), true);
+        assert_eq!(chip8.screen.get_pixel(4, 6), false);
+        assert_eq!(chip8.screen.get_pixel(5, 6), true);
+        assert_eq!(chip8.screen.get_pixel(6, 6), false);
+        assert_eq!(chip8.screen.get_pixel(7, 6), true);
+        assert_eq!(chip8.screen.get_pixel(8, 6), false);
     }
 }
@@ -0,0 +1,152 @@
+// snapshots of the whole machine that can be written to a file and picked up again later.
+// the layout, quirks and rom go in too so a restored machine resets the way the saved one
+// would have
+use super::blocks::BlockCache;
+use super::quirks::{Layout, Quirks};
+use super::ram::EmulatedRam;
+use super::Chip8;
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::path::Path;
+
+// bumped whenever the fields change so old files are refused rather than misread
+const VERSION: u32 = 1;
+
+#[derive(Serialize, Deserialize)]
+struct SaveState {
+    version: u32,
+    pc: u16,
+    i_reg: u16,
+    stack: Vec<u16>,
+    v_registers: [u8; 16],
+    delay_timer: u8,
+    sound_timer: u8,
+    screen: [u64; 32],
+    ram: Vec<u8>,
+    keys: [bool; 16],
+    // the key an FX0A is waiting to see let go
+    key_wait: Option<u8>,
+    quirks: Quirks,
+    layout: Layout,
+    rom: Vec<u8>,
+    // what reset starts CXNN from, and where the numbers carry on from now
+    seed: u64,
+    next_seed: u64,
+}
+
+impl Chip8 {
+    // the random number generator can't be saved part way through, so it's re-seeded
+    // from itself here and the saved machine and this one both carry on from that seed
+    pub fn save_state(&mut self) -> Vec<u8> {
+        let next_seed = self.rng.gen();
+        self.rng = StdRng::seed_from_u64(next_seed);
+        let state = SaveState {
+            version: VERSION,
+            pc: self.pc,
+            i_reg: self.i_reg,
+            stack: self.address_stack.clone(),
+            v_registers: self.v_registers,
+            delay_timer: self.delay_timer.val,
+            sound_timer: self.sound_timer.val,
+            screen: *self.screen.rows(),
+            ram: (0..0x1000).map(|address| self.ram.read_byte(address)).collect(),
+            keys: self.keys,
+            key_wait: self.key_wait,
+            quirks: self.quirks,
+            layout: self.layout,
+            rom: self.rom.clone(),
+            seed: self.seed,
+            next_seed,
+        };
+        serde_json::to_vec(&state).expect("save states always serialize")
+    }
+    // breakpoints, the tracer and the decode cache setting stay as they are
+    pub fn load_state(&mut self, bytes: &[u8]) -> Result<(), String> {
+        let state: SaveState = serde_json::from_slice(bytes).map_err(|e| format!("not a save state: {}", e))?;
+        if state.version != VERSION {
+            return Err(format!("save state version {} isn't supported, expected {}", state.version, VERSION));
+        }
+        if state.ram.len() != 0x1000 || state.stack.len() > super::STACK_DEPTH as usize || state.pc > 0xFFF || state.key_wait.is_some_and(|key| key > 0xF) {
+            return Err("save state is damaged".to_string());
+        }
+        self.set_layout(state.layout)?;
+        self.pc = state.pc;
+        self.i_reg = state.i_reg;
+        self.stack_pointer = state.stack.len() as u8;
+        self.address_stack = state.stack;
+        self.v_registers = state.v_registers;
+        self.delay_timer.val = state.delay_timer;
+        self.sound_timer.val = state.sound_timer;
+        self.screen.restore(state.screen);
+        self.ram = EmulatedRam::new();
+        self.ram.load_program(&state.ram, 0);
+        self.blocks = BlockCache::new();
+        self.keys = state.keys;
+        self.key_wait = state.key_wait;
+        self.quirks = state.quirks;
+        self.rom = state.rom;
+        self.seed = state.seed;
+        self.rng = StdRng::seed_from_u64(state.next_seed);
+        self.fault = None;
+        Ok(())
+    }
+    pub fn save_state_to(&mut self, path: &Path) -> Result<(), String> {
+        fs::write(path, self.save_state()).map_err(|e| format!("{}: {}", path.display(), e))
+    }
+    pub fn load_state_from(&mut self, path: &Path) -> Result<(), String> {
+        let bytes = fs::read(path).map_err(|e| format!("{}: {}", path.display(), e))?;
+        self.load_state(&bytes).map_err(|e| format!("{}: {}", path.display(), e))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cpu::quirks::Platform;
+
+    #[test]
+    fn restored_machines_carry_on_the_same() {
+        let mut chip8 = Chip8::new();
+        chip8.set_layout(Platform::Eti660.layout()).unwrap();
+        chip8.set_quirks(Platform::Eti660.quirks());
+        // draw a random sprite byte at a random place, forever
+        let rom = [0xC0, 0xFF, 0xC1, 0xFF, 0xA6, 0x10, 0xF0, 0x55, 0xD0, 0x11, 0x16, 0x00, 0, 0, 0, 0, 0];
+        chip8.load_rom_bytes(&rom).unwrap();
+        chip8.run_steps(100);
+        let state = chip8.save_state();
+        let mut restored = Chip8::new();
+        restored.load_state(&state).unwrap();
+        chip8.run_steps(100);
+        restored.run_steps(100);
+        assert_eq!(restored.screen_rows(), chip8.screen_rows());
+        assert_eq!(restored.machine_state(), chip8.machine_state());
+        // and reset to the same rom, layout and first random numbers
+        chip8.reset();
+        restored.reset();
+        assert_eq!(restored.layout().entry, 0x600);
+        chip8.run_steps(10);
+        restored.run_steps(10);
+        assert_eq!(restored.machine_state(), chip8.machine_state());
+        assert!(restored.load_state(b"{}").is_err());
+        assert!(restored.load_state(&state[..state.len() / 2]).is_err());
+    }
+    #[test]
+    fn key_waits_are_kept_and_reset() {
+        let mut chip8 = Chip8::new();
+        // FX0A into V3
+        chip8.load_rom_bytes(&[0xF3, 0x0A]).unwrap();
+        chip8.set_key(7, true);
+        chip8.cycle();
+        let mut restored = Chip8::new();
+        restored.load_state(&chip8.save_state()).unwrap();
+        restored.set_key(7, false);
+        restored.cycle();
+        assert_eq!((restored.pc, restored.v_registers[3]), (0x202, 7));
+        // reset forgets the key went down
+        chip8.reset();
+        chip8.cycle();
+        assert_eq!(chip8.pc, 0x200);
+    }
+}
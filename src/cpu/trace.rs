@@ -0,0 +1,242 @@
+use super::debugger::MachineState;
+use super::opcodes::OpCode;
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::str::FromStr;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum TraceFormat {
+    // `0200  6A02  LD VA, 0x02  VA:00->02`, only what the instruction changed
+    Delta,
+    // full machine state before each instruction on one line. the layout is this
+    // emulator's own, meant for diffing two runs of it line for line rather than
+    // matching any other emulator's logs
+    State,
+}
+impl FromStr for TraceFormat {
+    type Err = String;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "delta" => Ok(TraceFormat::Delta),
+            "state" => Ok(TraceFormat::State),
+            _ => Err(format!("unknown trace format `{}`, expected delta or state", s)),
+        }
+    }
+}
+
+// groups of instructions a trace can be limited to
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum OpClass {
+    Flow,
+    Skip,
+    Arithmetic,
+    Memory,
+    Draw,
+    Input,
+    Timer,
+}
+impl OpClass {
+    pub fn of(op: &OpCode) -> Option<OpClass> {
+        let class = match op {
+            OpCode::JMP(_) | OpCode::CALL(_) | OpCode::RET | OpCode::JumpPlusV0(_) => OpClass::Flow,
+            OpCode::SkipEqualNN(..) | OpCode::SkipNotEqualNN(..) | OpCode::SkipEqualXY(..) | OpCode::SkipNotEqualXY(..) => {
+                OpClass::Skip
+            }
+            OpCode::SET(..)
+            | OpCode::ADD(..)
+            | OpCode::LDXY(..)
+            | OpCode::BOR(..)
+            | OpCode::BAND(..)
+            | OpCode::BXOR(..)
+            | OpCode::AddXY(..)
+            | OpCode::SubXY(..)
+            | OpCode::SHR(..)
+            | OpCode::SUBN(..)
+            | OpCode::SHL(..)
+            | OpCode::RAND(..) => OpClass::Arithmetic,
+            OpCode::SetAddrReg(_)
+            | OpCode::AddVxToI(_)
+            | OpCode::SetIToSprite(_)
+            | OpCode::SaveBCD(_)
+            | OpCode::StoreV0ToVx(_)
+            | OpCode::ReadV0ToVx(_) => OpClass::Memory,
+            OpCode::CLR | OpCode::DXYN(..) => OpClass::Draw,
+            OpCode::SkipKeyPressed(_) | OpCode::SkipKeyNotPressed(_) | OpCode::WaitForKeyPress(_) => OpClass::Input,
+            OpCode::SetVxToDelayTimer(_) | OpCode::SetDelayTimer(_) | OpCode::SetSoundTimer(_) => OpClass::Timer,
+            OpCode::UNFINISHED => return None,
+        };
+        Some(class)
+    }
+}
+impl FromStr for OpClass {
+    type Err = String;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "flow" => Ok(OpClass::Flow),
+            "skip" => Ok(OpClass::Skip),
+            "arithmetic" => Ok(OpClass::Arithmetic),
+            "memory" => Ok(OpClass::Memory),
+            "draw" => Ok(OpClass::Draw),
+            "input" => Ok(OpClass::Input),
+            "timer" => Ok(OpClass::Timer),
+            _ => Err(format!("unknown opcode class `{}`", s)),
+        }
+    }
+}
+
+pub struct Tracer {
+    out: Box<dyn Write>,
+    format: TraceFormat,
+    // only trace instructions whose address falls in start..=end
+    pub address_range: Option<(u16, u16)>,
+    // empty means every class is traced
+    pub classes: Vec<OpClass>,
+    failed: bool,
+}
+impl Tracer {
+    pub fn new(out: Box<dyn Write>, format: TraceFormat) -> Self {
+        Tracer { out, format, address_range: None, classes: Vec::new(), failed: false }
+    }
+    pub fn to_file(path: &str, format: TraceFormat) -> io::Result<Self> {
+        let file = File::create(path)?;
+        Ok(Tracer::new(Box::new(BufWriter::new(file)), format))
+    }
+
+    fn wants(&self, pc: u16, op: &OpCode) -> bool {
+        let in_range = self.address_range.is_none_or(|(start, end)| (start..=end).contains(&pc));
+        let in_class = self.classes.is_empty() || OpClass::of(op).is_some_and(|class| self.classes.contains(&class));
+        in_range && in_class
+    }
+
+    pub fn record(&mut self, pc: u16, word: u16, op: &OpCode, before: &MachineState, after: &MachineState) {
+        if self.failed || !self.wants(pc, op) {
+            return;
+        }
+        let line = match self.format {
+            TraceFormat::Delta => format_delta(pc, word, op, before, after),
+            TraceFormat::State => format_state(word, before),
+        };
+        // a broken trace file shouldn't stop the program, report it once and stop tracing
+        if let Err(e) = writeln!(self.out, "{}", line) {
+            eprintln!("trace: {}, tracing disabled", e);
+            self.failed = true;
+        }
+    }
+    pub fn flush(&mut self) -> io::Result<()> {
+        self.out.flush()
+    }
+}
+impl Drop for Tracer {
+    fn drop(&mut self) {
+        let _ = self.out.flush();
+    }
+}
+
+pub fn format_delta(pc: u16, word: u16, op: &OpCode, before: &MachineState, after: &MachineState) -> String {
+    let mut line = format!("{:04X}  {:04X}  {:<16}", pc, word, op.to_string());
+    for (x, (old, new)) in before.v_registers.iter().zip(after.v_registers.iter()).enumerate() {
+        if old != new {
+            line.push_str(&format!(" V{:X}:{:02X}->{:02X}", x, old, new));
+        }
+    }
+    if before.i_reg != after.i_reg {
+        line.push_str(&format!(" I:{:04X}->{:04X}", before.i_reg, after.i_reg));
+    }
+    if before.stack_pointer != after.stack_pointer {
+        line.push_str(&format!(" SP:{:X}->{:X}", before.stack_pointer, after.stack_pointer));
+    }
+    if before.delay_timer != after.delay_timer {
+        line.push_str(&format!(" DT:{:02X}->{:02X}", before.delay_timer, after.delay_timer));
+    }
+    if before.sound_timer != after.sound_timer {
+        line.push_str(&format!(" ST:{:02X}->{:02X}", before.sound_timer, after.sound_timer));
+    }
+    line.trim_end().to_string()
+}
+
+pub fn format_state(word: u16, state: &MachineState) -> String {
+    let v: Vec<String> = state.v_registers.iter().map(|v| format!("{:02X}", v)).collect();
+    format!(
+        "PC:{:04X} OP:{:04X} I:{:04X} SP:{:02X} DT:{:02X} ST:{:02X} V:{}",
+        state.pc,
+        word,
+        state.i_reg,
+        state.stack_pointer,
+        state.delay_timer,
+        state.sound_timer,
+        v.join(" ")
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Chip8;
+    use std::cell::RefCell;
+    use std::rc::Rc;
+
+    // writer the test can still read after handing it to the tracer
+    #[derive(Clone, Default)]
+    struct SharedBuffer(Rc<RefCell<Vec<u8>>>);
+    impl Write for SharedBuffer {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0.borrow_mut().extend_from_slice(buf);
+            Ok(buf.len())
+        }
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    fn run(program: &[u8], tracer: impl FnOnce(Box<dyn Write>) -> Tracer) -> String {
+        let buffer = SharedBuffer::default();
+        let mut chip8 = Chip8::new();
+        chip8.pc = 0x200;
+        for (i, byte) in program.iter().enumerate() {
+            chip8.ram.write_byte(0x200 + i as u16, *byte);
+        }
+        chip8.set_tracer(Some(tracer(Box::new(buffer.clone()))));
+        for _ in 0..program.len() / 2 {
+            chip8.cycle();
+        }
+        chip8.set_tracer(None);
+        let text = String::from_utf8(buffer.0.borrow().clone()).unwrap();
+        text
+    }
+
+    #[test]
+    fn delta_lines() {
+        // LD VA, 2; LD I, 0x22A; CLS
+        let text = run(&[0x6A, 0x02, 0xA2, 0x2A, 0x00, 0xE0], |out| Tracer::new(out, TraceFormat::Delta));
+        let lines: Vec<&str> = text.lines().collect();
+        assert_eq!(lines[0], "0200  6A02  LD VA, 0x02      VA:00->02");
+        assert_eq!(lines[1], "0202  A22A  LD I, 0x22A      I:0000->022A");
+        assert_eq!(lines[2], "0204  00E0  CLS");
+    }
+    #[test]
+    fn state_lines() {
+        let text = run(&[0x6A, 0x02, 0x00, 0xE0], |out| Tracer::new(out, TraceFormat::State));
+        let second = text.lines().nth(1).unwrap();
+        assert_eq!(
+            second,
+            "PC:0202 OP:00E0 I:0000 SP:00 DT:00 ST:00 V:00 00 00 00 00 00 00 00 00 00 02 00 00 00 00 00"
+        );
+    }
+    #[test]
+    fn filters() {
+        let program = [0x6A, 0x02, 0xA2, 0x2A, 0x00, 0xE0, 0x6B, 0x01];
+        let text = run(&program, |out| {
+            let mut tracer = Tracer::new(out, TraceFormat::Delta);
+            tracer.classes = vec![OpClass::Arithmetic];
+            tracer
+        });
+        assert_eq!(text.lines().count(), 2);
+        let text = run(&program, |out| {
+            let mut tracer = Tracer::new(out, TraceFormat::Delta);
+            tracer.address_range = Some((0x202, 0x204));
+            tracer
+        });
+        assert!(text.starts_with("0202"));
+        assert_eq!(text.lines().count(), 2);
+    }
+}
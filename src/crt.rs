@@ -0,0 +1,331 @@
+// cpu post-processing that makes the window look like a crt. each pass reads one
+// 0RGB buffer and writes another of the same size, so they can be chained in any order
+use std::str::FromStr;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Pass {
+    // darkens every other line by this much, 0 to 1
+    Scanlines(f32),
+    // adds a box blur of the image with this radius in pixels, at this strength
+    Bloom(usize, f32),
+    // barrel distortion, 0 is flat
+    Curvature(f32),
+    // darkens towards the corners by this much
+    Vignette(f32),
+}
+impl Pass {
+    fn apply(&self, src: &[u32], dst: &mut [u32], state: &mut State) {
+        let (width, height) = (state.width, state.height);
+        match *self {
+            Pass::Scanlines(strength) => scanlines(src, dst, width, strength),
+            Pass::Bloom(radius, strength) => bloom(src, dst, state, radius, strength),
+            Pass::Curvature(_) => {
+                for (d, &i) in dst.iter_mut().zip(&state.table) {
+                    *d = src.get(i as usize).copied().unwrap_or(0);
+                }
+            }
+            Pass::Vignette(strength) => {
+                for (y, (src_row, dst_row)) in src.chunks(width).zip(dst.chunks_mut(width)).enumerate() {
+                    let v = centered(y, height);
+                    let row = 256 - (strength * v * v * 128.0) as i32;
+                    for ((d, &s), &column) in dst_row.iter_mut().zip(src_row).zip(&state.table) {
+                        *d = scale_color(s, (row - column as i32).max(0) as u32);
+                    }
+                }
+            }
+        }
+    }
+    // lookup tables that only depend on the frame size
+    fn table(&self, width: usize, height: usize) -> Vec<u32> {
+        match *self {
+            // index of the source pixel for every output pixel, past the end for black
+            Pass::Curvature(amount) => (0..width * height)
+                .map(|i| {
+                    let (u, v) = (centered(i % width, width), centered(i / width, height));
+                    // sample further out the further from the middle, so the picture bulges
+                    let bend = 1.0 + amount * (u * u + v * v);
+                    let (su, sv) = (u * bend, v * bend);
+                    if su.abs() > 1.0 || sv.abs() > 1.0 {
+                        return u32::MAX;
+                    }
+                    let sx = (((su + 1.0) / 2.0 * width as f32) as usize).min(width - 1);
+                    let sy = (((sv + 1.0) / 2.0 * height as f32) as usize).min(height - 1);
+                    (sy * width + sx) as u32
+                })
+                .collect(),
+            // the darkening contributed by each column, in 256ths. the falloff is
+            // 1 - strength * (u² + v²) / 2 so rows and columns add up independently
+            Pass::Vignette(strength) => (0..width)
+                .map(|x| {
+                    let u = centered(x, width);
+                    (strength * u * u * 128.0) as u32
+                })
+                .collect(),
+            _ => Vec::new(),
+        }
+    }
+}
+
+// what a pass keeps between frames
+#[derive(Default)]
+struct State {
+    width: usize,
+    height: usize,
+    table: Vec<u32>,
+    small: Vec<u32>,
+    small_scratch: Vec<u32>,
+}
+
+// `scanlines`, `scanlines:0.5`, `bloom:8:0.4`, `curvature:0.1`, `vignette:0.3`
+impl FromStr for Pass {
+    type Err = String;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut parts = s.split(':');
+        let name = parts.next().unwrap_or_default();
+        let args: Vec<&str> = parts.collect();
+        let arg = |index: usize, default: f32| match args.get(index) {
+            Some(arg) => arg.parse::<f32>().ok().filter(|v| *v >= 0.0).ok_or_else(|| format!("invalid {} setting `{}`", name, arg)),
+            None => Ok(default),
+        };
+        let max_args = match name {
+            "bloom" => 2,
+            _ => 1,
+        };
+        if args.len() > max_args {
+            return Err(format!("too many settings for {} in `{}`", name, s));
+        }
+        match name {
+            "scanlines" => Ok(Pass::Scanlines(arg(0, 0.35)?.min(1.0))),
+            "bloom" => Ok(Pass::Bloom(arg(0, 8.0)? as usize, arg(1, 0.5)?)),
+            "curvature" => Ok(Pass::Curvature(arg(0, 0.08)?)),
+            "vignette" => Ok(Pass::Vignette(arg(0, 0.35)?.min(1.0))),
+            _ => Err(format!("unknown crt pass `{}`, expected scanlines, bloom, curvature or vignette", name)),
+        }
+    }
+}
+
+// comma separated passes, applied in the order given
+pub fn parse_passes(s: &str) -> Result<Vec<Pass>, String> {
+    s.split(',').map(|pass| pass.trim().parse()).collect()
+}
+
+// multiplies every channel by factor / 256
+fn scale_color(color: u32, factor: u32) -> u32 {
+    let rb = (((color & 0xFF00FF) * factor) >> 8) & 0xFF00FF;
+    let g = (((color & 0x00FF00) * factor) >> 8) & 0x00FF00;
+    rb | g
+}
+fn factor(scale: f32) -> u32 {
+    (scale.clamp(0.0, 1.0) * 256.0) as u32
+}
+// per channel, a channel that carries out is filled with ones instead
+fn add_saturating(a: u32, b: u32) -> u32 {
+    let rb = (a & 0xFF00FF) + (b & 0xFF00FF);
+    let rb_carry = rb & 0x1000100;
+    let g = (a & 0x00FF00) + (b & 0x00FF00);
+    let g_carry = g & 0x10000;
+    ((rb | (rb_carry - (rb_carry >> 8))) & 0xFF00FF) | ((g | (g_carry - (g_carry >> 8))) & 0x00FF00)
+}
+
+fn scanlines(src: &[u32], dst: &mut [u32], width: usize, strength: f32) {
+    let dim = factor(1.0 - strength);
+    for (y, (src_row, dst_row)) in src.chunks(width).zip(dst.chunks_mut(width)).enumerate() {
+        if y % 2 == 0 {
+            dst_row.copy_from_slice(src_row);
+        } else {
+            for (d, &s) in dst_row.iter_mut().zip(src_row) {
+                *d = scale_color(s, dim);
+            }
+        }
+    }
+}
+
+// the three channels of a pixel spread 21 bits apart, so sums of them can be added up in one go
+fn spread(color: u32) -> u64 {
+    let color = color as u64;
+    (color & 0xFF) | (color & 0xFF00) << 13 | (color & 0xFF0000) << 26
+}
+// divides each lane by the divisor that `inverse` was made from and packs it back into a pixel
+fn unspread(sums: u64, inverse: u64) -> u32 {
+    let lane = |shift: u32| (((sums >> shift & 0x1FFFFF) * inverse) >> 16) as u32;
+    lane(42) << 16 | lane(21) << 8 | lane(0)
+}
+// rounded up so that dividing by multiplying doesn't lose the last step to truncation
+fn inverse(divisor: usize) -> u64 {
+    65536u64.div_ceil(divisor as u64)
+}
+
+// box blur of one line of pixels `stride` apart, using a running sum
+fn blur_line(src: &[u32], dst: &mut [u32], start: usize, len: usize, stride: usize, radius: usize) {
+    let inverse = inverse(2 * radius + 1);
+    let at = |i: usize| spread(src[start + i * stride]);
+    let mut sums: u64 = (0..radius.min(len)).map(at).sum();
+    for i in 0..len {
+        if i + radius < len {
+            sums += at(i + radius);
+        }
+        dst[start + i * stride] = unspread(sums, inverse);
+        if i >= radius {
+            sums -= at(i - radius);
+        }
+    }
+}
+
+// the glow is blurry anyway, so it's worked out on a frame shrunk by `step` and
+// stretched back over the original. keeps the cost down at large window scales
+fn bloom(src: &[u32], dst: &mut [u32], state: &mut State, radius: usize, strength: f32) {
+    if radius == 0 {
+        dst.copy_from_slice(src);
+        return;
+    }
+    let (width, height) = (state.width, state.height);
+    let step = (radius / 2).clamp(1, 8);
+    let radius = radius.div_ceil(step);
+    let (small_width, small_height) = (width.div_ceil(step), height.div_ceil(step));
+    state.small.resize(small_width * small_height, 0);
+    state.small_scratch.resize(small_width * small_height, 0);
+    // average each step x step block
+    let mut sums = vec![0u64; small_width];
+    for (small_y, block) in src.chunks(width * step).enumerate() {
+        sums.fill(0);
+        for row in block.chunks(width) {
+            for (sum, run) in sums.iter_mut().zip(row.chunks(step)) {
+                *sum += run.iter().map(|&color| spread(color)).sum::<u64>();
+            }
+        }
+        let count = (block.len() / width) * step;
+        let row = &mut state.small[small_y * small_width..][..small_width];
+        for (pixel, &sum) in row.iter_mut().zip(&sums) {
+            *pixel = unspread(sum, inverse(count));
+        }
+    }
+    // horizontal into scratch, vertical back, then add the glow on top
+    for y in 0..small_height {
+        blur_line(&state.small, &mut state.small_scratch, y * small_width, small_width, 1, radius);
+    }
+    for x in 0..small_width {
+        blur_line(&state.small_scratch, &mut state.small, x, small_height, small_width, radius);
+    }
+    let glow = factor(strength);
+    for pixel in state.small.iter_mut() {
+        *pixel = scale_color(*pixel, glow);
+    }
+    // stretch each row of glow back to full width once, then add it to every row it covers
+    let mut glow_row = vec![0; width];
+    for (y, (src_row, dst_row)) in src.chunks(width).zip(dst.chunks_mut(width)).enumerate() {
+        if y % step == 0 {
+            let small_row = &state.small[y / step * small_width..][..small_width];
+            for (run, &glow) in glow_row.chunks_mut(step).zip(small_row) {
+                run.fill(glow);
+            }
+        }
+        for ((d, &s), &glow) in dst_row.iter_mut().zip(src_row).zip(&glow_row) {
+            *d = add_saturating(s, glow);
+        }
+    }
+}
+
+// coordinates from -1 to 1 across the image
+fn centered(x: usize, size: usize) -> f32 {
+    (x as f32 + 0.5) / size as f32 * 2.0 - 1.0
+}
+
+// runs a list of passes over a frame, keeping its buffers between frames
+pub struct Crt {
+    passes: Vec<(Pass, State)>,
+    front: Vec<u32>,
+    back: Vec<u32>,
+}
+impl Crt {
+    pub fn new(passes: Vec<Pass>) -> Self {
+        let passes = passes.into_iter().map(|pass| (pass, State::default())).collect();
+        Crt { passes, front: Vec::new(), back: Vec::new() }
+    }
+    pub fn is_empty(&self) -> bool {
+        self.passes.is_empty()
+    }
+    pub fn apply(&mut self, input: &[u32], width: usize, height: usize) -> &[u32] {
+        self.front.resize(input.len(), 0);
+        self.back.resize(input.len(), 0);
+        self.front.copy_from_slice(input);
+        for (pass, state) in &mut self.passes {
+            if (state.width, state.height) != (width, height) {
+                *state = State { width, height, table: pass.table(width, height), ..State::default() };
+            }
+            pass.apply(&self.front, &mut self.back, state);
+            std::mem::swap(&mut self.front, &mut self.back);
+        }
+        &self.front
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_pass_list() {
+        assert_eq!(
+            parse_passes("scanlines, bloom:3:0.5,vignette"),
+            Ok(vec![Pass::Scanlines(0.35), Pass::Bloom(3, 0.5), Pass::Vignette(0.35)])
+        );
+        assert!(parse_passes("bloom:a").is_err());
+        assert!(parse_passes("curvature:0.1:2").is_err());
+        assert!(parse_passes("blur").is_err());
+    }
+    #[test]
+    fn scanlines_dim_odd_rows() {
+        let src = vec![0xFFFFFF; 4];
+        let mut crt = Crt::new(vec![Pass::Scanlines(0.5)]);
+        assert_eq!(crt.apply(&src, 2, 2), &[0xFFFFFF, 0xFFFFFF, 0x7F7F7F, 0x7F7F7F]);
+    }
+    #[test]
+    fn bloom_spreads_light() {
+        // one lit pixel in the middle of a 5x5 frame
+        let mut src = vec![0; 25];
+        src[12] = 0xFF0000;
+        let mut crt = Crt::new(vec![Pass::Bloom(1, 1.0)]);
+        let out = crt.apply(&src, 5, 5).to_vec();
+        assert_eq!(out[12], 0xFF0000);
+        // a ninth of the light reaches the neighbours, nothing past the radius
+        assert_eq!(out[11], 0x1C0000);
+        assert_eq!(out[6], 0x1C0000);
+        assert_eq!(out[10], 0);
+        // wide glows are worked out at a lower resolution but still reach as far
+        let mut src = vec![0; 64 * 64];
+        for y in 28..36 {
+            src[y * 64 + 28..y * 64 + 36].fill(0xFFFFFF);
+        }
+        let mut crt = Crt::new(vec![Pass::Bloom(8, 1.0)]);
+        let out = crt.apply(&src, 64, 64).to_vec();
+        assert_ne!(out[32 * 64 + 40], 0);
+        assert_eq!(out[32 * 64 + 46], 0);
+    }
+    #[test]
+    fn adding_saturates_each_channel() {
+        assert_eq!(add_saturating(0xF01080, 0x2010F0), 0xFF20FF);
+        assert_eq!(add_saturating(0x00FF00, 0x000100), 0x00FF00);
+        assert_eq!(add_saturating(0x010203, 0x010101), 0x020304);
+    }
+    #[test]
+    fn curvature_and_vignette_keep_the_middle() {
+        let src: Vec<u32> = (0..64 * 32).map(|i| i as u32).collect();
+        let mut crt = Crt::new(vec![Pass::Curvature(0.2)]);
+        let out = crt.apply(&src, 64, 32).to_vec();
+        assert_eq!(out[16 * 64 + 32], src[16 * 64 + 32]);
+        // corners fall off the edge of the tube
+        assert_eq!(out[0], 0);
+        let mut crt = Crt::new(vec![Pass::Vignette(1.0)]);
+        let white = vec![0xFFFFFF; 64 * 32];
+        let out = crt.apply(&white, 64, 32).to_vec();
+        assert!(out[16 * 64 + 32] > 0xF00000);
+        assert!(out[0] < 0x100000);
+    }
+    #[test]
+    fn passes_chain() {
+        let src = vec![0xFFFFFF; 16];
+        let mut crt = Crt::new(parse_passes("scanlines:1,scanlines:1").unwrap());
+        assert_eq!(crt.apply(&src, 4, 4)[4], 0);
+        assert!(Crt::new(Vec::new()).is_empty());
+    }
+}
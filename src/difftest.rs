@@ -0,0 +1,305 @@
+// differential testing: run a rom and compare the machine after every
+// instruction against a recorded trace. the traces in tests/snapshots were recorded
+// with this emulator's own `record`, so they're regression snapshots that catch
+// changes in behaviour, not a check against another emulator.
+use crate::Chip8;
+use std::fmt;
+use std::str::FromStr;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Snapshot {
+    pub pc: u16,
+    pub i_reg: u16,
+    pub stack_pointer: u8,
+    pub stack: Vec<u16>,
+    pub delay_timer: u8,
+    pub sound_timer: u8,
+    pub v_registers: [u8; 16],
+    pub screen_hash: u64,
+}
+
+// FNV-1a over the pixels, enough to spot a different frame
+pub fn screen_hash(chip8: &Chip8) -> u64 {
+    let mut hash: u64 = 0xcbf29ce484222325;
+    for row in chip8.get_screen().iter() {
+        for &pixel in row.iter() {
+            hash ^= pixel as u64;
+            hash = hash.wrapping_mul(0x100000001b3);
+        }
+    }
+    hash
+}
+
+impl Snapshot {
+    pub fn capture(chip8: &Chip8) -> Self {
+        Snapshot {
+            pc: chip8.pc,
+            i_reg: chip8.i_reg,
+            stack_pointer: chip8.stack_pointer(),
+            stack: chip8.stack().to_vec(),
+            delay_timer: chip8.delay_timer(),
+            sound_timer: chip8.sound_timer(),
+            v_registers: chip8.v_registers,
+            screen_hash: screen_hash(chip8),
+        }
+    }
+
+    // readable list of every field that differs from `other`
+    pub fn differences(&self, other: &Snapshot) -> Vec<String> {
+        let mut out = Vec::new();
+        let mut check = |name: &str, expected: String, actual: String| {
+            if expected != actual {
+                out.push(format!("{}: expected {}, got {}", name, expected, actual));
+            }
+        };
+        check("PC", format!("{:04X}", self.pc), format!("{:04X}", other.pc));
+        check("I", format!("{:04X}", self.i_reg), format!("{:04X}", other.i_reg));
+        check("SP", format!("{:02X}", self.stack_pointer), format!("{:02X}", other.stack_pointer));
+        check("stack", format_stack(&self.stack), format_stack(&other.stack));
+        check("DT", format!("{:02X}", self.delay_timer), format!("{:02X}", other.delay_timer));
+        check("ST", format!("{:02X}", self.sound_timer), format!("{:02X}", other.sound_timer));
+        for x in 0..16 {
+            check(&format!("V{:X}", x), format!("{:02X}", self.v_registers[x]), format!("{:02X}", other.v_registers[x]));
+        }
+        check("screen", format!("{:016x}", self.screen_hash), format!("{:016x}", other.screen_hash));
+        out
+    }
+}
+
+fn format_stack(stack: &[u16]) -> String {
+    if stack.is_empty() {
+        return "-".to_string();
+    }
+    stack.iter().map(|addr| format!("{:04X}", addr)).collect::<Vec<_>>().join(",")
+}
+
+// PC:0200 I:0000 SP:00 STACK:- DT:00 ST:00 V:00,00,... SCREEN:<hash>
+impl fmt::Display for Snapshot {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let v: Vec<String> = self.v_registers.iter().map(|v| format!("{:02X}", v)).collect();
+        write!(
+            f,
+            "PC:{:04X} I:{:04X} SP:{:02X} STACK:{} DT:{:02X} ST:{:02X} V:{} SCREEN:{:016x}",
+            self.pc,
+            self.i_reg,
+            self.stack_pointer,
+            format_stack(&self.stack),
+            self.delay_timer,
+            self.sound_timer,
+            v.join(","),
+            self.screen_hash
+        )
+    }
+}
+impl FromStr for Snapshot {
+    type Err = String;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut snapshot = Snapshot {
+            pc: 0,
+            i_reg: 0,
+            stack_pointer: 0,
+            stack: Vec::new(),
+            delay_timer: 0,
+            sound_timer: 0,
+            v_registers: [0; 16],
+            screen_hash: 0,
+        };
+        let hex16 = |v: &str| u16::from_str_radix(v, 16).map_err(|_| format!("invalid value `{}`", v));
+        let hex8 = |v: &str| u8::from_str_radix(v, 16).map_err(|_| format!("invalid value `{}`", v));
+        let mut seen = 0;
+        for field in s.split_whitespace() {
+            let (key, value) = field.split_once(':').ok_or_else(|| format!("malformed field `{}`", field))?;
+            match key {
+                "PC" => snapshot.pc = hex16(value)?,
+                "I" => snapshot.i_reg = hex16(value)?,
+                "SP" => snapshot.stack_pointer = hex8(value)?,
+                "STACK" if value == "-" => {}
+                "STACK" => snapshot.stack = value.split(',').map(hex16).collect::<Result<_, _>>()?,
+                "DT" => snapshot.delay_timer = hex8(value)?,
+                "ST" => snapshot.sound_timer = hex8(value)?,
+                "V" => {
+                    let values: Vec<u8> = value.split(',').map(hex8).collect::<Result<_, _>>()?;
+                    snapshot.v_registers = values.try_into().map_err(|_| "expected 16 V registers".to_string())?;
+                }
+                "SCREEN" => {
+                    snapshot.screen_hash =
+                        u64::from_str_radix(value, 16).map_err(|_| format!("invalid screen hash `{}`", value))?
+                }
+                _ => return Err(format!("unknown field `{}`", key)),
+            }
+            seen += 1;
+        }
+        if seen != 8 {
+            return Err(format!("expected 8 fields, found {}", seen));
+        }
+        Ok(snapshot)
+    }
+}
+
+// state before the first instruction followed by the state after each step
+pub fn record(chip8: &mut Chip8, steps: usize) -> Vec<Snapshot> {
+    let mut trace = vec![Snapshot::capture(chip8)];
+    for _ in 0..steps {
+        chip8.cycle();
+        trace.push(Snapshot::capture(chip8));
+    }
+    trace
+}
+
+pub fn write_trace(trace: &[Snapshot]) -> String {
+    trace.iter().enumerate().map(|(step, snapshot)| format!("{} {}\n", step, snapshot)).collect()
+}
+
+pub fn parse_trace(text: &str) -> Result<Vec<Snapshot>, String> {
+    text.lines()
+        .filter(|line| !line.trim().is_empty())
+        .enumerate()
+        .map(|(index, line)| {
+            let (step, rest) = line.split_once(' ').ok_or_else(|| format!("line {}: missing step number", index + 1))?;
+            if step.parse() != Ok(index) {
+                return Err(format!("line {}: expected step {}, found `{}`", index + 1, index, step));
+            }
+            rest.parse().map_err(|e| format!("line {}: {}", index + 1, e))
+        })
+        .collect()
+}
+
+#[derive(Debug)]
+pub struct Divergence {
+    pub step: usize,
+    pub expected: Snapshot,
+    pub actual: Snapshot,
+    // the step before, so the report shows where things were coming from
+    pub previous: Option<Snapshot>,
+}
+impl fmt::Display for Divergence {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "diverged at step {}", self.step)?;
+        if let Some(previous) = &self.previous {
+            writeln!(f, "  previous  {}", previous)?;
+        }
+        writeln!(f, "  expected  {}", self.expected)?;
+        writeln!(f, "  actual    {}", self.actual)?;
+        for difference in self.expected.differences(&self.actual) {
+            writeln!(f, "  {}", difference)?;
+        }
+        Ok(())
+    }
+}
+
+// runs as many steps as the reference has and stops at the first mismatch
+pub fn compare(chip8: &mut Chip8, reference: &[Snapshot]) -> Result<(), Box<Divergence>> {
+    let mut previous = None;
+    for (step, expected) in reference.iter().enumerate() {
+        if step > 0 {
+            chip8.cycle();
+        }
+        let actual = Snapshot::capture(chip8);
+        if actual != *expected {
+            return Err(Box::new(Divergence { step, expected: expected.clone(), actual, previous }));
+        }
+        previous = Some(actual);
+    }
+    Ok(())
+}
+
+// like compare, but runs `chunk` instructions at a time through the block cache and
+// checks the machine against the reference at each chunk boundary
+pub fn compare_blocks(chip8: &mut Chip8, reference: &[Snapshot], chunk: usize) -> Result<(), Box<Divergence>> {
+    let mut previous = None;
+    for step in (0..reference.len()).step_by(chunk.max(1)) {
+        if step > 0 {
+            chip8.run_steps(chunk);
+        }
+        let actual = Snapshot::capture(chip8);
+        if actual != reference[step] {
+            return Err(Box::new(Divergence { step, expected: reference[step].clone(), actual, previous }));
+        }
+        previous = Some(actual);
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    const ROMS: [&str; 4] = ["1-chip8-logo", "2-ibm-logo", "3-corax+", "4-flags"];
+
+    fn boot(rom: &str) -> Chip8 {
+        let mut chip8 = Chip8::new();
+        chip8.load_program(&format!("{}.ch8", rom)).unwrap();
+        chip8.pc = 0x200;
+        chip8
+    }
+
+    #[test]
+    fn bundled_roms_match_snapshots() {
+        for rom in ROMS {
+            let text = fs::read_to_string(format!("tests/snapshots/{}.trace", rom)).unwrap();
+            let reference = parse_trace(&text).unwrap();
+            if let Err(divergence) = compare(&mut boot(rom), &reference) {
+                panic!("{}: {}", rom, divergence);
+            }
+        }
+    }
+    #[test]
+    fn blocks_match_snapshots() {
+        for rom in ROMS {
+            let text = fs::read_to_string(format!("tests/snapshots/{}.trace", rom)).unwrap();
+            let reference = parse_trace(&text).unwrap();
+            for chunk in [1, 7, 64] {
+                if let Err(divergence) = compare_blocks(&mut boot(rom), &reference, chunk) {
+                    panic!("{} in chunks of {}: {}", rom, chunk, divergence);
+                }
+            }
+        }
+    }
+    #[test]
+    fn snapshot_round_trip() {
+        let mut chip8 = boot("2-ibm-logo");
+        let trace = record(&mut chip8, 30);
+        assert_eq!(parse_trace(&write_trace(&trace)).unwrap(), trace);
+    }
+    #[test]
+    fn reports_first_divergence() {
+        let mut reference = record(&mut boot("2-ibm-logo"), 10);
+        reference[6].v_registers[0] = 0x42;
+        reference[8].pc = 0;
+        let divergence = compare(&mut boot("2-ibm-logo"), &reference).unwrap_err();
+        assert_eq!(divergence.step, 6);
+        let report = divergence.to_string();
+        assert!(report.starts_with("diverged at step 6"));
+        assert!(report.contains("V0: expected 42, got "));
+        assert!(!report.contains("PC: expected"));
+    }
+
+    mod properties {
+        use super::*;
+        use proptest::collection::vec;
+        use proptest::prelude::*;
+
+        proptest! {
+            #[test]
+            fn blocks_match_interpreter_on_random_roms(rom in vec(any::<u8>(), 0..0x400), chunk in 1..100usize) {
+                let boot = || {
+                    let mut chip8 = Chip8::new();
+                    chip8.pc = 0x200;
+                    for (i, byte) in rom.iter().enumerate() {
+                        // RAND can't be reproduced on two machines, turn CXNN into 6XNN
+                        let byte = if byte >> 4 == 0xC { byte ^ 0xA0 } else { *byte };
+                        chip8.ram.write_byte(0x200 + i as u16, byte);
+                    }
+                    chip8
+                };
+                let mut interpreted = boot();
+                let reference = record(&mut interpreted, 500);
+                let mut compiled = boot();
+                if let Err(divergence) = compare_blocks(&mut compiled, &reference, chunk) {
+                    return Err(TestCaseError::fail(divergence.to_string()));
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,198 @@
+// turns a rom back into readable instructions. code is found by following
+// control flow from the entry point so sprite data isn't shown as instructions.
+use crate::cpu::opcodes::OpCode;
+use std::collections::{BTreeSet, HashSet};
+use std::fmt;
+
+#[derive(Debug, PartialEq)]
+pub enum Item {
+    Instruction { address: u16, word: u16, op: OpCode },
+    Data { address: u16, byte: u8 },
+}
+
+pub struct Disassembly {
+    pub items: Vec<Item>,
+    // jump and call targets that get a label in the listing
+    pub labels: BTreeSet<u16>,
+    // bytes of the rom that would land past FFF, where nothing can address them
+    pub past_end: usize,
+}
+
+pub fn label_name(address: u16) -> String {
+    format!("L{:03X}", address)
+}
+
+// marks which rom offsets start an instruction, starting from origin
+fn trace_code(rom: &[u8], origin: u16, labels: &mut BTreeSet<u16>) -> HashSet<u16> {
+    let end = origin as usize + rom.len();
+    let mut code = HashSet::new();
+    let mut pending = vec![origin];
+    while let Some(address) = pending.pop() {
+        let mut pc = address;
+        // stop at the end of the rom or once we reach code we already walked
+        while (pc as usize) + 1 < end && (pc as usize) >= origin as usize && code.insert(pc) {
+            let offset = (pc - origin) as usize;
+            let word = (rom[offset] as u16) << 8 | rom[offset + 1] as u16;
+            let next = pc + 2;
+            match OpCode::decode(word) {
+                OpCode::JMP(nnn) => {
+                    labels.insert(nnn);
+                    pending.push(nnn);
+                    break;
+                }
+                OpCode::JumpPlusV0(nnn) => {
+                    // usually a table of jumps, so walk it from the start
+                    labels.insert(nnn);
+                    pending.push(nnn);
+                    break;
+                }
+                OpCode::CALL(nnn) => {
+                    labels.insert(nnn);
+                    pending.push(nnn);
+                }
+                OpCode::RET => break,
+                OpCode::SkipEqualNN(..)
+                | OpCode::SkipNotEqualNN(..)
+                | OpCode::SkipEqualXY(..)
+                | OpCode::SkipNotEqualXY(..)
+                | OpCode::SkipKeyPressed(_)
+                | OpCode::SkipKeyNotPressed(_) => pending.push(next + 2),
+                OpCode::UNFINISHED => {
+                    code.remove(&pc);
+                    break;
+                }
+                _ => {}
+            }
+            pc = next;
+        }
+    }
+    code
+}
+
+pub fn disassemble(rom: &[u8], origin: u16) -> Disassembly {
+    // addresses are 12 bits, anything after FFF is left out rather than wrapped around
+    let fits = rom.len().min(0x1000usize.saturating_sub(origin as usize));
+    let (rom, past_end) = (&rom[..fits], rom.len() - fits);
+    let mut labels = BTreeSet::new();
+    let code = trace_code(rom, origin, &mut labels);
+    let mut items = Vec::new();
+    let mut offset = 0;
+    while offset < rom.len() {
+        let address = origin + offset as u16;
+        if code.contains(&address) {
+            let word = (rom[offset] as u16) << 8 | rom[offset + 1] as u16;
+            items.push(Item::Instruction { address, word, op: OpCode::decode(word) });
+            offset += 2;
+        } else {
+            items.push(Item::Data { address, byte: rom[offset] });
+            offset += 1;
+        }
+    }
+    // only keep labels that land inside the listing
+    let listed: HashSet<u16> = items
+        .iter()
+        .map(|item| match item {
+            Item::Instruction { address, .. } | Item::Data { address, .. } => *address,
+        })
+        .collect();
+    labels.retain(|address| listed.contains(address));
+    Disassembly { items, labels, past_end }
+}
+
+// jump and call targets use labels when one exists
+pub fn mnemonic(op: &OpCode, labels: &BTreeSet<u16>) -> String {
+    match op {
+        OpCode::JMP(nnn) if labels.contains(nnn) => format!("JP {}", label_name(*nnn)),
+        OpCode::CALL(nnn) if labels.contains(nnn) => format!("CALL {}", label_name(*nnn)),
+        OpCode::JumpPlusV0(nnn) if labels.contains(nnn) => format!("JP V0, {}", label_name(*nnn)),
+        _ => op.to_string(),
+    }
+}
+
+impl Disassembly {
+    // listing without addresses or raw words that the assembler accepts
+    pub fn to_source(&self) -> String {
+        let mut out = String::new();
+        for item in &self.items {
+            let (address, text) = match item {
+                Item::Instruction { address, op, .. } => (address, mnemonic(op, &self.labels)),
+                Item::Data { address, byte } => (address, format!("db 0x{:02X}", byte)),
+            };
+            if self.labels.contains(address) {
+                out.push_str(&format!("{}:\n", label_name(*address)));
+            }
+            out.push_str(&format!("    {}\n", text));
+        }
+        out
+    }
+}
+
+impl fmt::Display for Disassembly {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for item in &self.items {
+            match item {
+                Item::Instruction { address, word, op } => {
+                    if self.labels.contains(address) {
+                        writeln!(f, "{}:", label_name(*address))?;
+                    }
+                    writeln!(f, "{:03X}  {:04X}  {}", address, word, mnemonic(op, &self.labels))?;
+                }
+                Item::Data { address, byte } => {
+                    if self.labels.contains(address) {
+                        writeln!(f, "{}:", label_name(*address))?;
+                    }
+                    writeln!(f, "{:03X}  {:02X}    db 0x{:02X}", address, byte, byte)?;
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn separates_code_from_data() {
+        // LD I, 0x208; DRW V0, V0, 1; JP 0x204; sprite byte
+        let rom = [0xA2, 0x08, 0xD0, 0x01, 0x12, 0x04, 0x00, 0x00, 0xFF];
+        let listing = disassemble(&rom, 0x200);
+        assert_eq!(
+            listing.items[0],
+            Item::Instruction { address: 0x200, word: 0xA208, op: OpCode::SetAddrReg(0x208) }
+        );
+        assert!(matches!(listing.items[2], Item::Instruction { address: 0x204, .. }));
+        assert_eq!(listing.items[3], Item::Data { address: 0x206, byte: 0x00 });
+        assert_eq!(listing.items[5], Item::Data { address: 0x208, byte: 0xFF });
+        assert!(listing.labels.contains(&0x204));
+    }
+    #[test]
+    fn follows_calls_and_skips() {
+        // CALL 0x208; SE V0, 1; JP 0x206; JP 0x206; RET
+        let rom = [0x22, 0x08, 0x30, 0x01, 0x12, 0x06, 0x12, 0x06, 0x00, 0xEE];
+        let listing = disassemble(&rom, 0x200);
+        assert_eq!(listing.items.len(), 5);
+        assert!(listing.items.iter().all(|item| matches!(item, Item::Instruction { .. })));
+        let text = listing.to_string();
+        assert!(text.contains("L208:\n208  00EE  RET"));
+        assert!(text.contains("200  2208  CALL L208"));
+    }
+    #[test]
+    fn odd_length_rom() {
+        let rom = [0x00, 0xE0, 0x12];
+        let listing = disassemble(&rom, 0x200);
+        assert_eq!(listing.items.len(), 2);
+        assert_eq!(listing.items[1], Item::Data { address: 0x202, byte: 0x12 });
+    }
+    #[test]
+    fn stops_at_the_end_of_memory() {
+        // bigger than the whole address space and all code, LD V0, 0 over and over
+        let rom: Vec<u8> = [0x60, 0x00].repeat(35_000);
+        let listing = disassemble(&rom, 0x200);
+        assert_eq!(listing.items.len(), (0x1000 - 0x200) / 2);
+        assert_eq!(listing.past_end, 70_000 - (0x1000 - 0x200));
+        assert!(matches!(listing.items.last(), Some(Item::Instruction { address: 0xFFE, .. })));
+        assert_eq!(disassemble(&[0x00, 0xE0, 0x12, 0x00], 0xFFE).items.len(), 1);
+    }
+}
@@ -0,0 +1,192 @@
+// how the 64x32 screen is turned into window pixels: scale, colors and grid
+use crate::cpu::Rect;
+use crate::crt::Pass;
+use crate::filter::{Filter, Levels};
+use crate::Chip8;
+
+pub const SCREEN_WIDTH: usize = 64;
+pub const SCREEN_HEIGHT: usize = 32;
+
+// foreground and background of a named palette
+pub const PALETTES: [(&str, u32, u32); 5] = [
+    ("classic", 0xFFFFFF, 0x000000),
+    ("green", 0x33FF66, 0x0A1F0F),
+    ("amber", 0xFFB000, 0x1F1200),
+    ("octo", 0xFFCC00, 0x996600),
+    ("lcd", 0x0F380F, 0x9BBC0F),
+];
+
+pub fn palette(name: &str) -> Option<(u32, u32)> {
+    PALETTES.iter().find(|(n, ..)| n.eq_ignore_ascii_case(name)).map(|&(_, fg, bg)| (fg, bg))
+}
+
+// `#RRGGBB`, `0xRRGGBB` or bare `RRGGBB`
+pub fn parse_color(s: &str) -> Result<u32, String> {
+    let hex = s.trim();
+    let hex = hex.strip_prefix('#').or_else(|| hex.strip_prefix("0x")).unwrap_or(hex);
+    match u32::from_str_radix(hex, 16) {
+        Ok(color) if hex.len() == 6 => Ok(color),
+        _ => Err(format!("invalid color `{}`, expected RRGGBB hex", s)),
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct DisplayConfig {
+    // window pixels per chip-8 pixel
+    pub scale: usize,
+    pub foreground: u32,
+    pub background: u32,
+    // color of the line drawn between pixels, None for no grid
+    pub grid: Option<u32>,
+    // flicker reduction, None draws the screen as is
+    pub filter: Option<Filter>,
+    // post-processing applied to the finished window buffer, in order
+    pub crt: Vec<Pass>,
+}
+impl Default for DisplayConfig {
+    fn default() -> Self {
+        DisplayConfig { scale: 20, foreground: 0xFFFFFF, background: 0x000000, grid: None, filter: None, crt: Vec::new() }
+    }
+}
+impl DisplayConfig {
+    pub fn width(&self) -> usize {
+        SCREEN_WIDTH * self.scale
+    }
+    pub fn height(&self) -> usize {
+        SCREEN_HEIGHT * self.scale
+    }
+    pub fn set_palette(&mut self, name: &str) -> Result<(), String> {
+        let names: Vec<&str> = PALETTES.iter().map(|(name, ..)| *name).collect();
+        let (fg, bg) = palette(name)
+            .ok_or_else(|| format!("unknown palette `{}`, expected one of {}", name, names.join(", ")))?;
+        self.foreground = fg;
+        self.background = bg;
+        Ok(())
+    }
+    // halfway between the two colors, visible on either
+    pub fn default_grid_color(&self) -> u32 {
+        let mix = |shift: u32| (((self.foreground >> shift & 0xFF) + (self.background >> shift & 0xFF)) / 2) << shift;
+        mix(16) | mix(8) | mix(0)
+    }
+
+    // background to foreground, channel by channel
+    pub fn shade(&self, level: f32) -> u32 {
+        let level = level.clamp(0.0, 1.0);
+        let mix = |shift: u32| {
+            let (fg, bg) = ((self.foreground >> shift & 0xFF) as f32, (self.background >> shift & 0xFF) as f32);
+            ((bg + (fg - bg) * level).round() as u32) << shift
+        };
+        mix(16) | mix(8) | mix(0)
+    }
+
+    // `width` is the buffer's width in pixels
+    fn fill_cell(&self, buffer: &mut [u32], width: usize, x: usize, y: usize, color: u32) {
+        for j in 0..self.scale {
+            let start = (y * self.scale + j) * width + x * self.scale;
+            let cell = &mut buffer[start..start + self.scale];
+            cell.fill(color);
+            // grid lines on the right and bottom edge of every cell
+            if let Some(grid) = self.grid.filter(|_| self.scale > 1) {
+                cell[self.scale - 1] = grid;
+                if j == self.scale - 1 {
+                    cell.fill(grid);
+                }
+            }
+        }
+    }
+    // paints one area of the chip-8 screen into a width() x height() buffer
+    pub fn draw_rect(&self, buffer: &mut [u32], chip8: &Chip8, rect: Rect) {
+        for y in rect.y..rect.y + rect.height {
+            for x in rect.x..rect.x + rect.width {
+                let color = if chip8.get_pixel(x as u8, y as u8) { self.foreground } else { self.background };
+                self.fill_cell(buffer, self.width(), x, y, color);
+            }
+        }
+    }
+    pub fn draw(&self, buffer: &mut [u32], chip8: &Chip8) {
+        self.draw_rect(buffer, chip8, Rect { x: 0, y: 0, width: SCREEN_WIDTH, height: SCREEN_HEIGHT });
+    }
+    // a screen of any size into a new buffer, scale times as wide and high
+    pub fn render<const W: usize>(&self, screen: &[[bool; W]]) -> Vec<u32> {
+        let width = W * self.scale;
+        let mut buffer = vec![0; width * screen.len() * self.scale];
+        for (y, row) in screen.iter().enumerate() {
+            for (x, &lit) in row.iter().enumerate() {
+                self.fill_cell(&mut buffer, width, x, y, if lit { self.foreground } else { self.background });
+            }
+        }
+        buffer
+    }
+    // whole screen from filtered brightness levels
+    pub fn draw_levels(&self, buffer: &mut [u32], levels: &Levels) {
+        for (y, row) in levels.iter().enumerate() {
+            for (x, &level) in row.iter().enumerate() {
+                self.fill_cell(buffer, self.width(), x, y, self.shade(level));
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cpu::opcodes::OpCode;
+
+    #[test]
+    fn colors_and_palettes() {
+        assert_eq!(parse_color("#33FF66"), Ok(0x33FF66));
+        assert_eq!(parse_color("0x000000"), Ok(0));
+        assert_eq!(parse_color("ffb000"), Ok(0xFFB000));
+        assert!(parse_color("#FFF").is_err());
+        assert!(parse_color("red").is_err());
+        assert_eq!(palette("Amber"), Some((0xFFB000, 0x1F1200)));
+        let mut config = DisplayConfig::default();
+        assert!(config.set_palette("octo").is_ok());
+        assert_eq!((config.foreground, config.background), (0xFFCC00, 0x996600));
+        assert!(config.set_palette("sepia").unwrap_err().contains("classic, green"));
+    }
+    #[test]
+    fn draw_scaled_with_grid() {
+        let mut chip8 = Chip8::new();
+        // top left pixel on
+        chip8.i_reg = 0x300;
+        chip8.ram.write_byte(0x300, 0x80);
+        chip8.pc = 0x200;
+        for (i, byte) in OpCode::DXYN(0, 0, 1).encode().to_be_bytes().iter().enumerate() {
+            chip8.ram.write_byte(0x200 + i as u16, *byte);
+        }
+        chip8.cycle();
+        let config = DisplayConfig { scale: 4, grid: Some(0x808080), ..Default::default() };
+        assert_eq!((config.width(), config.height()), (256, 128));
+        let mut buffer = vec![0x123456; config.width() * config.height()];
+        config.draw(&mut buffer, &chip8);
+        let at = |x: usize, y: usize| buffer[y * config.width() + x];
+        assert_eq!(at(0, 0), 0xFFFFFF);
+        assert_eq!(at(2, 2), 0xFFFFFF);
+        assert_eq!(at(3, 0), 0x808080);
+        assert_eq!(at(0, 3), 0x808080);
+        assert_eq!(at(4, 0), 0x000000);
+        assert_eq!(at(255, 127), 0x808080);
+        assert!(!buffer.contains(&0x123456));
+        // rendering a copy of the screen gives the same picture
+        assert_eq!(config.render(&chip8.get_screen()), buffer);
+    }
+    #[test]
+    fn grid_color_sits_between() {
+        let config = DisplayConfig::default();
+        assert_eq!(config.default_grid_color(), 0x7F7F7F);
+    }
+    #[test]
+    fn shade_between_colors() {
+        let config = DisplayConfig { foreground: 0xFFB000, background: 0x000000, ..Default::default() };
+        assert_eq!(config.shade(1.0), 0xFFB000);
+        assert_eq!(config.shade(0.0), 0x000000);
+        assert_eq!(config.shade(0.5), 0x805800);
+        let mut levels = [[0.0; SCREEN_WIDTH]; SCREEN_HEIGHT];
+        levels[0][1] = 0.5;
+        let config = DisplayConfig { scale: 1, ..config };
+        let mut buffer = vec![0; config.width() * config.height()];
+        config.draw_levels(&mut buffer, &levels);
+        assert_eq!(buffer[1], 0x805800);
+    }
+}
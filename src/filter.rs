@@ -0,0 +1,179 @@
+// flicker reduction. XOR drawing means sprites are erased and redrawn every frame,
+// so these turn successive screens into a brightness per pixel instead of on/off
+use crate::display::{SCREEN_HEIGHT, SCREEN_WIDTH};
+use crate::Chip8;
+use std::collections::VecDeque;
+use std::str::FromStr;
+
+pub type Levels = [[f32; SCREEN_WIDTH]; SCREEN_HEIGHT];
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Filter {
+    // average of the last n frames
+    Blend(usize),
+    // lit pixels fade out linearly over this many seconds
+    Phosphor(f32),
+    // only present at vblank, showing the finished frame from before the last 00E0
+    Vblank,
+}
+// `blend:3`, `phosphor:150` (milliseconds) or `vblank`
+impl FromStr for Filter {
+    type Err = String;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (name, arg) = match s.split_once(':') {
+            Some((name, arg)) => (name, Some(arg)),
+            None => (s, None),
+        };
+        let number = |default: u32| match arg {
+            Some(arg) => arg.parse::<u32>().ok().filter(|&n| n > 0).ok_or_else(|| format!("invalid filter argument `{}`", arg)),
+            None => Ok(default),
+        };
+        match name {
+            "blend" => Ok(Filter::Blend(number(2)? as usize)),
+            "phosphor" => Ok(Filter::Phosphor(number(150)? as f32 / 1000.0)),
+            "vblank" if arg.is_none() => Ok(Filter::Vblank),
+            _ => Err(format!("unknown filter `{}`, expected blend[:frames], phosphor[:ms] or vblank", s)),
+        }
+    }
+}
+
+pub struct FrameFilter {
+    filter: Filter,
+    history: VecDeque<[[bool; SCREEN_WIDTH]; SCREEN_HEIGHT]>,
+    levels: Levels,
+    last_clear: u32,
+}
+impl FrameFilter {
+    pub fn new(filter: Filter) -> Self {
+        FrameFilter { filter, history: VecDeque::new(), levels: [[0.0; SCREEN_WIDTH]; SCREEN_HEIGHT], last_clear: 0 }
+    }
+    pub fn levels(&self) -> &Levels {
+        &self.levels
+    }
+
+    // feeds the screen at the end of a frame that took `elapsed` seconds, `vblank` is
+    // set on frames that line up with the 60Hz timer tick. returns whether levels changed
+    pub fn update(&mut self, chip8: &Chip8, elapsed: f32, vblank: bool) -> bool {
+        let screen = chip8.get_screen();
+        let mut next = self.levels;
+        match self.filter {
+            Filter::Blend(frames) => {
+                self.history.push_back(screen);
+                while self.history.len() > frames {
+                    self.history.pop_front();
+                }
+                for (y, row) in next.iter_mut().enumerate() {
+                    for (x, level) in row.iter_mut().enumerate() {
+                        let lit = self.history.iter().filter(|frame| frame[y][x]).count();
+                        *level = lit as f32 / frames as f32;
+                    }
+                }
+            }
+            Filter::Phosphor(decay) => {
+                for (row, lit_row) in next.iter_mut().zip(screen.iter()) {
+                    for (level, &lit) in row.iter_mut().zip(lit_row.iter()) {
+                        *level = if lit { 1.0 } else { (*level - elapsed / decay).max(0.0) };
+                    }
+                }
+            }
+            Filter::Vblank => {
+                if !vblank {
+                    return false;
+                }
+                // a clear since the last vblank means a whole frame was drawn before it
+                let clears = chip8.clear_count();
+                let rows = if clears != self.last_clear { *chip8.frame_before_clear() } else { *chip8.screen_rows() };
+                self.last_clear = clears;
+                for (row, bits) in next.iter_mut().zip(rows.iter()) {
+                    for (x, level) in row.iter_mut().enumerate() {
+                        *level = (bits >> (63 - x) & 1) as f32;
+                    }
+                }
+            }
+        }
+        let changed = next != self.levels;
+        self.levels = next;
+        changed
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // a machine with the top left pixel toggled by each cycle
+    fn blinker() -> Chip8 {
+        let mut chip8 = Chip8::new();
+        chip8.pc = 0x200;
+        chip8.i_reg = 0x300;
+        chip8.ram.write_byte(0x300, 0x80);
+        // DRW V0, V0, 1; JP 0x200
+        for (i, byte) in [0xD0, 0x01, 0x12, 0x00].iter().enumerate() {
+            chip8.ram.write_byte(0x200 + i as u16, *byte);
+        }
+        chip8
+    }
+    fn step(chip8: &mut Chip8) {
+        chip8.cycle();
+        chip8.cycle();
+    }
+
+    #[test]
+    fn parse_filters() {
+        assert_eq!("blend".parse(), Ok(Filter::Blend(2)));
+        assert_eq!("blend:4".parse(), Ok(Filter::Blend(4)));
+        assert_eq!("phosphor:200".parse(), Ok(Filter::Phosphor(0.2)));
+        assert_eq!("vblank".parse(), Ok(Filter::Vblank));
+        assert!("blend:0".parse::<Filter>().is_err());
+        assert!("vblank:2".parse::<Filter>().is_err());
+        assert!("crt".parse::<Filter>().is_err());
+    }
+    #[test]
+    fn blend_averages_frames() {
+        let mut chip8 = blinker();
+        let mut filter = FrameFilter::new(Filter::Blend(2));
+        step(&mut chip8);
+        assert!(filter.update(&chip8, 0.0, false));
+        assert_eq!(filter.levels()[0][0], 0.5);
+        step(&mut chip8);
+        filter.update(&chip8, 0.0, false);
+        assert_eq!(filter.levels()[0][0], 0.5);
+        assert_eq!(filter.levels()[0][1], 0.0);
+    }
+    #[test]
+    fn phosphor_fades() {
+        let mut chip8 = blinker();
+        let mut filter = FrameFilter::new(Filter::Phosphor(0.1));
+        step(&mut chip8);
+        filter.update(&chip8, 0.025, false);
+        assert_eq!(filter.levels()[0][0], 1.0);
+        step(&mut chip8);
+        filter.update(&chip8, 0.025, false);
+        assert_eq!(filter.levels()[0][0], 0.75);
+        for _ in 0..3 {
+            filter.update(&chip8, 0.025, false);
+        }
+        assert_eq!(filter.levels()[0][0], 0.0);
+        assert!(!filter.update(&chip8, 0.025, false));
+    }
+    #[test]
+    fn vblank_shows_frame_before_clear() {
+        let mut chip8 = Chip8::new();
+        chip8.pc = 0x200;
+        chip8.i_reg = 0x300;
+        chip8.ram.write_byte(0x300, 0x80);
+        // DRW V0, V0, 1; CLS
+        for (i, byte) in [0xD0, 0x01, 0x00, 0xE0].iter().enumerate() {
+            chip8.ram.write_byte(0x200 + i as u16, *byte);
+        }
+        let mut filter = FrameFilter::new(Filter::Vblank);
+        chip8.cycle();
+        assert!(!filter.update(&chip8, 0.0, false));
+        chip8.cycle();
+        assert!(filter.update(&chip8, 0.0, true));
+        assert_eq!(filter.levels()[0][0], 1.0);
+        // no clear since, so the (empty) live screen is shown
+        filter.update(&chip8, 0.0, true);
+        assert_eq!(filter.levels()[0][0], 0.0);
+    }
+}
@@ -0,0 +1,535 @@
+// GDB remote serial protocol stub so a debugger front-end can attach over tcp.
+// registers are numbered V0-VF (0-15), I (16), PC (17), SP (18), DT (19), ST (20)
+// and sent little-endian, matching the target description below.
+use crate::cpu::debugger::{Access, BreakReason, WatchKind};
+use crate::Chip8;
+use std::io::{self, ErrorKind, Read, Write};
+use std::net::{TcpListener, TcpStream, ToSocketAddrs};
+
+const REGISTER_COUNT: usize = 21;
+const MEMORY_SIZE: u32 = 0x1000;
+
+const TARGET_XML: &str = r#"<?xml version="1.0"?>
+<!DOCTYPE target SYSTEM "gdb-target.dtd">
+<target version="1.0">
+  <feature name="org.chip8.core">
+    <reg name="v0" bitsize="8" type="uint8" regnum="0"/>
+    <reg name="v1" bitsize="8" type="uint8"/>
+    <reg name="v2" bitsize="8" type="uint8"/>
+    <reg name="v3" bitsize="8" type="uint8"/>
+    <reg name="v4" bitsize="8" type="uint8"/>
+    <reg name="v5" bitsize="8" type="uint8"/>
+    <reg name="v6" bitsize="8" type="uint8"/>
+    <reg name="v7" bitsize="8" type="uint8"/>
+    <reg name="v8" bitsize="8" type="uint8"/>
+    <reg name="v9" bitsize="8" type="uint8"/>
+    <reg name="va" bitsize="8" type="uint8"/>
+    <reg name="vb" bitsize="8" type="uint8"/>
+    <reg name="vc" bitsize="8" type="uint8"/>
+    <reg name="vd" bitsize="8" type="uint8"/>
+    <reg name="ve" bitsize="8" type="uint8"/>
+    <reg name="vf" bitsize="8" type="uint8"/>
+    <reg name="i" bitsize="16" type="data_ptr"/>
+    <reg name="pc" bitsize="16" type="code_ptr"/>
+    <reg name="sp" bitsize="8" type="uint8"/>
+    <reg name="dt" bitsize="8" type="uint8"/>
+    <reg name="st" bitsize="8" type="uint8"/>
+  </feature>
+</target>
+"#;
+
+fn register_size(reg: usize) -> usize {
+    match reg {
+        16 | 17 => 2,
+        _ => 1,
+    }
+}
+fn read_register(chip8: &Chip8, reg: usize) -> Option<u16> {
+    let val = match reg {
+        0..=15 => chip8.v_registers[reg] as u16,
+        16 => chip8.i_reg,
+        17 => chip8.pc,
+        18 => chip8.stack_pointer() as u16,
+        19 => chip8.delay_timer() as u16,
+        20 => chip8.sound_timer() as u16,
+        _ => return None,
+    };
+    Some(val)
+}
+fn write_register(chip8: &mut Chip8, reg: usize, val: u16) -> bool {
+    match reg {
+        0..=15 => chip8.v_registers[reg] = val as u8,
+        16 => chip8.i_reg = val & 0xFFF,
+        17 => chip8.pc = val & 0xFFF,
+        18 => chip8.set_stack_pointer(val as u8),
+        19 => chip8.set_delay_timer(val as u8),
+        20 => chip8.set_sound_timer(val as u8),
+        _ => return false,
+    }
+    true
+}
+fn encode_register(out: &mut String, reg: usize, val: u16) {
+    for byte in 0..register_size(reg) {
+        out.push_str(&format!("{:02x}", (val >> (8 * byte)) as u8));
+    }
+}
+fn decode_hex_bytes(hex: &str) -> Option<Vec<u8>> {
+    if !hex.len().is_multiple_of(2) {
+        return None;
+    }
+    (0..hex.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(hex.get(i..i + 2)?, 16).ok())
+        .collect()
+}
+fn decode_register(reg: usize, hex: &str) -> Option<u16> {
+    let bytes = decode_hex_bytes(hex)?;
+    if bytes.len() != register_size(reg) {
+        return None;
+    }
+    Some(bytes.iter().rev().fold(0, |acc, &b| acc << 8 | b as u16))
+}
+fn parse_hex(s: &str) -> Option<u32> {
+    u32::from_str_radix(s, 16).ok()
+}
+// splits "addr,len" style arguments
+fn parse_pair(s: &str) -> Option<(u32, u32)> {
+    let (a, b) = s.split_once(',')?;
+    Some((parse_hex(a)?, parse_hex(b)?))
+}
+// addr..addr + len is inside memory, without adding the two up so huge lengths can't wrap
+fn in_memory(addr: u32, len: u32) -> bool {
+    addr <= MEMORY_SIZE && len <= MEMORY_SIZE - addr
+}
+
+pub fn checksum(data: &str) -> u8 {
+    data.bytes().fold(0u8, |acc, b| acc.wrapping_add(b))
+}
+pub fn frame_packet(data: &str) -> String {
+    format!("${}#{:02x}", data, checksum(data))
+}
+
+// what the stub wants the caller to do after a packet
+#[derive(Debug, PartialEq)]
+pub enum Action {
+    Reply(String),
+    // run one instruction then report the stop
+    Step,
+    // let the cpu run until it hits a break or gets interrupted
+    Continue,
+    Detach,
+}
+
+pub fn stop_reply(reason: Option<&BreakReason>) -> String {
+    match reason {
+        Some(BreakReason::Watchpoint { address, access, .. }) => {
+            let kind = match access {
+                Access::Read => "rwatch",
+                Access::Write => "watch",
+            };
+            format!("T05{}:{:x};", kind, address)
+        }
+        Some(BreakReason::Breakpoint(_)) => "T05swbreak:;".to_string(),
+        // reported as SIGSEGV, the closest thing gdb has to a bad stack
+        Some(BreakReason::Fault(_)) => "S0b".to_string(),
+        _ => "S05".to_string(),
+    }
+}
+
+// handles one packet body (without `$` and checksum)
+pub fn handle_packet(chip8: &mut Chip8, packet: &str) -> Action {
+    let reply = |s: &str| Action::Reply(s.to_string());
+    let mut chars = packet.chars();
+    let command = match chars.next() {
+        Some(c) => c,
+        None => return reply(""),
+    };
+    let args = chars.as_str();
+    match command {
+        '?' => reply("S05"),
+        'g' => {
+            let mut out = String::new();
+            for reg in 0..REGISTER_COUNT {
+                encode_register(&mut out, reg, read_register(chip8, reg).unwrap());
+            }
+            Action::Reply(out)
+        }
+        'G' => {
+            let mut rest = args;
+            for reg in 0..REGISTER_COUNT {
+                let len = register_size(reg) * 2;
+                let val = match rest.get(..len).and_then(|hex| decode_register(reg, hex)) {
+                    Some(val) => val,
+                    None => return reply("E01"),
+                };
+                write_register(chip8, reg, val);
+                rest = &rest[len..];
+            }
+            reply("OK")
+        }
+        'p' => match parse_hex(args).and_then(|reg| read_register(chip8, reg as usize).map(|v| (reg, v))) {
+            Some((reg, val)) => {
+                let mut out = String::new();
+                encode_register(&mut out, reg as usize, val);
+                Action::Reply(out)
+            }
+            None => reply("E01"),
+        },
+        'P' => {
+            let parsed = args.split_once('=').and_then(|(reg, val)| {
+                let reg = parse_hex(reg)? as usize;
+                Some((reg, decode_register(reg, val)?))
+            });
+            match parsed {
+                Some((reg, val)) if write_register(chip8, reg, val) => reply("OK"),
+                _ => reply("E01"),
+            }
+        }
+        'm' => match parse_pair(args) {
+            Some((addr, len)) if in_memory(addr, len) => {
+                let out = (addr..addr + len)
+                    .map(|a| format!("{:02x}", chip8.ram.read_byte(a as u16)))
+                    .collect();
+                Action::Reply(out)
+            }
+            _ => reply("E01"),
+        },
+        'M' => {
+            let parsed = args.split_once(':').and_then(|(range, data)| {
+                let (addr, len) = parse_pair(range)?;
+                let bytes = decode_hex_bytes(data)?;
+                (bytes.len() as u32 == len && in_memory(addr, len)).then_some((addr, bytes))
+            });
+            match parsed {
+                Some((addr, bytes)) => {
+                    for (offset, byte) in bytes.into_iter().enumerate() {
+                        chip8.debug_write_byte(addr as u16 + offset as u16, byte);
+                    }
+                    reply("OK")
+                }
+                None => reply("E01"),
+            }
+        }
+        'Z' | 'z' => {
+            // Ztype,addr,kind
+            let mut parts = args.split(',');
+            let kind = parts.next();
+            let addr = parts.next().and_then(parse_hex);
+            let len = parts.next().and_then(parse_hex).unwrap_or(1).max(1);
+            let watch = match kind {
+                Some("0") | Some("1") => None,
+                Some("2") => Some(WatchKind::Write),
+                Some("3") => Some(WatchKind::Read),
+                Some("4") => Some(WatchKind::ReadWrite),
+                _ => return reply(""),
+            };
+            // a breakpoint's kind is the instruction size, only watchpoints cover a range
+            let len = if watch.is_some() { len } else { 1 };
+            let addr = match addr {
+                Some(addr) if in_memory(addr, len) => addr as u16,
+                _ => return reply("E01"),
+            };
+            let end = addr + (len - 1) as u16;
+            match (command, watch) {
+                ('Z', None) => chip8.debugger.add_breakpoint(addr),
+                ('z', None) => chip8.debugger.remove_breakpoint(addr),
+                ('Z', Some(kind)) => chip8.debugger.add_watchpoint(addr, end, kind),
+                (_, Some(_)) => chip8.debugger.remove_watchpoint(addr, end),
+                _ => unreachable!(),
+            }
+            reply("OK")
+        }
+        's' | 'c' => {
+            if let Some(addr) = parse_hex(args) {
+                chip8.pc = addr as u16 & 0xFFF;
+            }
+            if command == 's' {
+                Action::Step
+            } else {
+                Action::Continue
+            }
+        }
+        'H' => reply("OK"),
+        'D' => Action::Detach,
+        'k' => Action::Detach,
+        'q' => {
+            if args.starts_with("Supported") {
+                reply("PacketSize=1000;qXfer:features:read+;swbreak+")
+            } else if args == "Attached" {
+                reply("1")
+            } else if args == "C" {
+                reply("QC1")
+            } else if args == "fThreadInfo" {
+                reply("m1")
+            } else if args == "sThreadInfo" {
+                reply("l")
+            } else if let Some(request) = args.strip_prefix("Xfer:features:read:target.xml:") {
+                match parse_pair(request) {
+                    Some((offset, len)) => Action::Reply(xfer_chunk(TARGET_XML, offset as usize, len as usize)),
+                    None => reply("E01"),
+                }
+            } else {
+                reply("")
+            }
+        }
+        _ => reply(""),
+    }
+}
+
+// qXfer replies are prefixed with `m` when more data follows and `l` for the last chunk
+fn xfer_chunk(doc: &str, offset: usize, len: usize) -> String {
+    if offset >= doc.len() {
+        return "l".to_string();
+    }
+    let end = offset + len.min(doc.len() - offset);
+    let marker = if end == doc.len() { 'l' } else { 'm' };
+    format!("{}{}", marker, &doc[offset..end])
+}
+
+pub struct GdbServer {
+    listener: TcpListener,
+    stream: Option<TcpStream>,
+    buffer: Vec<u8>,
+    // cpu only runs while the client has asked it to continue
+    running: bool,
+}
+impl GdbServer {
+    pub fn bind<A: ToSocketAddrs>(addr: A) -> io::Result<GdbServer> {
+        let listener = TcpListener::bind(addr)?;
+        listener.set_nonblocking(true)?;
+        Ok(GdbServer { listener, stream: None, buffer: Vec::new(), running: true })
+    }
+    pub fn local_port(&self) -> io::Result<u16> {
+        Ok(self.listener.local_addr()?.port())
+    }
+    // the frontend should only cycle the cpu while this is true
+    pub fn is_running(&self) -> bool {
+        self.running
+    }
+
+    // call once per frame, accepts a client and handles any packets that arrived
+    pub fn poll(&mut self, chip8: &mut Chip8) -> io::Result<()> {
+        if self.stream.is_none() {
+            match self.listener.accept() {
+                Ok((stream, _)) => {
+                    stream.set_nonblocking(true)?;
+                    stream.set_nodelay(true)?;
+                    self.stream = Some(stream);
+                    self.buffer.clear();
+                    // gdb expects the target to be halted once it attaches
+                    self.running = false;
+                }
+                Err(e) if e.kind() == ErrorKind::WouldBlock => return Ok(()),
+                Err(e) => return Err(e),
+            }
+        }
+        let mut chunk = [0; 1024];
+        loop {
+            let stream = match self.stream.as_mut() {
+                Some(stream) => stream,
+                None => return Ok(()),
+            };
+            match stream.read(&mut chunk) {
+                Ok(0) => {
+                    self.disconnect();
+                    return Ok(());
+                }
+                Ok(n) => self.buffer.extend_from_slice(&chunk[..n]),
+                Err(e) if e.kind() == ErrorKind::WouldBlock => break,
+                Err(e) => return Err(e),
+            }
+        }
+        while let Some(packet) = self.next_packet()? {
+            self.dispatch(chip8, &packet)?;
+        }
+        Ok(())
+    }
+
+    // tell the client the cpu stopped while running
+    pub fn report_stop(&mut self, reason: &BreakReason) -> io::Result<()> {
+        if self.stream.is_some() && self.running {
+            self.running = false;
+            self.send(&stop_reply(Some(reason)))?;
+        }
+        Ok(())
+    }
+
+    fn disconnect(&mut self) {
+        self.stream = None;
+        self.buffer.clear();
+        self.running = true;
+    }
+
+    // pulls the next complete packet out of the buffer, handling acks and interrupts
+    fn next_packet(&mut self) -> io::Result<Option<String>> {
+        loop {
+            match self.buffer.first() {
+                None => return Ok(None),
+                Some(b'+') | Some(b'-') => {
+                    self.buffer.remove(0);
+                }
+                Some(0x03) => {
+                    self.buffer.remove(0);
+                    if self.running {
+                        self.running = false;
+                        self.send("S02")?;
+                    }
+                }
+                Some(b'$') => {
+                    let end = match self.buffer.iter().position(|&b| b == b'#') {
+                        Some(end) if self.buffer.len() >= end + 3 => end,
+                        _ => return Ok(None),
+                    };
+                    let body = String::from_utf8_lossy(&self.buffer[1..end]).into_owned();
+                    let sum = std::str::from_utf8(&self.buffer[end + 1..end + 3])
+                        .ok()
+                        .and_then(|s| u8::from_str_radix(s, 16).ok());
+                    self.buffer.drain(..end + 3);
+                    if sum == Some(checksum(&body)) {
+                        self.write_raw(b"+")?;
+                        return Ok(Some(body));
+                    }
+                    self.write_raw(b"-")?;
+                }
+                Some(_) => {
+                    self.buffer.remove(0);
+                }
+            }
+        }
+    }
+
+    fn dispatch(&mut self, chip8: &mut Chip8, packet: &str) -> io::Result<()> {
+        match handle_packet(chip8, packet) {
+            Action::Reply(reply) => self.send(&reply),
+            Action::Step => {
+                let reason = chip8.cycle();
+                self.send(&stop_reply(reason.as_ref()))
+            }
+            Action::Continue => {
+                self.running = true;
+                Ok(())
+            }
+            Action::Detach => {
+                self.send("OK")?;
+                self.disconnect();
+                Ok(())
+            }
+        }
+    }
+
+    fn send(&mut self, data: &str) -> io::Result<()> {
+        self.write_raw(frame_packet(data).as_bytes())
+    }
+    fn write_raw(&mut self, bytes: &[u8]) -> io::Result<()> {
+        if let Some(stream) = self.stream.as_mut() {
+            stream.set_nonblocking(false)?;
+            stream.write_all(bytes)?;
+            stream.set_nonblocking(true)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cpu::quirks::Platform;
+
+    fn reply(chip8: &mut Chip8, packet: &str) -> String {
+        match handle_packet(chip8, packet) {
+            Action::Reply(s) => s,
+            other => panic!("expected reply, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn packet_framing() {
+        assert_eq!(frame_packet("OK"), "$OK#9a");
+        assert_eq!(frame_packet(""), "$#00");
+    }
+    #[test]
+    fn read_registers() {
+        let mut chip8 = Chip8::new();
+        chip8.v_registers[0] = 0x12;
+        chip8.v_registers[0xF] = 0x01;
+        chip8.i_reg = 0x0ABC;
+        chip8.pc = 0x0200;
+        chip8.set_delay_timer(0x3C);
+        let regs = reply(&mut chip8, "g");
+        assert_eq!(regs.len(), (16 + 2 + 2 + 3) * 2);
+        assert_eq!(&regs[0..2], "12");
+        assert_eq!(&regs[30..32], "01");
+        assert_eq!(&regs[32..36], "bc0a");
+        assert_eq!(&regs[36..40], "0002");
+        assert_eq!(&regs[42..44], "3c");
+        assert_eq!(reply(&mut chip8, "p11"), "0002");
+    }
+    #[test]
+    fn write_registers() {
+        let mut chip8 = Chip8::new();
+        assert_eq!(reply(&mut chip8, "P3=7f"), "OK");
+        assert_eq!(chip8.v_registers[3], 0x7F);
+        assert_eq!(reply(&mut chip8, "P11=3402"), "OK");
+        assert_eq!(chip8.pc, 0x234);
+        assert_eq!(reply(&mut chip8, "P15=00"), "E01");
+        let all = "01".repeat(16) + "0003" + "0002" + "00" + "05" + "06";
+        assert_eq!(reply(&mut chip8, &format!("G{}", all)), "OK");
+        assert_eq!(chip8.v_registers, [1; 16]);
+        assert_eq!(chip8.i_reg, 0x300);
+        assert_eq!(chip8.sound_timer(), 6);
+    }
+    #[test]
+    fn read_write_memory() {
+        let mut chip8 = Chip8::new();
+        assert_eq!(reply(&mut chip8, "m0,2"), "f090");
+        assert_eq!(reply(&mut chip8, "M300,3:aabbcc"), "OK");
+        assert_eq!(chip8.ram.read_byte(0x302), 0xCC);
+        assert_eq!(reply(&mut chip8, "m300,3"), "aabbcc");
+        assert_eq!(reply(&mut chip8, "mfff,2"), "E01");
+        // lengths that would wrap if added to the address
+        assert_eq!(reply(&mut chip8, "m200,ffffffff"), "E01");
+        assert_eq!(reply(&mut chip8, "mffffffff,1"), "E01");
+        assert_eq!(reply(&mut chip8, "M200,ffffffff:aa"), "E01");
+    }
+    #[test]
+    fn memory_writes_reach_a_screen_kept_in_memory() {
+        let mut chip8 = Chip8::new();
+        chip8.set_layout(Platform::HybridVip.layout()).unwrap();
+        assert_eq!(reply(&mut chip8, "Mf08,2:8001"), "OK");
+        assert_eq!(chip8.screen_rows()[1], 0x8001 << 48);
+        assert_eq!(reply(&mut chip8, "mf08,2"), "8001");
+    }
+    #[test]
+    fn breakpoints_and_watchpoints() {
+        let mut chip8 = Chip8::new();
+        assert_eq!(reply(&mut chip8, "Z0,204,2"), "OK");
+        assert_eq!(chip8.debugger.breakpoints(), &[0x204]);
+        assert_eq!(reply(&mut chip8, "z0,204,2"), "OK");
+        assert!(chip8.debugger.breakpoints().is_empty());
+        assert_eq!(reply(&mut chip8, "Z2,300,4"), "OK");
+        assert_eq!(chip8.debugger.watchpoints()[0].end, 0x303);
+        assert_eq!(reply(&mut chip8, "Z2,300,ffffffff"), "E01");
+        assert_eq!(reply(&mut chip8, "Z2,ffe,4"), "E01");
+        assert_eq!(reply(&mut chip8, "Z0,1000,2"), "E01");
+        assert_eq!(reply(&mut chip8, "Z0,ffe,2"), "OK");
+    }
+    #[test]
+    fn target_description() {
+        let mut chip8 = Chip8::new();
+        assert!(reply(&mut chip8, "qSupported:xmlRegisters=i386").contains("qXfer:features:read+"));
+        let first = reply(&mut chip8, "qXfer:features:read:target.xml:0,a");
+        assert_eq!(first, "m<?xml vers");
+        let all = reply(&mut chip8, "qXfer:features:read:target.xml:0,1000");
+        assert!(all.starts_with('l'));
+        assert!(all.contains("name=\"vf\""));
+        assert_eq!(reply(&mut chip8, "qXfer:features:read:target.xml:10,ffffffffffffffff"), "E01");
+        assert!(reply(&mut chip8, "qXfer:features:read:target.xml:10,ffffffff").starts_with('l'));
+    }
+    #[test]
+    fn step_and_continue() {
+        let mut chip8 = Chip8::new();
+        assert_eq!(handle_packet(&mut chip8, "s"), Action::Step);
+        assert_eq!(handle_packet(&mut chip8, "c200"), Action::Continue);
+        assert_eq!(chip8.pc, 0x200);
+    }
+}
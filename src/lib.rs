@@ -0,0 +1,17 @@
+pub mod asm;
+pub mod audio;
+pub mod capture;
+pub mod config;
+pub mod cpu;
+pub mod crt;
+pub mod difftest;
+pub mod disasm;
+pub mod display;
+pub mod filter;
+pub mod gdb;
+pub mod rom;
+pub mod romdb;
+pub mod scheduler;
+pub mod screenshot;
+pub mod terminal;
+pub use cpu::Chip8;
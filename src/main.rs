@@ -1,6 +1,20 @@
 #![allow(unused)]
-mod cpu;
-use cpu::Chip8;
+use chip_8::audio::{AudioConfig, Beeper};
+use chip_8::capture::{Recorder, CAPTURE_FPS};
+use chip_8::config::{self, Config, DisplaySection, MachineSection};
+use chip_8::cpu::opcodes::OpCode;
+use chip_8::cpu::quirks::Platform;
+use chip_8::cpu::trace::{OpClass, TraceFormat, Tracer};
+use chip_8::crt::Crt;
+use chip_8::disasm::Item;
+use chip_8::display::DisplayConfig;
+use chip_8::filter::FrameFilter;
+use chip_8::gdb::GdbServer;
+use chip_8::scheduler::{Controls, Scheduler, DEFAULT_SPEED, FRAMES_PER_TIMER_TICK, UPDATE_RATE};
+use chip_8::terminal::{self, Glyphs};
+use chip_8::rom;
+use chip_8::romdb::{self, RomInfo, Source};
+use chip_8::{asm, difftest, disasm, screenshot, Chip8};
 // fn main() {
 //     let mut chip8 = Chip8::new();
 //     chip8.load_program("1-chip8-logo.ch8");
@@ -10,68 +24,656 @@ use cpu::Chip8;
 //     //let op_code = chip8.decode(0x00E0);
 //     //println!("{:?}", op_code)
 // }
+use clap::{Args, Parser, Subcommand};
 use minifb::{Key, KeyRepeat, Window, WindowOptions};
-use std::env;
-const WIDTH: usize = 1280;
-const HEIGHT: usize = 640;
-const BACKGROUND_COLOR: u32 = 0x0;
-const ACTIVE_COLOR: u32 = 0xFFFFFFFF;
-const SCALE: usize = 20;
+use std::fmt::Display;
+use std::fs;
+use std::path::{Path, PathBuf};
 
-const FPS:f32 = 240.0;
-const UPDATE_RATE:f32 = 1.0 / FPS;
-const UPDATE_RATE_MS:f32 = UPDATE_RATE * 1000.0;
+// the keys configured as characters, by default the hex keypad laid out on the left
+// of a qwerty keyboard
+//   1 2 3 C      1 2 3 4
+//   4 5 6 D      Q W E R
+//   7 8 9 E  ->  A S D F
+//   A 0 B F      Z X C V
+fn host_key(c: char) -> Option<Key> {
+    const DIGITS: [Key; 10] =
+        [Key::Key0, Key::Key1, Key::Key2, Key::Key3, Key::Key4, Key::Key5, Key::Key6, Key::Key7, Key::Key8, Key::Key9];
+    const LETTERS: [Key; 26] = [
+        Key::A, Key::B, Key::C, Key::D, Key::E, Key::F, Key::G, Key::H, Key::I, Key::J, Key::K, Key::L, Key::M,
+        Key::N, Key::O, Key::P, Key::Q, Key::R, Key::S, Key::T, Key::U, Key::V, Key::W, Key::X, Key::Y, Key::Z,
+    ];
+    match c {
+        '0'..='9' => Some(DIGITS[c as usize - '0' as usize]),
+        'a'..='z' => Some(LETTERS[c as usize - 'a' as usize]),
+        _ => None,
+    }
+}
 
-fn main() {
-    let mut fake_screen: [[bool; 64]; 32] = [[false; 64]; 32];
-    for y in 0..32 {
-        for x in 0..64 {
-            let pix = y % 2 == 0;
-            fake_screen[y][x] = pix;
+#[derive(Parser)]
+#[command(name = "chip-8", version, about = "CHIP-8 interpreter, debugger and assembler")]
+struct Cli {
+    #[command(subcommand)]
+    command: Command,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Run a rom in a window, or in the terminal with --terminal
+    Run {
+        /// A rom file, a .zip with one rom in it, or - for stdin
+        rom: PathBuf,
+        #[command(flatten)]
+        machine: MachineArgs,
+        #[command(flatten)]
+        display: DisplayArgs,
+        #[command(flatten)]
+        trace: TraceArgs,
+        /// Start a gdb remote stub on localhost
+        #[arg(long, value_name = "PORT", num_args = 0..=1, default_missing_value = "1234")]
+        gdb: Option<u16>,
+        /// Draw in the terminal with half blocks or braille instead of opening a window
+        #[arg(long, value_name = "GLYPHS", num_args = 0..=1, default_missing_value = "half")]
+        terminal: Option<Glyphs>,
+        /// Record from the start to a .gif or a directory of pngs (F9/F10 start and stop otherwise)
+        #[arg(long, value_name = "PATH")]
+        capture: Option<PathBuf>,
+        /// Save state file F6 writes and F7 reads back, the rom's name with .state by default
+        #[arg(long, value_name = "FILE")]
+        state: Option<PathBuf>,
+        /// Start from a save state instead of the beginning of the rom
+        #[arg(long, value_name = "FILE")]
+        load_state: Option<PathBuf>,
+    },
+    /// Run without a window for a number of 60Hz frames
+    Headless {
+        /// A rom file, a .zip with one rom in it, or - for stdin
+        rom: PathBuf,
+        /// 60Hz frames to run for
+        #[arg(long)]
+        frames: u32,
+        #[command(flatten)]
+        machine: MachineArgs,
+        #[command(flatten)]
+        display: DisplayArgs,
+        #[command(flatten)]
+        trace: TraceArgs,
+        /// Save the screen at the end as .png, .pbm or .pgm
+        #[arg(long, value_name = "FILE")]
+        screenshot: Option<PathBuf>,
+        /// Record every frame to a .gif or a directory of pngs
+        #[arg(long, value_name = "PATH")]
+        capture: Option<PathBuf>,
+        /// Start from a save state instead of the beginning of the rom
+        #[arg(long, value_name = "FILE")]
+        load_state: Option<PathBuf>,
+        /// Save the state at the end
+        #[arg(long, value_name = "FILE")]
+        save_state: Option<PathBuf>,
+    },
+    /// Print a listing of a rom
+    Disasm {
+        /// A rom file, a .zip with one rom in it, or - for stdin
+        rom: PathBuf,
+        /// Hex address the rom is loaded at
+        #[arg(long, value_name = "HEX", value_parser = parse_address, default_value = "200")]
+        load_address: u16,
+    },
+    /// Assemble a source file into a rom
+    Asm {
+        source: PathBuf,
+        /// Where to write the rom, next to the source by default
+        #[arg(short, long)]
+        output: Option<PathBuf>,
+        /// Use the Octo syntax, the default for .8o files
+        #[arg(long)]
+        octo: bool,
+    },
+    /// Show what's in a rom
+    Info {
+        /// A rom file, a .zip with one rom in it, or - for stdin
+        rom: PathBuf,
+        /// Hex address the rom is loaded at
+        #[arg(long, value_name = "HEX", value_parser = parse_address, default_value = "200")]
+        load_address: u16,
+    },
+    /// Write a trace of the first steps of a rom, to check later runs against
+    Record {
+        rom: PathBuf,
+        trace: PathBuf,
+        #[arg(long, default_value_t = 500)]
+        steps: usize,
+    },
+    /// Check a rom against a trace written by record
+    Difftest {
+        rom: PathBuf,
+        trace: PathBuf,
+        /// Run this many instructions at a time through the block cache
+        #[arg(long, value_name = "N")]
+        blocks: Option<usize>,
+    },
+}
+
+#[derive(Args)]
+struct MachineArgs {
+    /// Settings file, instead of chip8.toml here or chip-8/config.toml in the user config directory
+    #[arg(long, value_name = "FILE")]
+    config: Option<PathBuf>,
+    /// Quirks and memory layout of the interpreter the rom was written for: cosmac, schip, eti660 or hybrid
+    #[arg(long)]
+    platform: Option<Platform>,
+    /// Hex address the rom is loaded at, 200 unless the platform or config says otherwise
+    #[arg(long, value_name = "HEX", value_parser = parse_address)]
+    load_address: Option<u16>,
+    /// Hex address of the first instruction, the load address unless set
+    #[arg(long, value_name = "HEX", value_parser = parse_address)]
+    entry: Option<u16>,
+    /// Instructions per 60Hz frame, 4 unless the config says otherwise
+    #[arg(long)]
+    speed: Option<u32>,
+    /// Seed for the random number instruction, so runs repeat exactly
+    #[arg(long)]
+    seed: Option<u64>,
+}
+
+#[derive(Args)]
+struct DisplayArgs {
+    /// Window pixels per chip-8 pixel
+    #[arg(long)]
+    scale: Option<usize>,
+    /// classic, green, amber, octo or lcd
+    #[arg(long)]
+    palette: Option<String>,
+    /// Foreground color as RRGGBB
+    #[arg(long, value_name = "COLOR")]
+    fg: Option<String>,
+    /// Background color as RRGGBB
+    #[arg(long, value_name = "COLOR")]
+    bg: Option<String>,
+    /// Draw lines between pixels
+    #[arg(long)]
+    grid: bool,
+    #[arg(long, value_name = "COLOR")]
+    grid_color: Option<String>,
+    /// Flicker reduction: blend[:frames], phosphor[:ms] or vblank
+    #[arg(long)]
+    filter: Option<String>,
+    /// CRT passes in order, e.g. scanlines,bloom:8:0.5,curvature,vignette
+    #[arg(long, value_name = "PASSES")]
+    crt: Option<String>,
+}
+
+#[derive(Args)]
+struct TraceArgs {
+    /// Log every instruction to a file
+    #[arg(long, value_name = "FILE")]
+    trace: Option<String>,
+    /// delta or state
+    #[arg(long, value_name = "FORMAT", default_value = "delta")]
+    trace_format: TraceFormat,
+    /// Only trace addresses in a hex range like 200-2FF
+    #[arg(long, value_name = "RANGE")]
+    trace_range: Option<String>,
+    /// Only trace these kinds of instruction, e.g. draw,flow
+    #[arg(long, value_name = "CLASSES", value_delimiter = ',')]
+    trace_ops: Vec<OpClass>,
+}
+
+// 600 or 0x600
+fn parse_address(s: &str) -> Result<u16, String> {
+    let hex = s.strip_prefix("0x").or_else(|| s.strip_prefix("0X")).unwrap_or(s);
+    match u16::from_str_radix(hex, 16) {
+        Ok(address) if address <= 0xFFF => Ok(address),
+        _ => Err(format!("`{}` should be a hex address from 0 to FFF", s)),
+    }
+}
+
+fn exit_with(e: impl Display) -> ! {
+    eprintln!("{}", e);
+    std::process::exit(1);
+}
+
+fn tracer(args: &TraceArgs) -> Result<Option<Tracer>, String> {
+    let Some(path) = &args.trace else { return Ok(None) };
+    let mut tracer = Tracer::to_file(path, args.trace_format).map_err(|e| format!("{}: {}", path, e))?;
+    if let Some(range) = &args.trace_range {
+        let parse = |s: &str| u16::from_str_radix(s, 16).ok();
+        tracer.address_range = match range.split_once('-') {
+            Some((start, end)) => parse(start).zip(parse(end)),
+            None => None,
+        };
+        if tracer.address_range.is_none() {
+            return Err(format!("trace range `{}` should be hex addresses like 200-2FF", range));
         }
     }
-    let args: Vec<String> = env::args().collect();
+    tracer.classes = args.trace_ops.clone();
+    Ok(Some(tracer))
+}
+
+// the first settings file found of --config, ./chip8.toml and the user's
+fn config(path: Option<&Path>) -> Result<Config, String> {
+    if let Some(path) = path {
+        return Config::load(path);
+    }
+    let found = [Some(PathBuf::from("chip8.toml")), config::user_config_path()].into_iter().flatten().find(|path| path.exists());
+    found.map_or_else(|| Ok(Config::default()), |path| Config::load(&path))
+}
+
+// a machine with the rom loaded and nothing else set up
+fn load_rom(rom: &Path) -> Result<Chip8, String> {
+    let bytes = rom::read(rom)?;
     let mut chip8 = Chip8::new();
-    chip8.load_program(&args[1]);
-    chip8.pc = 0x200;
-    let mut fake_screen = chip8.get_screen();
-    let mut buffer: Vec<u32> = vec![0; WIDTH * HEIGHT];
+    chip8.load_rom_bytes(&bytes).map_err(|e| format!("{}: {}", rom.display(), e))?;
+    Ok(chip8)
+}
+
+// everything a frontend needs to run a rom
+struct Loaded {
+    chip8: Chip8,
+    display: DisplayConfig,
+    audio: AudioConfig,
+    keys: [char; 16],
+    // chip-8 keys the arrow keys press, from the rom database
+    arrows: [Option<u8>; 4],
+    speed: u32,
+    // from the rom database or a guess at the platform
+    detected: Option<RomInfo>,
+}
 
-    let mut window = Window::new("Chip8 Interpreter", WIDTH, HEIGHT, WindowOptions::default())
+// defaults, then the config file, what the rom database or the code says about the rom,
+// the rom's own table in the config file and last command line flags
+fn load(rom: &Path, machine: &MachineArgs, display_args: &DisplayArgs) -> Result<Loaded, String> {
+    let bytes = rom::read(rom)?;
+    let config = config(machine.config.as_deref())?;
+    let flags = MachineSection {
+        speed: machine.speed,
+        platform: machine.platform.map(|platform| platform.name().to_string()),
+        load_address: machine.load_address,
+        entry: machine.entry,
+        ..MachineSection::default()
+    };
+    // the code is followed from wherever the rom is going to be loaded, as far as that's
+    // known before looking at the rom
+    let origin = config.for_rom(&bytes, None).machine.overridden_by(&flags).layout()?.load_address;
+    let detected = romdb::identify(&bytes, origin);
+    let config = config.for_rom(&bytes, detected.as_ref().map(RomInfo::settings).as_ref());
+    let settings = config.machine.overridden_by(&flags);
+    // checked against the platform it's going to run as, chosen or detected
+    let platform = match &settings.platform {
+        Some(name) => Some(name.parse::<Platform>()?),
+        None => detected.as_ref().and_then(|detected| romdb::profile(&detected.platform)),
+    };
+    let layout = settings.layout()?;
+    let report = rom::validate(&bytes, layout.load_address, platform);
+    if let Some(error) = report.errors().next() {
+        return Err(format!("{}: {}", rom.display(), error));
+    }
+    for warning in report.warnings() {
+        eprintln!("{}: warning: {}", rom.display(), warning);
+    }
+    let mut chip8 = Chip8::new();
+    chip8.set_layout(layout)?;
+    chip8.load_rom_bytes(&bytes).map_err(|e| format!("{}: {}", rom.display(), e))?;
+    chip8.set_quirks(settings.quirks()?);
+    if let Some(seed) = machine.seed {
+        chip8.set_seed(seed);
+    }
+    let flags = DisplaySection {
+        scale: display_args.scale,
+        palette: display_args.palette.clone(),
+        foreground: display_args.fg.clone(),
+        background: display_args.bg.clone(),
+        grid: display_args.grid.then_some(true),
+        grid_color: display_args.grid_color.clone(),
+        filter: display_args.filter.clone(),
+        crt: display_args.crt.clone(),
+    };
+    let mut display = DisplayConfig::default();
+    config.display.overridden_by(&flags).apply(&mut display)?;
+    let keys = config.input.keys()?;
+    let mut audio = AudioConfig::default();
+    config.audio.apply(&mut audio)?;
+    let arrows = detected.as_ref().map(RomInfo::arrows).unwrap_or_default();
+    Ok(Loaded { chip8, display, audio, keys, arrows, speed: settings.speed.unwrap_or(DEFAULT_SPEED), detected })
+}
+
+fn main() {
+    match Cli::parse().command {
+        Command::Run { rom, machine, display, trace, gdb, terminal, capture, state, load_state } => {
+            let Loaded { mut chip8, display, audio, keys, arrows, speed, detected } = load(&rom, &machine, &display).unwrap_or_else(|e| exit_with(e));
+            if let Some(path) = load_state {
+                chip8.load_state_from(&path).unwrap_or_else(|e| exit_with(e));
+            }
+            let state = state.unwrap_or_else(|| state_path(&rom));
+            if let Some(detected) = detected {
+                println!("{}", describe(&detected));
+            }
+            chip8.set_tracer(tracer(&trace).unwrap_or_else(|e| exit_with(e)));
+            let gdb = gdb.map(|port| {
+                let server = GdbServer::bind(("127.0.0.1", port)).unwrap_or_else(|e| exit_with(format!("failed to start gdb stub: {}", e)));
+                println!("gdb stub listening on 127.0.0.1:{}", port);
+                server
+            });
+            let mut scheduler = Scheduler::new(gdb);
+            scheduler.set_speed(speed);
+            // a missing sound card shouldn't stop the rom running
+            let beeper = Beeper::open(&audio).unwrap_or_else(|e| {
+                eprintln!("warning: no sound, {}", e);
+                Beeper::silent()
+            });
+            scheduler.beeper = Some(beeper);
+            match terminal {
+                Some(glyphs) => {
+                    terminal::run(&mut chip8, &mut scheduler, &display, glyphs, &keys, arrows, &state).unwrap_or_else(|e| exit_with(e))
+                }
+                None => {
+                    let keys = keys.map(|c| host_key(c).unwrap_or_else(|| exit_with(format!("`{}` isn't a key the window can use", c))));
+                    run_window(&mut chip8, &mut scheduler, &display, &keys, arrows, capture, &state)
+                }
+            }
+        }
+        Command::Headless { rom, frames, mut machine, display, trace, screenshot, capture, load_state, save_state } => {
+            // seeded unless asked otherwise so headless runs repeat exactly
+            machine.seed.get_or_insert(0);
+            let Loaded { mut chip8, display, speed, .. } = load(&rom, &machine, &display).unwrap_or_else(|e| exit_with(e));
+            if let Some(path) = load_state {
+                chip8.load_state_from(&path).unwrap_or_else(|e| exit_with(e));
+            }
+            chip8.set_tracer(tracer(&trace).unwrap_or_else(|e| exit_with(e)));
+            headless(&mut chip8, speed, frames, &display, screenshot, capture).unwrap_or_else(|e| exit_with(e));
+            if let Some(path) = save_state {
+                chip8.save_state_to(&path).unwrap_or_else(|e| exit_with(e));
+            }
+        }
+        Command::Disasm { rom, load_address } => {
+            let rom = rom::read(&rom).unwrap_or_else(|e| exit_with(e));
+            let listing = disasm::disassemble(&rom, load_address);
+            print!("{}", listing);
+            if listing.past_end > 0 {
+                eprintln!("warning: {} bytes past FFF left out", listing.past_end);
+            }
+        }
+        Command::Asm { source, output, octo } => {
+            let output = output.unwrap_or_else(|| source.with_extension("ch8"));
+            let octo = octo || source.extension().is_some_and(|ext| ext == "8o");
+            let result = if octo {
+                fs::read_to_string(&source)
+                    .map_err(|e| e.to_string())
+                    .and_then(|text| asm::octo::assemble_octo(&source.to_string_lossy(), &text).map_err(|e| e.to_string()))
+            } else {
+                asm::assemble_file(&source, 0x200).map_err(|e| e.to_string())
+            };
+            let rom = result.unwrap_or_else(|e| exit_with(e));
+            fs::write(&output, rom).unwrap_or_else(|e| exit_with(format!("{}: {}", output.display(), e)));
+        }
+        Command::Info { rom: path, load_address } => {
+            let rom = rom::read(&path).unwrap_or_else(|e| exit_with(e));
+            print!("{}", info(&path, &rom, load_address));
+        }
+        Command::Record { rom, trace, steps } => {
+            let mut chip8 = load_rom(&rom).unwrap_or_else(|e| exit_with(e));
+            let recorded = difftest::record(&mut chip8, steps);
+            fs::write(&trace, difftest::write_trace(&recorded)).unwrap_or_else(|e| exit_with(format!("{}: {}", trace.display(), e)));
+        }
+        Command::Difftest { rom, trace, blocks } => {
+            let mut chip8 = load_rom(&rom).unwrap_or_else(|e| exit_with(e));
+            let text = fs::read_to_string(&trace).unwrap_or_else(|e| exit_with(format!("{}: {}", trace.display(), e)));
+            let reference = difftest::parse_trace(&text).unwrap_or_else(|e| exit_with(format!("{}: {}", trace.display(), e)));
+            let result = match blocks {
+                Some(n) => difftest::compare_blocks(&mut chip8, &reference, n),
+                None => difftest::compare(&mut chip8, &reference),
+            };
+            match result {
+                Ok(()) => println!("{} steps match", reference.len()),
+                Err(divergence) => {
+                    print!("{}", divergence);
+                    std::process::exit(1);
+                }
+            }
+        }
+    }
+}
+
+// runs as fast as it can, stopping early if the cpu faults or hits a breakpoint
+fn headless(
+    chip8: &mut Chip8,
+    speed: u32,
+    frames: u32,
+    display: &DisplayConfig,
+    screenshot: Option<PathBuf>,
+    capture: Option<PathBuf>,
+) -> Result<(), String> {
+    let mut recorder = capture.map(|path| Recorder::create(&path, display)).transpose()?;
+    let mut scheduler = Scheduler::new(None);
+    scheduler.set_speed(speed);
+    for _ in 0..frames {
+        for _ in 0..FRAMES_PER_TIMER_TICK {
+            if let Some(reason) = scheduler.run_frame(chip8, Controls::default()).stop {
+                return Err(format!("stopped at {:03X}, {}", chip8.pc, reason));
+            }
+        }
+        if let Some(recorder) = recorder.as_mut() {
+            recorder.capture(chip8)?;
+        }
+    }
+    if let Some(recorder) = recorder {
+        println!("captured {} frames", recorder.finish()?);
+    }
+    if let Some(path) = screenshot {
+        screenshot::save(chip8, &path, display)?;
+    }
+    Ok(())
+}
+
+// size, where it loads and what kinds of instructions the reachable code uses
+fn info(path: &Path, rom: &[u8], load_address: u16) -> String {
+    let end = load_address as usize + rom.len();
+    let mut text = format!("{}\n", path.display());
+    text += &format!("size          {} bytes, loads at {:03X}-{:03X}\n", rom.len(), load_address, end.saturating_sub(1));
+    let listing = disasm::disassemble(rom, load_address);
+    let ops: Vec<&OpCode> = listing
+        .items
+        .iter()
+        .filter_map(|item| match item {
+            Item::Instruction { op, .. } => Some(op),
+            Item::Data { .. } => None,
+        })
+        .collect();
+    text += &format!("code          {} instructions, {} bytes of data\n", ops.len(), rom.len() - listing.past_end - 2 * ops.len());
+    if listing.past_end > 0 {
+        text += &format!("              {} bytes past FFF\n", listing.past_end);
+    }
+    let classes = ["flow", "skip", "arithmetic", "memory", "draw", "input", "timer"];
+    let counts: Vec<String> = classes
+        .iter()
+        .filter_map(|name| {
+            let class: OpClass = name.parse().ok()?;
+            let count = ops.iter().filter(|op| OpClass::of(op) == Some(class)).count();
+            (count > 0).then(|| format!("{} {}", name, count))
+        })
+        .collect();
+    text += &format!("instructions  {}\n", counts.join(", "));
+    let unknown = ops.iter().filter(|op| ***op == OpCode::UNFINISHED).count();
+    if unknown > 0 {
+        text += &format!("              {} not recognised\n", unknown);
+    }
+    let detected = romdb::identify(rom, load_address);
+    match &detected {
+        Some(detected) => {
+            text += &format!("platform      {}\n", describe(detected));
+            if let Some(speed) = detected.speed {
+                text += &format!("speed         {} instructions per frame\n", speed);
+            }
+            if !detected.keys.is_empty() {
+                let keys: Vec<String> = detected.keys.iter().map(|(name, key)| format!("{} {:X}", name, key)).collect();
+                text += &format!("keys          {}\n", keys.join(", "));
+                if detected.arrows().iter().any(Option::is_some) {
+                    text += "              up, down, left and right are on the arrow keys\n";
+                }
+            }
+        }
+        None => text += "platform      not in the rom database, looks like plain chip-8\n",
+    }
+    let platform = detected.and_then(|detected| romdb::profile(&detected.platform));
+    text += &rom::validate(rom, load_address, platform).to_string();
+    text
+}
+
+// one line on what a rom was identified as
+fn describe(detected: &RomInfo) -> String {
+    match (&detected.title, detected.source) {
+        (Some(title), Source::Database) if detected.authors.is_empty() => format!("{}, {}", title, detected.platform),
+        (Some(title), Source::Database) => format!("{} by {}, {}", title, detected.authors.join(" and "), detected.platform),
+        _ => format!("{}, guessed from the opcodes it uses", detected.platform),
+    }
+}
+
+// next to the rom, or in the working directory when it came from stdin
+fn state_path(rom: &Path) -> PathBuf {
+    if rom == Path::new("-") {
+        PathBuf::from("chip8.state")
+    } else {
+        rom.with_extension("state")
+    }
+}
+
+fn run_window(
+    chip8: &mut Chip8,
+    scheduler: &mut Scheduler,
+    display: &DisplayConfig,
+    keys: &[Key; 16],
+    arrows: [Option<u8>; 4],
+    capture: Option<PathBuf>,
+    state: &Path,
+) {
+    let (width, height) = (display.width(), display.height());
+    let mut buffer: Vec<u32> = vec![0; width * height];
+    display.draw(&mut buffer, chip8);
+    let mut filter = display.filter.map(FrameFilter::new);
+    let mut crt = Crt::new(display.crt.clone());
+    // uploads a frame, through the crt passes if there are any
+    let present = |window: &mut Window, crt: &mut Crt, buffer: &[u32]| {
+        let frame = if crt.is_empty() { buffer } else { crt.apply(buffer, width, height) };
+        window.update_with_buffer(frame, width, height).unwrap();
+    };
+
+    let mut window = Window::new("Chip8 Interpreter", width, height, WindowOptions::default())
         .unwrap_or_else(|e| {
             panic!("{}", e);
         });
 
-    // Limit to max ~60 fps update rate
-    window.limit_update_rate(Some(std::time::Duration::from_millis(UPDATE_RATE_MS as u64)));
+    // the scheduler paces frames
+    window.limit_update_rate(None);
+    // the loop below only uploads frames that changed, start from the blank screen
+    present(&mut window, &mut crt, &buffer);
 
+    // buffer has changes that haven't been uploaded yet
+    let mut stale = false;
+    // F9 starts recording to a timestamped gif, or `--capture <file.gif|dir>` from the
+    // start. F10 stops, closing the window does too
+    let start_recording = |path: &Path| match Recorder::create(path, display) {
+        Ok(recorder) => {
+            println!("recording to {}", path.display());
+            Some(recorder)
+        }
+        Err(e) => {
+            println!("recording failed: {}", e);
+            None
+        }
+    };
+    let mut recorder = capture.as_deref().and_then(start_recording);
+    let stop_recording = |recorder: Recorder| match recorder.finish() {
+        Ok(frames) => println!("recorded {} frames", frames),
+        Err(e) => println!("recording failed: {}", e),
+    };
     while window.is_open() && !window.is_key_down(Key::Escape) {
-        //if window.is_key_pressed(Key::Space, KeyRepeat::Yes) {
-            chip8.cycle();
-            fake_screen = chip8.get_screen();
-            //println!("{:?}", chip8.v_registers[6]);
-            //println!("{:?}", chip8.i_reg);
-            //chip8.debug_print_data();
-        //}
-        for x in 0..64 {
-            for y in 0..32 {
-                if fake_screen[y][x] {
-                    for i in x * SCALE..x * SCALE + SCALE {
-                        for j in y * SCALE..y * SCALE + SCALE {
-                            let index = j * WIDTH + i;
-                            buffer[index] = ACTIVE_COLOR;
-                        }
-                    }
-                } else {
-                    for i in x * SCALE..x * SCALE + SCALE {
-                        for j in y * SCALE..y * SCALE + SCALE {
-                            let index = j * WIDTH + i;
-                            buffer[index] = BACKGROUND_COLOR;
-                        }
-                    }
-                }
+        for (key, &host_key) in keys.iter().enumerate() {
+            chip8.set_key(key as u8, window.is_key_down(host_key));
+        }
+        for (host_key, key) in [Key::Up, Key::Down, Key::Left, Key::Right].into_iter().zip(arrows) {
+            if let Some(key) = key.filter(|_| window.is_key_down(host_key)) {
+                chip8.set_key(key, true);
+            }
+        }
+        // space steps and F5 continues once the debugger stops execution, F2 starts over
+        let controls = Controls {
+            resume: window.is_key_pressed(Key::F5, KeyRepeat::No),
+            step: window.is_key_pressed(Key::Space, KeyRepeat::Yes),
+            reset: window.is_key_pressed(Key::F2, KeyRepeat::No),
+        };
+        let frame = scheduler.run_frame(chip8, controls);
+        if let Some(e) = &frame.gdb_error {
+            println!("gdb stub error: {}", e);
+        }
+        if let Some(reason) = &frame.stop {
+            println!("break: {}", reason);
+            chip8.debug_print_data();
+        }
+        // F12 saves what's on screen next to the rom's working directory, in the window's colors
+        if window.is_key_pressed(Key::F12, KeyRepeat::No) {
+            let path = format!("chip8-{}.png", unix_time());
+            match screenshot::save(chip8, Path::new(&path), display) {
+                Ok(()) => println!("saved {}", path),
+                Err(e) => println!("screenshot failed: {}", e),
+            }
+        }
+        // F6 saves the whole machine and F7 puts it back
+        if window.is_key_pressed(Key::F6, KeyRepeat::No) {
+            match chip8.save_state_to(state) {
+                Ok(()) => println!("saved {}", state.display()),
+                Err(e) => println!("saving failed: {}", e),
+            }
+        }
+        if window.is_key_pressed(Key::F7, KeyRepeat::No) {
+            match chip8.load_state_from(state) {
+                Ok(()) => println!("loaded {}", state.display()),
+                Err(e) => println!("loading failed: {}", e),
             }
         }
-        window.update_with_buffer(&buffer, WIDTH, HEIGHT).unwrap();
+        if recorder.is_none() && window.is_key_pressed(Key::F9, KeyRepeat::No) {
+            recorder = start_recording(Path::new(&format!("chip8-{}.gif", unix_time())));
+        }
+        if window.is_key_pressed(Key::F10, KeyRepeat::No) {
+            if let Some(recorder) = recorder.take() {
+                stop_recording(recorder);
+            }
+        }
+        // frames are captured on the emulated vblank so a slow window doesn't change the timing
+        if let Some(capture) = recorder.as_mut().filter(|_| frame.ran && scheduler.is_vblank()) {
+            if let Err(e) = capture.capture(chip8) {
+                println!("recording failed: {}", e);
+                recorder = None;
+            }
+        }
+        // filters change the picture even on frames where nothing was drawn
+        if let Some(filter) = filter.as_mut() {
+            if filter.update(chip8, UPDATE_RATE, scheduler.is_vblank()) {
+                display.draw_levels(&mut buffer, filter.levels());
+                stale = true;
+            }
+        } else {
+            // only repaint the cells that changed
+            for rect in chip8.take_dirty_rects() {
+                display.draw_rect(&mut buffer, chip8, rect);
+                stale = true;
+            }
+        }
+        // skip the upload when nothing changed. crt passes take a few milliseconds so
+        // those frames only go out at the 60Hz a monitor would show them anyway
+        if stale && (crt.is_empty() || scheduler.is_paused() || scheduler.is_vblank()) {
+            present(&mut window, &mut crt, &buffer);
+            stale = false;
+        } else {
+            window.update();
+        }
+        scheduler.wait();
     }
+    if let Some(recorder) = recorder {
+        stop_recording(recorder);
+    }
+}
+
+// seconds since 1970, for naming screenshots and recordings
+fn unix_time() -> u64 {
+    std::time::SystemTime::now().duration_since(std::time::UNIX_EPOCH).map_or(0, |d| d.as_secs())
 }
@@ -0,0 +1,248 @@
+// reading roms from files, stdin and zip archives, and checks on them before they're
+// loaded: does it fit in memory, is it really a chip-8 binary rather than something that
+// needs converting first, and does it use opcodes the profile it's about to run under
+// doesn't have
+use crate::cpu::quirks::Platform;
+use crate::romdb::{self, Extension};
+use std::fmt;
+use std::fs;
+use std::io::{self, Cursor, Read};
+use std::path::Path;
+
+// what a rom inside an archive can be called
+const ROM_EXTENSIONS: [&str; 3] = ["ch8", "sc8", "xo8"];
+
+// `-` reads stdin. zip archives, from a file or stdin, are opened and the one rom in
+// them read out
+pub fn read(path: &Path) -> Result<Vec<u8>, String> {
+    let bytes = if path == Path::new("-") {
+        let mut bytes = Vec::new();
+        io::stdin().read_to_end(&mut bytes).map(|_| bytes)
+    } else {
+        fs::read(path)
+    };
+    let bytes = bytes.map_err(|e| format!("{}: {}", path.display(), e))?;
+    if bytes.starts_with(b"PK\x03\x04") {
+        return from_zip(&bytes).map_err(|e| format!("{}: {}", path.display(), e));
+    }
+    Ok(bytes)
+}
+
+// the single .ch8, .sc8 or .xo8 file in an archive
+pub fn from_zip(archive: &[u8]) -> Result<Vec<u8>, String> {
+    let mut archive = zip::ZipArchive::new(Cursor::new(archive)).map_err(|e| e.to_string())?;
+    let is_rom = |name: &str| {
+        let extension = Path::new(name).extension().and_then(|ext| ext.to_str()).unwrap_or_default();
+        !name.ends_with('/') && ROM_EXTENSIONS.iter().any(|rom| rom.eq_ignore_ascii_case(extension))
+    };
+    let roms: Vec<String> = archive.file_names().filter(|name| is_rom(name)).map(String::from).collect();
+    let name = match roms.as_slice() {
+        [name] => name,
+        [] => return Err(format!("no {} file in the archive", ROM_EXTENSIONS.map(|ext| format!(".{}", ext)).join(", "))),
+        _ => return Err(format!("more than one rom in the archive: {}", roms.join(", "))),
+    };
+    let mut file = archive.by_name(name).map_err(|e| format!("{}: {}", name, e))?;
+    let mut rom = Vec::new();
+    file.read_to_end(&mut rom).map_err(|e| format!("{}: {}", name, e))?;
+    Ok(rom)
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Severity {
+    // loads, but may not run right
+    Warning,
+    // can't be loaded
+    Error,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Problem {
+    Empty,
+    TooLarge { size: usize, capacity: usize },
+    // instructions are two bytes so the last one is cut in half
+    OddLength,
+    // hex digits written out as text, e.g. copied from a listing. only a guess, a binary
+    // can be all printable too, so it's a warning
+    HexText,
+    // Octo source that still needs assembling, guessed the same way
+    OctoSource,
+    // an instruction the code reaches that only another platform has
+    ExtensionOpcode { address: u16, word: u16, extension: Extension },
+}
+impl Problem {
+    pub fn severity(&self) -> Severity {
+        match self {
+            Problem::Empty | Problem::TooLarge { .. } => Severity::Error,
+            Problem::HexText | Problem::OctoSource | Problem::OddLength | Problem::ExtensionOpcode { .. } => Severity::Warning,
+        }
+    }
+}
+impl fmt::Display for Problem {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Problem::Empty => write!(f, "the rom is empty"),
+            Problem::TooLarge { size, capacity } => {
+                write!(f, "{} bytes is too large, {} fit in memory after the load address", size, capacity)
+            }
+            Problem::OddLength => write!(f, "odd length, the last instruction is cut in half"),
+            Problem::HexText => write!(f, "looks like hex written out as text rather than a binary rom"),
+            Problem::OctoSource => write!(f, "looks like Octo source, assemble it with `chip-8 asm` first"),
+            Problem::ExtensionOpcode { address, word, extension } => {
+                write!(f, "{:04X} at {:03X} is a {} instruction", word, address, extension.name())
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Report {
+    pub size: usize,
+    // bytes of memory from the load address up
+    pub capacity: usize,
+    pub problems: Vec<Problem>,
+}
+impl Report {
+    // nothing stops it being loaded
+    pub fn is_loadable(&self) -> bool {
+        self.problems.iter().all(|problem| problem.severity() == Severity::Warning)
+    }
+    pub fn errors(&self) -> impl Iterator<Item = &Problem> {
+        self.problems.iter().filter(|problem| problem.severity() == Severity::Error)
+    }
+    pub fn warnings(&self) -> impl Iterator<Item = &Problem> {
+        self.problems.iter().filter(|problem| problem.severity() == Severity::Warning)
+    }
+}
+impl fmt::Display for Report {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for problem in &self.problems {
+            let severity = if problem.severity() == Severity::Error { "error" } else { "warning" };
+            writeln!(f, "{}: {}", severity, problem)?;
+        }
+        Ok(())
+    }
+}
+
+fn is_hex_text(rom: &[u8]) -> bool {
+    let mut digits = 0;
+    for token in rom.split(|byte| byte.is_ascii_whitespace() || *byte == b',') {
+        let token = token.strip_prefix(b"0x").or_else(|| token.strip_prefix(b"0X")).unwrap_or(token);
+        if !token.iter().all(u8::is_ascii_hexdigit) {
+            return false;
+        }
+        digits += token.len();
+    }
+    digits >= 4
+}
+
+fn is_octo_source(rom: &[u8]) -> bool {
+    let Ok(text) = std::str::from_utf8(rom) else { return false };
+    let words: Vec<&str> = text.split_whitespace().collect();
+    // a label or constant and something that only appears in source
+    let has = |word: &str| words.contains(&word);
+    (has(":") || has(":const") || has(":alias")) && (has(":=") || has("loop") || has("sprite") || has("return"))
+}
+
+// `platform` is the profile the rom will run under, None for plain chip-8. SUPER-CHIP
+// instructions are expected under the schip profile and reported under any other,
+// XO-CHIP ones are always reported as no profile here has them
+pub fn validate(rom: &[u8], load_address: u16, platform: Option<Platform>) -> Report {
+    let capacity = 0x1000usize.saturating_sub(load_address as usize);
+    let mut problems = Vec::new();
+    if rom.is_empty() {
+        problems.push(Problem::Empty);
+    }
+    if rom.len() > capacity {
+        problems.push(Problem::TooLarge { size: rom.len(), capacity });
+    }
+    if is_octo_source(rom) {
+        problems.push(Problem::OctoSource);
+    } else if is_hex_text(rom) {
+        problems.push(Problem::HexText);
+    }
+    // text isn't worth checking as code
+    if !problems.is_empty() {
+        return Report { size: rom.len(), capacity, problems };
+    }
+    if rom.len() % 2 == 1 {
+        problems.push(Problem::OddLength);
+    }
+    for (address, word, extension) in romdb::extension_opcodes(rom, load_address) {
+        if !(extension == Extension::Schip && platform == Some(Platform::Schip)) {
+            problems.push(Problem::ExtensionOpcode { address, word, extension });
+        }
+    }
+    Report { size: rom.len(), capacity, problems }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn size_against_memory() {
+        let report = validate(&[0x12, 0x00], 0x200, None);
+        assert_eq!(report, Report { size: 2, capacity: 3584, problems: Vec::new() });
+        let report = validate(&vec![0x12; 3585], 0x200, None);
+        assert_eq!(report.problems, vec![Problem::TooLarge { size: 3585, capacity: 3584 }]);
+        assert!(!report.is_loadable());
+        assert!(validate(&vec![0x12; 3584], 0x200, None).is_loadable());
+        assert_eq!(validate(&[], 0x200, None).problems, vec![Problem::Empty]);
+        // a load address past the end of memory leaves no room rather than underflowing
+        assert_eq!(validate(&[0x12, 0x00], 0xFFFF, None).problems, vec![Problem::TooLarge { size: 2, capacity: 0 }]);
+        let report = validate(&[0x12, 0x00, 0xFF], 0x200, None);
+        assert_eq!(report.problems, vec![Problem::OddLength]);
+        assert!(report.is_loadable());
+    }
+    #[test]
+    fn text_instead_of_binary() {
+        assert_eq!(validate(b"00E0 A22A\n600C 6108\n", 0x200, None).problems, vec![Problem::HexText]);
+        assert_eq!(validate(b"0x00, 0xE0, 0xA2, 0x2A", 0x200, None).problems, vec![Problem::HexText]);
+        let octo = b": main\n  clear\n  v0 := 5\n  loop again\n";
+        assert_eq!(validate(octo, 0x200, None).problems, vec![Problem::OctoSource]);
+        // both are guesses, so they warn rather than refuse the rom
+        assert!(validate(octo, 0x200, None).is_loadable());
+        assert_eq!(validate(b"00E0 A22A", 0x200, None).warnings().count(), 1);
+        // a binary that happens to be printable isn't flagged
+        assert!(validate(b"abcdefgh", 0x200, None).problems.is_empty());
+        // and the fixtures the assembler is tested against, source and output
+        for name in ["bounce", "counter", "keys"] {
+            let source = std::fs::read(format!("tests/octo/{}.8o", name)).unwrap();
+            assert_eq!(validate(&source, 0x200, None).problems, vec![Problem::OctoSource], "{}", name);
+            let rom = std::fs::read(format!("tests/octo/{}.ch8", name)).unwrap();
+            assert!(validate(&rom, 0x200, None).is_loadable(), "{}", name);
+        }
+    }
+    fn zip(files: &[(&str, &[u8])]) -> Vec<u8> {
+        let mut writer = zip::ZipWriter::new(Cursor::new(Vec::new()));
+        for (name, data) in files {
+            writer.start_file(*name, zip::write::SimpleFileOptions::default()).unwrap();
+            std::io::Write::write_all(&mut writer, data).unwrap();
+        }
+        writer.finish().unwrap().into_inner()
+    }
+    #[test]
+    fn roms_from_archives() {
+        let archive = zip(&[("readme.txt", b"hello"), ("games/Pong.CH8", &[0x12, 0x00])]);
+        assert_eq!(from_zip(&archive), Ok(vec![0x12, 0x00]));
+        let path = std::env::temp_dir().join(format!("chip8-pack-{}.zip", std::process::id()));
+        fs::write(&path, &archive).unwrap();
+        assert_eq!(read(&path), Ok(vec![0x12, 0x00]));
+        fs::remove_file(&path).unwrap();
+        let two = zip(&[("a.ch8", &[0x00, 0xE0]), ("b.sc8", &[0x00, 0xFF])]);
+        assert_eq!(from_zip(&two), Err("more than one rom in the archive: a.ch8, b.sc8".to_string()));
+        assert!(from_zip(&zip(&[("readme.txt", b"hello")])).unwrap_err().contains("no .ch8"));
+        // not an archive, just read as is
+        assert_eq!(read(Path::new("2-ibm-logo.ch8")).unwrap().len(), 132);
+    }
+    #[test]
+    fn opcodes_from_other_platforms() {
+        // CLS, hires, then spin
+        let rom = [0x00, 0xE0, 0x00, 0xFF, 0x12, 0x04];
+        let report = validate(&rom, 0x200, Some(Platform::Cosmac));
+        assert_eq!(report.problems, vec![Problem::ExtensionOpcode { address: 0x202, word: 0x00FF, extension: Extension::Schip }]);
+        assert_eq!(report.warnings().count(), 1);
+        assert_eq!(report.to_string(), "warning: 00FF at 202 is a SUPER-CHIP instruction\n");
+        assert!(validate(&rom, 0x200, Some(Platform::Schip)).problems.is_empty());
+    }
+}
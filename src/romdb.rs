@@ -0,0 +1,335 @@
+// telling which platform a rom was written for. known roms are looked up by SHA-1 in
+// programs.json, which follows the schema of the community database at
+// github.com/chip-8/chip-8-database so its programs.json can be dropped in as is. the
+// bundled file isn't a copy of it, only a few entries written by hand for the roms in
+// this repo, with hashes taken from those files.
+// anything else is guessed at from opcodes only some platforms have
+use crate::config::{rom_hash, DisplaySection, MachineSection, RomSection};
+use crate::cpu::opcodes::OpCode;
+use crate::cpu::quirks::{Platform, Quirks};
+use crate::disasm::{self, Item};
+use serde::Deserialize;
+use std::collections::{BTreeMap, HashMap};
+use std::sync::OnceLock;
+
+const BUNDLED: &str = include_str!("romdb/programs.json");
+
+// only the parts used here, the database has plenty more
+#[derive(Debug, Deserialize)]
+struct Program {
+    title: String,
+    #[serde(default)]
+    authors: Vec<String>,
+    roms: HashMap<String, Rom>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct Rom {
+    // best first
+    #[serde(default)]
+    platforms: Vec<String>,
+    // quirks that differ from what the platform normally has
+    #[serde(default)]
+    quirky_platforms: HashMap<String, HashMap<String, bool>>,
+    tickrate: Option<u32>,
+    #[serde(default)]
+    keys: BTreeMap<String, u8>,
+    colors: Option<Colors>,
+}
+
+#[derive(Debug, Deserialize)]
+struct Colors {
+    // background first, then foreground
+    #[serde(default)]
+    pixels: Vec<String>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Source {
+    Database,
+    Heuristics,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct RomInfo {
+    pub source: Source,
+    pub title: Option<String>,
+    pub authors: Vec<String>,
+    // a database platform id like originalChip8 or superchip
+    pub platform: String,
+    pub quirks: Quirks,
+    // instructions per 60Hz frame
+    pub speed: Option<u32>,
+    // what chip-8 key the game uses for up, down, a and so on. up, down, left and right
+    // go on the arrow keys, the rest are only shown
+    pub keys: BTreeMap<String, u8>,
+    pub background: Option<String>,
+    pub foreground: Option<String>,
+}
+impl RomInfo {
+    // the chip-8 keys for the arrow keys, up, down, left and right
+    pub fn arrows(&self) -> [Option<u8>; 4] {
+        ["up", "down", "left", "right"].map(|name| self.keys.get(name).copied().filter(|&key| key < 16))
+    }
+    // as settings that go between the config file's defaults and its table for the rom
+    pub fn settings(&self) -> RomSection {
+        let quirks = self.quirks;
+        RomSection {
+            title: self.title.clone(),
+            display: DisplaySection {
+                foreground: self.foreground.clone(),
+                background: self.background.clone(),
+                ..DisplaySection::default()
+            },
+            machine: MachineSection {
+                speed: self.speed,
+                shift_vx: Some(quirks.shift_vx),
+                memory_increments_i: Some(quirks.memory_increments_i),
+                jump_vx: Some(quirks.jump_vx),
+                vf_reset: Some(quirks.vf_reset),
+                clip_sprites: Some(quirks.clip_sprites),
+                ..MachineSection::default()
+            },
+            ..RomSection::default()
+        }
+    }
+}
+
+// the quirks each database platform runs with, None for ones this interpreter can't run
+fn platform_quirks(id: &str) -> Option<Quirks> {
+    match id {
+        "originalChip8" => Some(Platform::Cosmac.quirks()),
+        "hybridVIP" => Some(Platform::HybridVip.quirks()),
+        "modernChip8" => Some(Quirks::default()),
+        "chip48" | "superchip1" | "superchip" => Some(Platform::Schip.quirks()),
+        "xochip" => Some(Quirks { memory_increments_i: true, ..Quirks::default() }),
+        _ => None,
+    }
+}
+
+// quirk names from the database, ones with no equivalent here are left alone
+fn set_quirk(quirks: &mut Quirks, name: &str, on: bool) {
+    match name {
+        "shift" => quirks.shift_vx = on,
+        "memoryLeaveIUnchanged" => quirks.memory_increments_i = !on,
+        "jump" => quirks.jump_vx = on,
+        "logic" => quirks.vf_reset = on,
+        "wrap" => quirks.clip_sprites = !on,
+        _ => {}
+    }
+}
+
+pub struct Database {
+    programs: Vec<Program>,
+}
+impl Database {
+    pub fn parse(json: &str) -> Result<Database, String> {
+        serde_json::from_str(json).map(|programs| Database { programs }).map_err(|e| e.to_string())
+    }
+    // the copy built into the binary
+    pub fn bundled() -> &'static Database {
+        static DATABASE: OnceLock<Database> = OnceLock::new();
+        DATABASE.get_or_init(|| Database::parse(BUNDLED).expect("bundled rom database is valid"))
+    }
+
+    pub fn lookup(&self, rom: &[u8]) -> Option<RomInfo> {
+        let hash = rom_hash(rom);
+        let (program, entry) = self.programs.iter().find_map(|program| {
+            let entry = program.roms.iter().find(|(key, _)| key.eq_ignore_ascii_case(&hash))?.1;
+            Some((program, entry))
+        })?;
+        // the first platform listed that can run here
+        let (platform, mut quirks) =
+            entry.platforms.iter().find_map(|id| Some((id.clone(), platform_quirks(id)?))).unwrap_or_default();
+        for (name, &on) in entry.quirky_platforms.get(&platform).into_iter().flatten() {
+            set_quirk(&mut quirks, name, on);
+        }
+        let pixels = entry.colors.as_ref().map(|colors| colors.pixels.as_slice()).unwrap_or_default();
+        Some(RomInfo {
+            source: Source::Database,
+            title: Some(program.title.clone()),
+            authors: program.authors.clone(),
+            platform,
+            quirks,
+            speed: entry.tickrate,
+            keys: entry.keys.clone(),
+            background: pixels.first().cloned(),
+            foreground: pixels.get(1).cloned(),
+        })
+    }
+}
+
+// the words where following the code ran into something that doesn't decode: the entry
+// point, a jump or call target, or straight after an instruction that carries on
+fn undecoded_words(rom: &[u8], origin: u16) -> Vec<(u16, u16)> {
+    let listing = disasm::disassemble(rom, origin);
+    let word = |address: u16| {
+        let offset = (address - origin) as usize;
+        Some((address, (*rom.get(offset)? as u16) << 8 | *rom.get(offset + 1)? as u16))
+    };
+    let mut starts: Vec<u16> = listing.labels.iter().copied().chain([origin]).collect();
+    for pair in listing.items.windows(2) {
+        if let [Item::Instruction { address, op, .. }, Item::Data { address: next, .. }] = pair {
+            let carries_on = !matches!(op, OpCode::JMP(_) | OpCode::JumpPlusV0(_) | OpCode::RET);
+            if carries_on && *next == address + 2 {
+                starts.push(*next);
+            }
+        }
+    }
+    let data: Vec<u16> =
+        listing.items.iter().filter_map(|item| if let Item::Data { address, .. } = item { Some(*address) } else { None }).collect();
+    starts.into_iter().filter(|address| data.contains(address)).filter_map(word).collect()
+}
+
+// platforms that add instructions to chip-8, none of which run here yet
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Extension {
+    Schip,
+    XoChip,
+}
+impl Extension {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Extension::Schip => "SUPER-CHIP",
+            Extension::XoChip => "XO-CHIP",
+        }
+    }
+    // the database id
+    fn platform(&self) -> &'static str {
+        match self {
+            Extension::Schip => "superchip",
+            Extension::XoChip => "xochip",
+        }
+    }
+}
+
+fn is_xochip(word: u16) -> bool {
+    let low = word & 0xFF;
+    match word >> 12 {
+        // 5XY2/5XY3 save and load a range of registers
+        0x5 => matches!(word & 0xF, 2 | 3),
+        // F000 NNNN loads a 16 bit address, FN01 picks planes, F002 loads audio, FX3A sets the pitch
+        0xF => word == 0xF000 || word == 0xF002 || low == 0x01 || low == 0x3A,
+        _ => false,
+    }
+}
+fn is_schip(word: u16) -> bool {
+    match word >> 12 {
+        // scrolling, exit and switching resolution
+        0x0 => word & 0xFFF0 == 0x00C0 || (0x00FB..=0x00FF).contains(&word),
+        // large font and the flag registers
+        0xF => matches!(word & 0xFF, 0x30 | 0x75 | 0x85),
+        _ => false,
+    }
+}
+
+// address and word of every instruction the code reaches that only another platform has
+pub fn extension_opcodes(rom: &[u8], origin: u16) -> Vec<(u16, u16, Extension)> {
+    undecoded_words(rom, origin)
+        .into_iter()
+        .filter_map(|(address, word)| match word {
+            _ if is_xochip(word) => Some((address, word, Extension::XoChip)),
+            _ if is_schip(word) => Some((address, word, Extension::Schip)),
+            _ => None,
+        })
+        .collect()
+}
+
+// the profile for a database platform id, None for modern chip-8 and ones without one here
+pub fn profile(id: &str) -> Option<Platform> {
+    match id {
+        "originalChip8" => Some(Platform::Cosmac),
+        "hybridVIP" => Some(Platform::HybridVip),
+        "chip48" | "superchip1" | "superchip" => Some(Platform::Schip),
+        _ => None,
+    }
+}
+
+// a guess from what the code uses, following it from where the rom is loaded. None when
+// it looks like plain chip-8. XO-CHIP is a superset of SUPER-CHIP so any of its opcodes
+// decide it
+pub fn guess(rom: &[u8], load_address: u16) -> Option<RomInfo> {
+    let found: Vec<Extension> = extension_opcodes(rom, load_address).into_iter().map(|(_, _, extension)| extension).collect();
+    let platform = if found.contains(&Extension::XoChip) {
+        Extension::XoChip.platform()
+    } else {
+        found.first()?.platform()
+    };
+    Some(RomInfo {
+        source: Source::Heuristics,
+        title: None,
+        authors: Vec::new(),
+        platform: platform.to_string(),
+        quirks: platform_quirks(platform)?,
+        speed: None,
+        keys: BTreeMap::new(),
+        background: None,
+        foreground: None,
+    })
+}
+
+// the bundled database first, then a guess
+pub fn identify(rom: &[u8], load_address: u16) -> Option<RomInfo> {
+    Database::bundled().lookup(rom).or_else(|| guess(rom, load_address))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn assemble(ops: &[u16]) -> Vec<u8> {
+        ops.iter().flat_map(|op| op.to_be_bytes()).collect()
+    }
+
+    #[test]
+    fn bundled_roms_are_known() {
+        let rom = std::fs::read("2-ibm-logo.ch8").unwrap();
+        let info = identify(&rom, 0x200).unwrap();
+        assert_eq!(info.source, Source::Database);
+        assert_eq!(info.title.as_deref(), Some("IBM Logo"));
+        assert_eq!(info.quirks, Quirks::default());
+    }
+    #[test]
+    fn database_platforms_and_quirks() {
+        let rom = [0x12, 0x00];
+        let json = format!(
+            r##"[{{"title": "loop", "authors": ["someone"], "roms": {{"{}": {{
+                "platforms": ["megachip8", "superchip"], "tickrate": 30,
+                "quirkyPlatforms": {{"superchip": {{"shift": false, "vblank": true}}}},
+                "keys": {{"up": 5, "a": 6}}, "colors": {{"pixels": ["#000000", "#ffffff"]}}
+            }}}}}}]"##,
+            rom_hash(&rom).to_uppercase()
+        );
+        let info = Database::parse(&json).unwrap().lookup(&rom).unwrap();
+        assert_eq!(info.platform, "superchip");
+        assert_eq!(info.quirks, Quirks { shift_vx: false, ..Platform::Schip.quirks() });
+        assert_eq!(info.speed, Some(30));
+        assert_eq!(info.keys.get("up"), Some(&5));
+        assert_eq!(info.arrows(), [Some(5), None, None, None]);
+        assert_eq!(info.foreground.as_deref(), Some("#ffffff"));
+        let settings = info.settings();
+        assert_eq!(settings.machine.quirks(), Ok(info.quirks));
+        assert_eq!(settings.display.background.as_deref(), Some("#000000"));
+        assert!(Database::parse(&json).unwrap().lookup(&[0x00, 0xE0]).is_none());
+    }
+    #[test]
+    fn guesses_from_opcodes() {
+        // CLS, hires, then spin
+        let schip = assemble(&[0x00E0, 0x00FF, 0x1204]);
+        assert_eq!(guess(&schip, 0x200).unwrap().platform, "superchip");
+        // load a long address
+        let xochip = assemble(&[0x00E0, 0xF000, 0x0300, 0x1206]);
+        assert_eq!(guess(&xochip, 0x200).unwrap().platform, "xochip");
+        // the same words as sprite data after a jump don't count
+        let chip8 = assemble(&[0x1202, 0x1202, 0x00FF, 0xF000]);
+        assert_eq!(guess(&chip8, 0x200), None);
+        // called subroutines are checked too
+        let called = assemble(&[0x2204, 0x1202, 0x00FE, 0x00EE]);
+        assert_eq!(guess(&called, 0x200).unwrap().quirks, Platform::Schip.quirks());
+        // jump targets only line up with the code from the address it's loaded at
+        let eti660 = assemble(&[0x1604, 0x0000, 0x00FF, 0x1606]);
+        assert_eq!(guess(&eti660, 0x600).unwrap().platform, "superchip");
+        assert_eq!(guess(&eti660, 0x200), None);
+    }
+}
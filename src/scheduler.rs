@@ -0,0 +1,231 @@
+// the frame loop shared by the window and terminal frontends: one instruction per
+// frame, timers at 60Hz, stopping for the debugger or a gdb client, and pacing
+use crate::audio::Beeper;
+use crate::cpu::debugger::BreakReason;
+use crate::gdb::GdbServer;
+use crate::Chip8;
+use std::io;
+use std::thread;
+use std::time::{Duration, Instant};
+
+pub const FPS: f32 = 240.0;
+pub const UPDATE_RATE: f32 = 1.0 / FPS;
+// timers count down at 60Hz regardless of how fast instructions run
+pub const FRAMES_PER_TIMER_TICK: u32 = (FPS / 60.0) as u32;
+// instructions per 60Hz frame unless set otherwise, one every FPS frame
+pub const DEFAULT_SPEED: u32 = FRAMES_PER_TIMER_TICK;
+
+// debugger keys the frontend saw this frame
+#[derive(Debug, Default, Clone, Copy)]
+pub struct Controls {
+    // carry on after a break
+    pub resume: bool,
+    // run a single instruction while stopped
+    pub step: bool,
+    // start the rom over
+    pub reset: bool,
+}
+
+// what happened during a frame, for the frontend to show however suits it
+#[derive(Debug, Default)]
+pub struct Frame {
+    // false when nothing ran because the debugger or a gdb client has the cpu stopped
+    pub ran: bool,
+    // the debugger stopped execution, with no gdb client to hand it to
+    pub stop: Option<BreakReason>,
+    // talking to the gdb client failed
+    pub gdb_error: Option<io::Error>,
+}
+
+pub struct Scheduler {
+    pub gdb: Option<GdbServer>,
+    // sounds while the sound timer is above zero and the machine is running
+    pub beeper: Option<Beeper>,
+    // set when the debugger stops execution
+    paused: bool,
+    frame: u32,
+    next_frame: Instant,
+    // instructions per 60Hz frame, spread as evenly as possible over the frames in between
+    speed: u32,
+}
+impl Scheduler {
+    pub fn new(gdb: Option<GdbServer>) -> Self {
+        Scheduler { gdb, beeper: None, paused: false, frame: 0, next_frame: Instant::now(), speed: DEFAULT_SPEED }
+    }
+    pub fn set_speed(&mut self, instructions_per_vblank: u32) {
+        self.speed = instructions_per_vblank;
+    }
+    pub fn is_paused(&self) -> bool {
+        self.paused
+    }
+    // frames that line up with the 60Hz timer tick, which is also when a monitor
+    // would show a new picture
+    pub fn is_vblank(&self) -> bool {
+        self.frame.is_multiple_of(FRAMES_PER_TIMER_TICK)
+    }
+
+    // emulates one frame, the keypad should already be set
+    pub fn run_frame(&mut self, chip8: &mut Chip8, controls: Controls) -> Frame {
+        let frame = self.emulate(chip8, controls);
+        if let Some(beeper) = &self.beeper {
+            beeper.set_playing(frame.ran && chip8.sound_timer() > 0);
+        }
+        frame
+    }
+    fn emulate(&mut self, chip8: &mut Chip8, controls: Controls) -> Frame {
+        let mut frame = Frame::default();
+        if controls.reset {
+            chip8.reset();
+            self.paused = false;
+        }
+        if self.paused && controls.resume {
+            self.paused = false;
+        }
+        if let Some(server) = self.gdb.as_mut() {
+            frame.gdb_error = server.poll(chip8).err();
+        }
+        let gdb_running = self.gdb.as_ref().is_none_or(|server| server.is_running());
+        if !gdb_running || (self.paused && !controls.step) {
+            return frame;
+        }
+        // stepping runs a single instruction, otherwise this frame's share of the speed
+        let slot = self.frame % FRAMES_PER_TIMER_TICK;
+        let instructions = if self.paused {
+            1
+        } else {
+            self.speed * (slot + 1) / FRAMES_PER_TIMER_TICK - self.speed * slot / FRAMES_PER_TIMER_TICK
+        };
+        for _ in 0..instructions {
+            match (chip8.cycle(), self.gdb.as_mut()) {
+                (Some(reason), Some(server)) => {
+                    if let Err(e) = server.report_stop(&reason) {
+                        frame.gdb_error = Some(e);
+                    }
+                    break;
+                }
+                (Some(reason), None) => {
+                    frame.stop = Some(reason);
+                    self.paused = true;
+                    break;
+                }
+                (None, _) => {}
+            }
+        }
+        self.frame += 1;
+        if self.is_vblank() {
+            chip8.tick_timers();
+        }
+        frame.ran = true;
+        frame
+    }
+
+    // sleeps until the next frame is due. a frontend that falls behind carries on from
+    // now rather than rushing to catch up
+    pub fn wait(&mut self) {
+        self.next_frame += Duration::from_secs_f32(UPDATE_RATE);
+        let now = Instant::now();
+        match self.next_frame.checked_duration_since(now) {
+            Some(remaining) => thread::sleep(remaining),
+            None => self.next_frame = now,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cpu::quirks::Layout;
+
+    #[test]
+    fn timers_tick_at_60hz() {
+        let mut chip8 = Chip8::new();
+        // 1200: jump to itself
+        chip8.ram.write_byte(0x200, 0x12);
+        chip8.ram.write_byte(0x201, 0x00);
+        chip8.pc = 0x200;
+        chip8.set_delay_timer(10);
+        let mut scheduler = Scheduler::new(None);
+        for _ in 0..FRAMES_PER_TIMER_TICK * 3 {
+            scheduler.run_frame(&mut chip8, Controls::default());
+        }
+        assert_eq!(chip8.delay_timer(), 7);
+        assert!(scheduler.is_vblank());
+    }
+    #[test]
+    fn speed_is_instructions_per_vblank() {
+        let mut chip8 = Chip8::new();
+        chip8.pc = 0x200;
+        let mut scheduler = Scheduler::new(None);
+        scheduler.set_speed(10);
+        for _ in 0..FRAMES_PER_TIMER_TICK * 2 {
+            scheduler.run_frame(&mut chip8, Controls::default());
+        }
+        // 0000 does nothing, so pc only counts instructions
+        assert_eq!(chip8.pc, 0x200 + 2 * 20);
+    }
+    #[test]
+    fn breakpoints_pause_until_resumed() {
+        let mut chip8 = Chip8::new();
+        chip8.pc = 0x200;
+        chip8.debugger.add_breakpoint(0x202);
+        let mut scheduler = Scheduler::new(None);
+        assert_eq!(scheduler.run_frame(&mut chip8, Controls::default()).stop, None);
+        // the frame that stops says why, for the frontend to show
+        assert_eq!(scheduler.run_frame(&mut chip8, Controls::default()).stop, Some(BreakReason::Breakpoint(0x202)));
+        assert!(scheduler.is_paused());
+        assert_eq!(chip8.pc, 0x202);
+        // stays put without input, steps one at a time
+        assert!(!scheduler.run_frame(&mut chip8, Controls::default()).ran);
+        assert_eq!(chip8.pc, 0x202);
+        assert!(scheduler.run_frame(&mut chip8, Controls { step: true, ..Controls::default() }).ran);
+        assert_eq!(chip8.pc, 0x204);
+        scheduler.run_frame(&mut chip8, Controls { resume: true, ..Controls::default() });
+        assert!(!scheduler.is_paused());
+    }
+    #[test]
+    fn beeper_sounds_while_the_sound_timer_runs() {
+        let mut chip8 = Chip8::new();
+        // 1200: jump to itself
+        chip8.ram.write_byte(0x200, 0x12);
+        chip8.ram.write_byte(0x201, 0x00);
+        chip8.pc = 0x200;
+        chip8.set_sound_timer(2);
+        let mut scheduler = Scheduler::new(None);
+        scheduler.beeper = Some(Beeper::silent());
+        scheduler.run_frame(&mut chip8, Controls::default());
+        assert!(scheduler.beeper.as_ref().unwrap().is_playing());
+        for _ in 0..FRAMES_PER_TIMER_TICK * 2 {
+            scheduler.run_frame(&mut chip8, Controls::default());
+        }
+        assert_eq!(chip8.sound_timer(), 0);
+        assert!(!scheduler.beeper.as_ref().unwrap().is_playing());
+        // quiet while stopped in the debugger even with time left on the timer
+        chip8.set_sound_timer(10);
+        chip8.debugger.add_breakpoint(0x200);
+        scheduler.run_frame(&mut chip8, Controls::default());
+        scheduler.run_frame(&mut chip8, Controls::default());
+        assert!(scheduler.is_paused());
+        assert!(!scheduler.beeper.as_ref().unwrap().is_playing());
+    }
+    #[test]
+    fn reset_starts_over_at_the_entry_point() {
+        let mut chip8 = Chip8::new();
+        chip8.set_layout(Layout { load_address: 0x600, entry: 0x602, display: None }).unwrap();
+        // 6005: V0 = 5, then 7001: V0 += 1 forever
+        chip8.load_rom_bytes(&[0x60, 0x05, 0x70, 0x01, 0x16, 0x02]).unwrap();
+        assert_eq!(chip8.pc, 0x602);
+        chip8.debugger.add_breakpoint(0x604);
+        let mut scheduler = Scheduler::new(None);
+        scheduler.set_speed(8);
+        scheduler.run_frame(&mut chip8, Controls::default());
+        assert!(scheduler.is_paused());
+        assert_eq!(chip8.v_registers[0], 1);
+        // self-modifying code is undone too, and it runs to the breakpoint again
+        chip8.ram.write_byte(0x602, 0x00);
+        scheduler.run_frame(&mut chip8, Controls { reset: true, ..Controls::default() });
+        assert!(scheduler.is_paused());
+        assert_eq!((chip8.pc, chip8.v_registers[0]), (0x604, 1));
+        assert_eq!(chip8.ram.read_byte(0x602), 0x70);
+        assert_eq!(chip8.layout().load_address, 0x600);
+    }
+}
@@ -0,0 +1,126 @@
+// saving the screen as an image. PBM and PGM are one pixel per chip-8 pixel and
+// meant for golden files in tests, PNG is scaled and colored like the window
+use crate::display::DisplayConfig;
+use crate::Chip8;
+use std::fs;
+use std::path::Path;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Format {
+    Pbm,
+    Pgm,
+    Png,
+}
+impl Format {
+    // picked from the file extension
+    pub fn from_path(path: &Path) -> Result<Format, String> {
+        let extension = path.extension().and_then(|ext| ext.to_str()).unwrap_or_default();
+        match extension.to_ascii_lowercase().as_str() {
+            "pbm" => Ok(Format::Pbm),
+            "pgm" => Ok(Format::Pgm),
+            "png" => Ok(Format::Png),
+            _ => Err(format!("can't save `{}`, expected a .png, .pbm or .pgm file", path.display())),
+        }
+    }
+}
+
+// binary P4, rows packed 8 pixels to a byte with 1 for lit
+pub fn pbm<const W: usize>(screen: &[[bool; W]]) -> Vec<u8> {
+    let mut image = format!("P4\n{} {}\n", W, screen.len()).into_bytes();
+    for row in screen {
+        for byte in row.chunks(8) {
+            image.push(byte.iter().enumerate().fold(0, |acc, (i, &lit)| acc | (lit as u8) << (7 - i)));
+        }
+    }
+    image
+}
+
+// binary P5, lit pixels are white like they are on the classic palette
+pub fn pgm<const W: usize>(screen: &[[bool; W]]) -> Vec<u8> {
+    let mut image = format!("P5\n{} {}\n255\n", W, screen.len()).into_bytes();
+    image.extend(screen.iter().flatten().map(|&lit| if lit { 255 } else { 0 }));
+    image
+}
+
+// scaled with the display's colors and grid
+pub fn png<const W: usize>(screen: &[[bool; W]], display: &DisplayConfig) -> Result<Vec<u8>, String> {
+    let pixels = display.render(screen);
+    let rgb: Vec<u8> = pixels.iter().flat_map(|&color| [(color >> 16) as u8, (color >> 8) as u8, color as u8]).collect();
+    let mut image = Vec::new();
+    let mut encoder = png::Encoder::new(&mut image, (W * display.scale) as u32, (screen.len() * display.scale) as u32);
+    encoder.set_color(png::ColorType::Rgb);
+    encoder.set_depth(png::BitDepth::Eight);
+    let mut writer = encoder.write_header().map_err(|e| e.to_string())?;
+    writer.write_image_data(&rgb).map_err(|e| e.to_string())?;
+    writer.finish().map_err(|e| e.to_string())?;
+    Ok(image)
+}
+
+pub fn encode<const W: usize>(screen: &[[bool; W]], format: Format, display: &DisplayConfig) -> Result<Vec<u8>, String> {
+    match format {
+        Format::Pbm => Ok(pbm(screen)),
+        Format::Pgm => Ok(pgm(screen)),
+        Format::Png => png(screen, display),
+    }
+}
+
+// writes what's on screen right now, the format comes from the extension
+pub fn save(chip8: &Chip8, path: &Path, display: &DisplayConfig) -> Result<(), String> {
+    let image = encode(&chip8.get_screen(), Format::from_path(path)?, display)?;
+    fs::write(path, image).map_err(|e| format!("{}: {}", path.display(), e))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn checkerboard<const W: usize, const H: usize>() -> [[bool; W]; H] {
+        let mut screen = [[false; W]; H];
+        for (y, row) in screen.iter_mut().enumerate() {
+            for (x, pixel) in row.iter_mut().enumerate() {
+                *pixel = (x + y) % 2 == 0;
+            }
+        }
+        screen
+    }
+
+    #[test]
+    fn netpbm_at_native_resolution() {
+        let screen: [[bool; 64]; 32] = checkerboard();
+        let image = pbm(&screen);
+        let header = b"P4\n64 32\n";
+        assert_eq!(&image[..header.len()], header);
+        assert_eq!(image.len(), header.len() + 8 * 32);
+        assert_eq!(image[header.len()], 0xAA);
+        assert_eq!(image[header.len() + 8], 0x55);
+        // rows that don't fill the last byte are padded
+        let image = pbm(&[[true; 12]; 1]);
+        assert_eq!(&image[image.len() - 2..], &[0xFF, 0xF0]);
+        let image = pgm(&checkerboard::<128, 64>());
+        let header = b"P5\n128 64\n255\n";
+        assert_eq!(&image[..header.len()], header);
+        assert_eq!(&image[header.len()..header.len() + 2], &[255, 0]);
+        assert_eq!(image.len(), header.len() + 128 * 64);
+    }
+    #[test]
+    fn png_is_scaled_with_the_palette() {
+        let mut display = DisplayConfig { scale: 3, ..DisplayConfig::default() };
+        display.set_palette("amber").unwrap();
+        let image = png(&checkerboard::<64, 32>(), &display).unwrap();
+        let decoder = png::Decoder::new(image.as_slice());
+        let mut reader = decoder.read_info().unwrap();
+        let mut pixels = vec![0; reader.output_buffer_size()];
+        let info = reader.next_frame(&mut pixels).unwrap();
+        assert_eq!((info.width, info.height), (192, 96));
+        assert_eq!(&pixels[..3], &[0xFF, 0xB0, 0x00]);
+        // second cell across is background
+        assert_eq!(&pixels[9..12], &[0x1F, 0x12, 0x00]);
+    }
+    #[test]
+    fn format_from_extension() {
+        assert_eq!(Format::from_path(Path::new("shot.PNG")), Ok(Format::Png));
+        assert_eq!(Format::from_path(Path::new("golden/ibm.pbm")), Ok(Format::Pbm));
+        assert!(Format::from_path(Path::new("shot.bmp")).is_err());
+        assert!(Format::from_path(Path::new("shot")).is_err());
+    }
+}
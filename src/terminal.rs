@@ -0,0 +1,271 @@
+// frontend for a plain terminal, e.g. over ssh. the screen is drawn with ANSI escapes
+// using half blocks (1x2 pixels per character) or braille (2x4), and the keypad is
+// read from stdin in raw mode
+use crate::display::DisplayConfig;
+use crate::scheduler::{Controls, Scheduler};
+use crate::Chip8;
+use crossterm::event::{
+    self, Event, KeyCode, KeyEventKind, KeyModifiers, KeyboardEnhancementFlags, PopKeyboardEnhancementFlags,
+    PushKeyboardEnhancementFlags,
+};
+use crossterm::{cursor, execute, terminal};
+use std::io::{self, Write};
+use std::path::Path;
+use std::str::FromStr;
+use std::time::{Duration, Instant};
+
+// same layout as the window, the left of a qwerty keyboard
+pub const KEYPAD: [char; 16] = ['x', '1', '2', '3', 'q', 'w', 'e', 'a', 's', 'd', 'z', 'c', '4', 'r', 'f', 'v'];
+// most terminals only report presses and auto repeats, so without release events a
+// key counts as held for this long after it last came in
+const KEY_HOLD: Duration = Duration::from_millis(200);
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Glyphs {
+    HalfBlock,
+    Braille,
+}
+impl Glyphs {
+    // pixels per character, across and down
+    fn cell_size(&self) -> (usize, usize) {
+        match self {
+            Glyphs::HalfBlock => (1, 2),
+            Glyphs::Braille => (2, 4),
+        }
+    }
+    fn cell(&self, lit: impl Fn(usize, usize) -> bool) -> char {
+        match self {
+            Glyphs::HalfBlock => match (lit(0, 0), lit(0, 1)) {
+                (false, false) => ' ',
+                (true, false) => '▀',
+                (false, true) => '▄',
+                (true, true) => '█',
+            },
+            Glyphs::Braille => {
+                // dots 1-6 run down the left column then the right, 7 and 8 are the bottom row
+                const DOTS: [(usize, usize, u32); 8] =
+                    [(0, 0, 0x01), (0, 1, 0x02), (0, 2, 0x04), (1, 0, 0x08), (1, 1, 0x10), (1, 2, 0x20), (0, 3, 0x40), (1, 3, 0x80)];
+                let bits: u32 = DOTS.iter().filter(|&&(x, y, _)| lit(x, y)).map(|&(_, _, bit)| bit).sum();
+                char::from_u32(0x2800 + bits).unwrap_or(' ')
+            }
+        }
+    }
+}
+// `half` or `braille`
+impl FromStr for Glyphs {
+    type Err = String;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "half" => Ok(Glyphs::HalfBlock),
+            "braille" => Ok(Glyphs::Braille),
+            _ => Err(format!("unknown terminal glyphs `{}`, expected half or braille", s)),
+        }
+    }
+}
+
+// the screen as lines of text separated by \r\n, which raw mode needs to get back to
+// the first column. works for any size: half blocks take a column per pixel, so 64x32
+// fits an 80 column terminal but 128x64 needs braille's two pixels per column to
+pub fn render<const W: usize>(screen: &[[bool; W]], glyphs: Glyphs) -> String {
+    let (cell_width, cell_height) = glyphs.cell_size();
+    let pixel = |x: usize, y: usize| screen.get(y).and_then(|row| row.get(x)).copied().unwrap_or(false);
+    let mut text = String::new();
+    for top in (0..screen.len()).step_by(cell_height) {
+        if top > 0 {
+            text.push_str("\r\n");
+        }
+        for left in (0..W).step_by(cell_width) {
+            text.push(glyphs.cell(|dx, dy| pixel(left + dx, top + dy)));
+        }
+    }
+    text
+}
+
+fn color_escapes(display: &DisplayConfig) -> String {
+    let rgb = |color: u32| format!("{};{};{}", color >> 16 & 0xFF, color >> 8 & 0xFF, color & 0xFF);
+    format!("\x1b[38;2;{}m\x1b[48;2;{}m", rgb(display.foreground), rgb(display.background))
+}
+
+struct Keypad {
+    held_until: [Option<Instant>; 16],
+}
+impl Keypad {
+    fn press(&mut self, key: usize, now: Instant, releases: bool) {
+        // with release events the key stays down until it's let go
+        self.held_until[key] = Some(if releases { now + Duration::from_secs(3600) } else { now + KEY_HOLD });
+    }
+    fn release(&mut self, key: usize) {
+        self.held_until[key] = None;
+    }
+    fn is_down(&self, key: usize, now: Instant) -> bool {
+        self.held_until[key].is_some_and(|until| now < until)
+    }
+}
+
+fn keypad_key(keys: &[char; 16], c: char) -> Option<usize> {
+    keys.iter().position(|&k| k == c.to_ascii_lowercase())
+}
+
+// raw mode on an alternate screen for as long as this is alive, put back on drop so a
+// panic doesn't leave the terminal unusable
+struct RawTerminal {
+    // the terminal reports key releases, see KEY_HOLD
+    releases: bool,
+}
+impl RawTerminal {
+    fn enter() -> io::Result<Self> {
+        terminal::enable_raw_mode()?;
+        let mut stdout = io::stdout();
+        execute!(stdout, terminal::EnterAlternateScreen, cursor::Hide, terminal::Clear(terminal::ClearType::All))?;
+        let releases = terminal::supports_keyboard_enhancement().unwrap_or(false);
+        if releases {
+            execute!(stdout, PushKeyboardEnhancementFlags(KeyboardEnhancementFlags::REPORT_EVENT_TYPES))?;
+        }
+        Ok(RawTerminal { releases })
+    }
+}
+impl Drop for RawTerminal {
+    fn drop(&mut self) {
+        let mut stdout = io::stdout();
+        if self.releases {
+            let _ = execute!(stdout, PopKeyboardEnhancementFlags);
+        }
+        let _ = execute!(stdout, cursor::Show, terminal::LeaveAlternateScreen);
+        let _ = terminal::disable_raw_mode();
+    }
+}
+
+// runs until escape or ctrl-c. space steps, F5 continues after a break, F2 starts the
+// rom over and F6/F7 save to and load from `state`, like the window.
+// `keys` are the characters for chip-8 keys 0 to F, KEYPAD unless configured otherwise.
+// `arrows` are the chip-8 keys for the arrow keys, when the rom database knows them
+pub fn run(
+    chip8: &mut Chip8,
+    scheduler: &mut Scheduler,
+    display: &DisplayConfig,
+    glyphs: Glyphs,
+    keys: &[char; 16],
+    arrows: [Option<u8>; 4],
+    state: &Path,
+) -> io::Result<()> {
+    let raw = RawTerminal::enter()?;
+    let mut stdout = io::stdout();
+    let colors = color_escapes(display);
+    let mut keypad = Keypad { held_until: [None; 16] };
+    // screen has changes that haven't been drawn yet
+    let mut stale = true;
+    // shown under the screen, raw mode and the redraws would mangle anything printed
+    let mut status = String::new();
+    loop {
+        let now = Instant::now();
+        let mut controls = Controls::default();
+        while event::poll(Duration::ZERO)? {
+            let Event::Key(key) = event::read()? else { continue };
+            let released = key.kind == KeyEventKind::Release;
+            match key.code {
+                KeyCode::Esc => return Ok(()),
+                KeyCode::Char('c') if key.modifiers.contains(KeyModifiers::CONTROL) => return Ok(()),
+                KeyCode::F(2) if !released => controls.reset = true,
+                KeyCode::F(5) if !released => controls.resume = true,
+                KeyCode::F(6) if !released => {
+                    status = match chip8.save_state_to(state) {
+                        Ok(()) => format!("saved {}", state.display()),
+                        Err(e) => format!("saving failed: {}", e),
+                    };
+                    stale = true;
+                }
+                KeyCode::F(7) if !released => {
+                    status = match chip8.load_state_from(state) {
+                        Ok(()) => format!("loaded {}", state.display()),
+                        Err(e) => format!("loading failed: {}", e),
+                    };
+                    stale = true;
+                }
+                KeyCode::Char(' ') if !released => controls.step = true,
+                KeyCode::Up | KeyCode::Down | KeyCode::Left | KeyCode::Right => {
+                    let arrow = [KeyCode::Up, KeyCode::Down, KeyCode::Left, KeyCode::Right].iter().position(|&code| code == key.code);
+                    match (arrow.and_then(|arrow| arrows[arrow]), released) {
+                        (Some(k), false) => keypad.press(k as usize, now, raw.releases),
+                        (Some(k), true) => keypad.release(k as usize),
+                        (None, _) => {}
+                    }
+                }
+                KeyCode::Char(c) => match (keypad_key(keys, c), released) {
+                    (Some(k), false) => keypad.press(k, now, raw.releases),
+                    (Some(k), true) => keypad.release(k),
+                    (None, _) => {}
+                },
+                _ => {}
+            }
+        }
+        for key in 0..16 {
+            chip8.set_key(key as u8, keypad.is_down(key, now));
+        }
+        let frame = scheduler.run_frame(chip8, controls);
+        if let Some(e) = frame.gdb_error {
+            status = format!("gdb stub error: {}", e);
+            stale = true;
+        }
+        if let Some(reason) = frame.stop {
+            let state = chip8.machine_state();
+            status = format!("break: {}, pc {:03X} i {:03X} v {:02X?}", reason, state.pc, state.i_reg, state.v_registers);
+            stale = true;
+        }
+        stale |= !chip8.take_dirty_rects().is_empty();
+        // writing the whole screen is slow over ssh, so only at the 60Hz vblank
+        if stale && (scheduler.is_vblank() || scheduler.is_paused()) {
+            write!(stdout, "\x1b[H{}{}\x1b[0m\r\n\x1b[K{}", colors, render(&chip8.get_screen(), glyphs), status)?;
+            stdout.flush()?;
+            stale = false;
+        }
+        scheduler.wait();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn half_blocks_pair_rows() {
+        let mut screen = [[false; 3]; 4];
+        screen[0] = [true, false, true];
+        screen[1] = [true, true, false];
+        screen[3][2] = true;
+        assert_eq!(render(&screen, Glyphs::HalfBlock), "█▄▀\r\n  ▄");
+    }
+    #[test]
+    fn braille_packs_two_by_four() {
+        let mut screen = [[false; 4]; 4];
+        // left column and the bottom right dot of the first cell
+        for row in screen.iter_mut() {
+            row[0] = true;
+        }
+        screen[3][1] = true;
+        screen[0][3] = true;
+        assert_eq!(render(&screen, Glyphs::Braille), "\u{28C7}\u{2808}");
+    }
+    #[test]
+    fn screen_sizes_fit() {
+        let lines = |text: String| text.split("\r\n").map(|line| line.chars().count()).collect::<Vec<_>>();
+        assert_eq!(lines(render(&[[false; 64]; 32], Glyphs::HalfBlock)), vec![64; 16]);
+        assert_eq!(lines(render(&[[false; 64]; 32], Glyphs::Braille)), vec![32; 8]);
+        assert_eq!(lines(render(&[[false; 128]; 64], Glyphs::Braille)), vec![64; 16]);
+        assert_eq!(lines(render(&[[true; 128]; 64], Glyphs::HalfBlock)), vec![128; 32]);
+    }
+    #[test]
+    fn keys_map_like_the_window() {
+        assert_eq!(keypad_key(&KEYPAD, 'x'), Some(0x0));
+        assert_eq!(keypad_key(&KEYPAD, 'V'), Some(0xF));
+        assert_eq!(keypad_key(&KEYPAD, '4'), Some(0xC));
+        assert_eq!(keypad_key(&KEYPAD, 'p'), None);
+        let mut keypad = Keypad { held_until: [None; 16] };
+        let now = Instant::now();
+        keypad.press(5, now, false);
+        assert!(keypad.is_down(5, now));
+        assert!(!keypad.is_down(5, now + KEY_HOLD));
+        keypad.press(5, now, true);
+        keypad.release(5);
+        assert!(!keypad.is_down(5, now));
+    }
+}